@@ -0,0 +1,206 @@
+//! # Config Module
+//!
+//! The `config` module provides the `config` subcommands of the CLI binary, which inspect the
+//! configuration the PAM module uses.
+//!
+//! - `check`: Validates the configuration file and its drop-in fragments. Problems are reported
+//!   with their file and line, and the command exits with a non-zero status.
+//! - `show`: Prints the effective configuration, including the defaults, the drop-in fragments
+//!   and, for a given user, the `[Service.<name>]`, `[Group.<name>]` and `[User.<name>]` overrides.
+//!
+//! ## License
+//!
+//! pam-authramp
+//! Copyright (C) 2023 github.com/34N0
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fmt::Write;
+use std::path::Path;
+
+use colored::Colorize;
+use common::config::{Config, DEFAULT_CONFIG_FILE_PATH};
+use common::validate::{check_config, ConfigIssue};
+
+use crate::cmd::config_path;
+use crate::{ArCliError, ArCliOutput, ArCliResult as Acr, ArCliSuccess};
+
+/// Validates the configuration file and its drop-in fragments.
+///
+/// # Arguments
+///
+/// - `file`: The configuration file to check. If not given, the file in `AUTHRAMP_CONFIG` or the
+///   default configuration file is checked.
+///
+/// # Returns
+///
+/// A `Result` representing the outcome of the operation.
+///
+/// - If the configuration is valid, returns `ArCliResult::Success` with a success message.
+/// - If problems are found, returns `ArCliResult::Error` listing each problem with its file and line.
+pub fn check(file: Option<&str>) -> Acr {
+    // a file given explicitly has to exist, unlike the default configuration file
+    if let Some(file) = file.filter(|file| !Path::new(file).exists()) {
+        return Acr::Error(ArCliError {
+            message: format!("configuration file not found: '{}'", file.yellow()),
+        });
+    }
+
+    let path = file.map(ToString::to_string).or_else(config_path);
+    let issues = check_config(path.as_deref());
+
+    check_result(path.as_deref().unwrap_or(DEFAULT_CONFIG_FILE_PATH), &issues)
+}
+
+/// Prints the effective configuration as configuration file.
+///
+/// # Arguments
+///
+/// - `file`: The configuration file to load. If not given, the file in `AUTHRAMP_CONFIG` or the
+///   default configuration file is loaded.
+/// - `user`: The user whose overrides are applied.
+/// - `service`: The PAM service whose overrides are applied together with the overrides of `user`.
+///
+/// # Returns
+///
+/// A `Result` representing the outcome of the operation.
+///
+/// - If successful, returns `ArCliResult::Output` with the configuration.
+/// - If the user does not exist, returns `ArCliResult::Error` with an `ArCliError` containing the error message.
+pub fn show(file: Option<&str>, user: Option<&str>, service: Option<&str>) -> Acr {
+    let path = file.map(ToString::to_string).or_else(config_path);
+    let mut config = Config::load_file(path.as_deref(), None);
+
+    if let Some(name) = user {
+        let Some(user) = uzers::get_user_by_name(name) else {
+            return Acr::Error(ArCliError {
+                message: format!("user not found: '{}'", name.yellow()),
+            });
+        };
+        config.apply_overrides(&user, service);
+        // the overrides are resolved, only the settings of the user remain
+        config.services.clear();
+        config.users.clear();
+        config.groups.clear();
+    }
+
+    show_result(&config)
+}
+
+/// Formats a configuration as configuration file.
+///
+/// # Arguments
+///
+/// - `config`: The configuration.
+///
+/// # Returns
+///
+/// `ArCliResult::Output` with the TOML document, `ArCliResult::Error` if it cannot be serialized.
+fn show_result(config: &Config) -> Acr {
+    let table = config.to_toml();
+    match (toml::to_string(&table), serde_json::to_value(&table)) {
+        (Ok(text), Ok(data)) => Acr::Output(ArCliOutput {
+            text,
+            users: Vec::new(),
+            data,
+        }),
+        (Err(e), _) => Acr::Error(ArCliError {
+            message: format!("{e}"),
+        }),
+        (_, Err(e)) => Acr::Error(ArCliError {
+            message: format!("{e}"),
+        }),
+    }
+}
+
+/// Formats the problems found in a configuration.
+///
+/// # Arguments
+///
+/// - `path`: The checked configuration file.
+/// - `issues`: The problems found in the configuration.
+///
+/// # Returns
+///
+/// `ArCliResult::Success` if there are no problems, `ArCliResult::Error` otherwise.
+fn check_result(path: &str, issues: &[ConfigIssue]) -> Acr {
+    if issues.is_empty() {
+        return Acr::Success(Some(ArCliSuccess {
+            message: format!("configuration is valid: '{}'", path.yellow()),
+            users: Vec::new(),
+        }));
+    }
+
+    let mut message = format!("{} problem(s) found in the configuration:", issues.len());
+    for issue in issues {
+        let _ = write!(message, "\n  {issue}");
+    }
+    Acr::Error(ArCliError { message })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_check_result() {
+        assert!(matches!(
+            check_result("authramp.conf", &[]),
+            Acr::Success(Some(_))
+        ));
+
+        let issues = [ConfigIssue {
+            path: PathBuf::from("authramp.conf"),
+            line: Some(3),
+            message: "unknown key free_trys in [Configuration]".to_string(),
+        }];
+        match check_result("authramp.conf", &issues) {
+            Acr::Error(error) => assert!(error
+                .message
+                .ends_with("\n  authramp.conf:3: unknown key free_trys in [Configuration]")),
+            result => panic!("unexpected result: {result:?}"),
+        }
+    }
+
+    #[test]
+    fn test_check_missing_file() {
+        assert!(matches!(
+            check(Some("/nonexistent/authramp.conf")),
+            Acr::Error(_)
+        ));
+    }
+
+    #[test]
+    fn test_show_result() {
+        let temp_dir = TempDir::new("test_show_result").unwrap();
+        let conf_file_path = temp_dir.path().join("authramp.conf");
+        std::fs::write(
+            &conf_file_path,
+            "[Configuration]\nfree_tries = 3\n\n[User.alice]\nbase_delay_seconds = 5\n",
+        )
+        .unwrap();
+        let config = Config::load_file(conf_file_path.to_str(), None);
+
+        // the output loads as the same configuration
+        let Acr::Output(ArCliOutput { text: toml, .. }) = show_result(&config) else {
+            panic!("no output");
+        };
+        assert!(toml.contains("free_tries = 3"));
+        assert!(toml.contains("[User.alice]"));
+        std::fs::write(&conf_file_path, toml).unwrap();
+        assert_eq!(Config::load_file(conf_file_path.to_str(), None), config);
+    }
+}
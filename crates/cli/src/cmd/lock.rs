@@ -0,0 +1,277 @@
+//! # Lock Module
+//!
+//! The `lock` module provides the `lock` subcommand of the CLI binary, which locks an account
+//! proactively, e.g. after its credentials were compromised. The lock is stored in the `[Lock]`
+//! table of the tallies of the user. The PAM module bounces the account until the lock expires,
+//! a successful login does not clear it. `authramp reset` removes it.
+//!
+//! ## License
+//!
+//! pam-authramp
+//! Copyright (C) 2023 github.com/34N0
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::BTreeSet;
+
+use chrono::{DateTime, Utc};
+use colored::Colorize;
+use common::audit::{AuditEvent, AuditEventKind};
+use common::config::Config;
+use common::schema::{AdminLock, FailsTable, TallyFile};
+use common::store::{self, StoreError, TallyStore};
+use common::track::{TallyKey, Track};
+
+use crate::cmd::load_config;
+use crate::{ArCliError, ArCliResult as Acr, ArCliSuccess};
+
+/// Locks the account of a user.
+///
+/// The function reads the configuration, opens the configured tally store and stores the lock in
+/// the tallies of the user in every `tally_namespace` of the configuration.
+///
+/// # Arguments
+///
+/// - `user`: The username of the account to lock.
+/// - `until`: The time the account is unlocked. The account stays locked until it is reset if not given.
+/// - `reason`: Why the account is locked, it is logged when an attempt is bounced.
+///
+/// # Returns
+///
+/// A `Result` representing the outcome of the operation.
+///
+/// - If successful, returns `ArCliResult::Success` with an optional `ArCliSuccess` containing a success message.
+/// - If the account cannot be locked, returns `ArCliResult::Error` with an `ArCliError` containing the error message.
+pub fn user(user: &str, until: Option<DateTime<Utc>>, reason: Option<String>) -> Acr {
+    let config = load_config();
+    let now = Utc::now();
+
+    let error = |message: String| Acr::Error(ArCliError { message });
+
+    if uzers::get_user_by_name(user).is_none() {
+        return error(format!("user not found: '{}'", user.yellow()));
+    }
+    if !config.track.contains(&Track::User) {
+        return error("accounts are not tracked, add \"user\" to the track setting".to_string());
+    }
+    if until.is_some_and(|until| until <= now) {
+        return error("the lock would already be expired".to_string());
+    }
+
+    let admin_lock = AdminLock {
+        instant: now,
+        until,
+        reason,
+    };
+
+    let names: Vec<_> = tally_namespaces(&config)
+        .iter()
+        .map(|namespace| TallyKey::User(user.to_string()).store_name(namespace.as_deref()))
+        .collect();
+    let (names, locked) = match store::open(&config) {
+        Ok(store) => lock_tallies(store.as_ref(), &names, &admin_lock),
+        Err(e) => (Vec::new(), Err(e)),
+    };
+
+    for name in &names {
+        let event = AuditEvent {
+            reason: admin_lock.reason.clone(),
+            ..AuditEvent::from_tally(AuditEventKind::Lock, name)
+        }
+        .locked_until(Some(admin_lock.locked_until()));
+        if let Err(e) = event.log(&config) {
+            return error(format!(
+                "user '{}' locked, but logging the audit event failed: {e}",
+                user.yellow()
+            ));
+        }
+    }
+
+    match locked {
+        Ok(()) => Acr::Success(Some(ArCliSuccess {
+            message: match until {
+                Some(until) => format!(
+                    "user '{}' locked until {}",
+                    user.yellow(),
+                    until.format("%Y-%m-%d %H:%M:%S UTC")
+                ),
+                None => format!("user '{}' locked until it is reset", user.yellow()),
+            },
+            users: vec![user.to_string()],
+        })),
+        Err(e) if names.is_empty() => error(format!("{e}")),
+        Err(e) => error(format!(
+            "user '{}' is only locked in the tallies {}, locking the others failed: {e}",
+            user.yellow(),
+            names.join(", ")
+        )),
+    }
+}
+
+/// Collects the namespaces the tallies of a user can be stored in: the `tally_namespace` of the
+/// configuration and of every override section.
+///
+/// # Arguments
+///
+/// - `config`: The configuration the PAM module uses.
+fn tally_namespaces(config: &Config) -> BTreeSet<Option<String>> {
+    let mut namespaces = BTreeSet::from([config.tally_namespace.clone()]);
+    for overrides in config
+        .services
+        .values()
+        .chain(config.users.values())
+        .chain(config.groups.values())
+    {
+        if let Some(namespace) = &overrides.tally_namespace {
+            namespaces.insert(Some(namespace.clone()));
+        }
+    }
+    namespaces
+}
+
+/// Stores a lock in tallies, stopping at the first tally that cannot be locked.
+///
+/// # Arguments
+///
+/// - `store`: The tally store.
+/// - `names`: Names of the tallies in the tally store.
+/// - `admin_lock`: The lock.
+///
+/// # Returns
+///
+/// The names of the locked tallies, and the `StoreError` of the tally that cannot be locked.
+fn lock_tallies(
+    store: &dyn TallyStore,
+    names: &[String],
+    admin_lock: &AdminLock,
+) -> (Vec<String>, Result<(), StoreError>) {
+    let mut locked = Vec::new();
+    for name in names {
+        if let Err(e) = lock_tally(store, name, admin_lock) {
+            return (locked, Err(e));
+        }
+        locked.push(name.clone());
+    }
+    (locked, Ok(()))
+}
+
+/// Stores a lock in a tally, keeping the failures of the tally.
+///
+/// # Arguments
+///
+/// - `store`: The tally store.
+/// - `name`: Name of the tally in the tally store.
+/// - `admin_lock`: The lock.
+///
+/// # Errors
+///
+/// Returns a `StoreError` if the tally cannot be read or written.
+fn lock_tally(
+    store: &dyn TallyStore,
+    name: &str,
+    admin_lock: &AdminLock,
+) -> Result<(), StoreError> {
+    store.update(name, &mut |tally_file| TallyFile {
+        lock: Some(admin_lock.clone()),
+        ..tally_file.unwrap_or_else(|| TallyFile::new(FailsTable::default(), Vec::new()))
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::temp_tally_dir;
+    use common::overrides::Overrides;
+    use common::store::FileStore;
+
+    #[test]
+    fn test_lock_tally() {
+        let temp_dir = temp_tally_dir("test_lock_tally");
+        let store = FileStore::new(temp_dir.path());
+        let admin_lock = AdminLock {
+            instant: Utc::now(),
+            until: None,
+            reason: Some("leaver".to_string()),
+        };
+
+        let fails = FailsTable {
+            count: 3,
+            ..FailsTable::default()
+        };
+        store
+            .save("alice", &TallyFile::new(fails.clone(), Vec::new()))
+            .unwrap();
+
+        lock_tally(&store, "alice", &admin_lock).unwrap();
+        lock_tally(&store, "bob", &admin_lock).unwrap();
+
+        // the failures are kept
+        let tally_file = store.load("alice").unwrap().unwrap();
+        assert_eq!(tally_file.fails, fails);
+        assert_eq!(tally_file.lock.as_ref(), Some(&admin_lock));
+
+        let tally_file = store.load("bob").unwrap().unwrap();
+        assert_eq!(tally_file.fails, FailsTable::default());
+        assert_eq!(tally_file.lock, Some(admin_lock));
+    }
+
+    #[test]
+    fn test_lock_tallies() {
+        let temp_dir = temp_tally_dir("test_lock_tallies");
+        let store = FileStore::new(temp_dir.path());
+        let admin_lock = AdminLock {
+            instant: Utc::now(),
+            until: None,
+            reason: None,
+        };
+
+        // the name is too long for a tally file
+        let names = [
+            "alice".to_string(),
+            "a".repeat(300),
+            "remote:alice".to_string(),
+        ];
+        let (locked, result) = lock_tallies(&store, &names, &admin_lock);
+        assert_eq!(locked, vec!["alice".to_string()]);
+        assert!(result.is_err());
+        assert!(store.load("alice").unwrap().unwrap().lock.is_some());
+        assert!(store.load("remote:alice").unwrap().is_none());
+
+        let (locked, result) = lock_tallies(&store, &names[..1], &admin_lock);
+        assert_eq!(locked, vec!["alice".to_string()]);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_tally_namespaces() {
+        let mut config = Config::default();
+        assert_eq!(tally_namespaces(&config), BTreeSet::from([None]));
+
+        config.services.insert(
+            "sshd".to_string(),
+            Overrides {
+                tally_namespace: Some("remote".to_string()),
+                ..Overrides::default()
+            },
+        );
+        config
+            .users
+            .insert("alice".to_string(), Overrides::default());
+        assert_eq!(
+            tally_namespaces(&config),
+            BTreeSet::from([None, Some("remote".to_string())])
+        );
+    }
+}
@@ -1 +1,239 @@
+pub mod config;
+pub mod lock;
 pub mod reset;
+pub mod simulate;
+pub mod status;
+
+use std::collections::BTreeSet;
+
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use common::config::Config;
+use common::track::TallyKey;
+
+/// Environment variable overriding the path of the configuration file.
+const CONFIG_ENV: &str = "AUTHRAMP_CONFIG";
+
+/// Returns the configuration file given in `AUTHRAMP_CONFIG`.
+///
+/// # Returns
+///
+/// The path, or `None` if the default configuration file is used.
+pub fn config_path() -> Option<String> {
+    std::env::var(CONFIG_ENV)
+        .ok()
+        .filter(|path| !path.is_empty())
+}
+
+/// Loads the configuration from the file given in `AUTHRAMP_CONFIG`, or from the default
+/// configuration file if it is not set.
+///
+/// # Returns
+///
+/// The `Config` the PAM module uses with the same configuration file.
+pub fn load_config() -> Config {
+    Config::load_file(config_path().as_deref(), None)
+}
+
+/// Parses a duration like `90`, `30m`, `12h`, `7d` or `2w`. A number without unit is in seconds.
+///
+/// # Arguments
+///
+/// * `duration`: The duration given on the command line
+///
+/// # Errors
+///
+/// Returns a message describing the problem if the duration cannot be parsed.
+pub fn parse_duration(duration: &str) -> Result<chrono::Duration, String> {
+    let (value, unit) = duration.split_at(
+        duration
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(duration.len()),
+    );
+    let value: i64 = value
+        .parse()
+        .map_err(|_| format!("invalid duration '{duration}'"))?;
+
+    let seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "invalid duration unit in '{duration}', use s, m, h, d or w"
+            ))
+        }
+    };
+
+    value
+        .checked_mul(seconds)
+        .and_then(chrono::Duration::try_seconds)
+        .ok_or_else(|| format!("duration '{duration}' is too long"))
+}
+
+/// Parses a point in time like `2024-02-04T12:00:00Z`, `2024-02-04 12:00`, `2024-02-04 12:00:00`
+/// or `2024-02-04`. Times without offset are in the local time zone, dates are at midnight.
+///
+/// # Arguments
+///
+/// * `time`: The time given on the command line
+///
+/// # Errors
+///
+/// Returns a message describing the problem if the time cannot be parsed.
+pub fn parse_time(time: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(time) {
+        return Ok(time.with_timezone(&Utc));
+    }
+
+    let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(time, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(time, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+        .ok_or_else(|| format!("invalid time '{time}', use e.g. '2024-02-04 12:00'"))?;
+
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|time| time.with_timezone(&Utc))
+        .ok_or_else(|| format!("time '{time}' does not exist in the local time zone"))
+}
+
+/// Collects the users of tallies, without duplicates and remote host tallies.
+///
+/// # Arguments
+///
+/// * `names`: Names of the tallies in the tally store
+pub fn tally_users<'a>(names: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    names
+        .into_iter()
+        .filter_map(|name| {
+            TallyKey::from_store_name(name)
+                .1
+                .user()
+                .map(ToString::to_string)
+        })
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// Formats a duration as days, hours, minutes and seconds, excluding leading zero values.
+///
+/// # Arguments
+///
+/// * `duration`: The duration
+pub fn format_duration(duration: chrono::Duration) -> String {
+    let (days, hours, minutes, seconds) = (
+        duration.num_days(),
+        duration.num_hours() % 24,
+        duration.num_minutes() % 60,
+        duration.num_seconds() % 60,
+    );
+    if days > 0 {
+        format!("{days}d {hours}h {minutes}m {seconds}s")
+    } else if hours > 0 {
+        format!("{hours}h {minutes}m {seconds}s")
+    } else if minutes > 0 {
+        format!("{minutes}m {seconds}s")
+    } else {
+        format!("{seconds}s")
+    }
+}
+
+/// Formats rows as table with left-aligned columns.
+///
+/// # Arguments
+///
+/// * `header`: The column names
+/// * `rows`: The rows
+pub fn format_columns<const N: usize>(header: &[&str; N], rows: &[[String; N]]) -> String {
+    let mut widths = header.map(str::len);
+    for row in rows {
+        for (width, column) in widths.iter_mut().zip(row) {
+            *width = (*width).max(column.chars().count());
+        }
+    }
+
+    let format_row = |row: &mut dyn Iterator<Item = &str>| {
+        row.zip(widths)
+            .map(|(column, width)| format!("{column:<width$}"))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    std::iter::once(format_row(&mut header.iter().copied()))
+        .chain(
+            rows.iter()
+                .map(|row| format_row(&mut row.iter().map(String::as_str))),
+        )
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Creates a temporary tally directory, trusted without running as root.
+#[cfg(test)]
+pub(crate) fn temp_tally_dir(prefix: &str) -> tempdir::TempDir {
+    common::store::set_tally_dir_owner(uzers::get_effective_uid());
+    tempdir::TempDir::new(prefix).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Ok(chrono::Duration::seconds(90)));
+        assert_eq!(parse_duration("30m"), Ok(chrono::Duration::minutes(30)));
+        assert_eq!(parse_duration("7d"), Ok(chrono::Duration::days(7)));
+        assert_eq!(parse_duration("2w"), Ok(chrono::Duration::weeks(2)));
+        assert!(parse_duration("7y").is_err());
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("-1d").is_err());
+    }
+
+    #[test]
+    fn test_tally_users() {
+        assert_eq!(
+            tally_users(["bob", "sshd:alice", "alice@10.0.0.1", "@10.0.0.1"]),
+            vec!["alice".to_string(), "bob".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(
+            parse_time("2024-02-04T12:00:00+01:00"),
+            Ok(DateTime::parse_from_rfc3339("2024-02-04T11:00:00Z")
+                .unwrap()
+                .with_timezone(&Utc))
+        );
+        let local = parse_time("2024-02-04 12:00")
+            .unwrap()
+            .with_timezone(&Local);
+        assert_eq!(local.naive_local().to_string(), "2024-02-04 12:00:00");
+        assert_eq!(
+            parse_time("2024-02-04").unwrap(),
+            parse_time("2024-02-04 00:00:00").unwrap()
+        );
+        assert!(parse_time("tomorrow").is_err());
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(chrono::Duration::seconds(5)), "5s");
+        assert_eq!(format_duration(chrono::Duration::seconds(90)), "1m 30s");
+        assert_eq!(
+            format_duration(chrono::Duration::seconds(2 * 86400 + 3600)),
+            "2d 1h 0m 0s"
+        );
+    }
+}
@@ -2,7 +2,9 @@
 //!
 //! The `reset` module provides functionality to reset the tally information for a user.
 //! It is used in the context of the `sm_authenticate` PAM hook when the `reset` command is specified.
-//! The tally information is stored in a file, and this module allows resetting the tally for a specific user.
+//! The tally information is kept in the configured tally store, and this module allows resetting the tally for a specific user.
+//! After an incident, tallies can be reset in bulk: all of them, or only those matching a user, a group, a lock state
+//! or an age. A dry run lists the tallies without resetting them.
 //!
 //! ## License
 //!
@@ -22,91 +24,379 @@
 //! You should have received a copy of the GNU General Public License
 //! along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use chrono::{DateTime, Duration, Utc};
 use colored::Colorize;
+use common::audit::{AuditEvent, AuditEventKind};
 use common::config::Config;
-use std::{fs, path::PathBuf};
+use common::store::{self, TallyStore};
 
+use crate::cmd::status::{tally_statuses, TallyStatus};
+use crate::cmd::{load_config, tally_users};
 use crate::{ArCliError, ArCliInfo, ArCliResult as Acr, ArCliSuccess};
 
-/// Resets the tally information for a specific user.
+/// The `ResetFilter` struct selects the tallies to reset. Tallies have to match all given criteria.
+#[derive(Debug, Default)]
+pub struct ResetFilter {
+    /// Only reset the tallies of this user, in any `tally_namespace` and from any remote host.
+    pub user: Option<String>,
+    /// Only reset the tallies of members of this group.
+    pub group: Option<String>,
+    /// Only reset tallies that currently lock the account.
+    pub locked_only: bool,
+    /// Only reset tallies whose last failure is older than this.
+    pub older_than: Option<Duration>,
+}
+
+/// Resets the tally information matching a filter.
 ///
-/// The function reads the configuration, constructs the path to the tally file for the given user,
-/// and attempts to delete the tally file. It returns a result indicating the success or failure of the operation.
+/// The function reads the configuration, opens the configured tally store and attempts to remove
+/// the matching tallies. It returns a result indicating the success or failure of the operation.
 ///
 /// # Arguments
 ///
-/// - `user`: The username for which the tally information should be reset.
+/// - `filter`: The `ResetFilter` selecting the tallies.
+/// - `dry_run`: Only list the matching tallies without resetting them.
 ///
 /// # Returns
 ///
 /// A `Result` representing the outcome of the operation.
 ///
-/// - If successful, returns `ArCliResult::Success` with an optional `ArCliSuccess` containing a success message.
-/// - If the tally file does not exist, returns `ArCliResult::Info` with an `ArCliInfo` containing an informational message.
-/// - If an error occurs during the file deletion, returns `ArCliResult::Error` with an `ArCliError` containing the error message.
-pub fn user(user: &str) -> Acr {
-    let config = Config::load_file(None, None);
+/// - If successful, returns `ArCliResult::Success` with an optional `ArCliSuccess` containing a summary of the reset tallies.
+/// - If no tally matches, or on a dry run, returns `ArCliResult::Info` with an `ArCliInfo` containing an informational message.
+/// - If an error occurs while removing a tally, returns `ArCliResult::Error` with an `ArCliError` containing the error message.
+pub fn tallies(filter: &ResetFilter, dry_run: bool) -> Acr {
+    let config = load_config();
 
-    let tally_path = config.tally_dir.join(user);
+    if let Some(group) = filter
+        .group
+        .as_deref()
+        .filter(|group| uzers::get_group_by_name(group).is_none())
+    {
+        return Acr::Error(ArCliError {
+            message: format!("group not found: '{}'", group.yellow()),
+        });
+    }
 
-    delete_tally(&tally_path, user)
+    match store::open(&config) {
+        Ok(store) => delete_tallies(store.as_ref(), &config, filter, dry_run, Utc::now()),
+        Err(e) => Acr::Error(ArCliError {
+            message: format!("{e}"),
+        }),
+    }
 }
 
-/// Deletes the tally file for a specific user.
+/// Deletes the tallies matching a filter.
 ///
-/// The function attempts to remove the tally file specified by the provided path.
-/// It returns a result indicating the success or failure of the operation.
+/// The function attempts to remove the matching tallies from the provided tally store and
+/// summarizes which tallies were reset. It returns a result indicating the success or failure of the operation.
 ///
 /// # Arguments
 ///
-/// - `path`: The path to the tally file.
-/// - `user`: The username associated with the tally file.
+/// - `store`: The tally store.
+/// - `config`: The configuration the PAM module uses.
+/// - `filter`: The `ResetFilter` selecting the tallies.
+/// - `dry_run`: Only list the matching tallies without resetting them.
+/// - `now`: The current time.
 ///
 /// # Returns
 ///
 /// A `Result` representing the outcome of the operation.
 ///
-/// - If successful, returns `ArCliResult::Success` with an optional `ArCliSuccess` containing a success message.
-/// - If the tally file does not exist, returns `ArCliResult::Info` with an `ArCliInfo` containing an informational message.
-/// - If an error occurs during the file deletion, returns `ArCliResult::Error` with an `ArCliError` containing the error message.
-fn delete_tally(path: &PathBuf, user: &str) -> Acr {
-    match fs::remove_file(path) {
-        Ok(()) => Acr::Success(Some(ArCliSuccess {
-            message: format!("tally reset for user: '{}'", user.yellow()),
-        })),
+/// - If successful, returns `ArCliResult::Success` with an optional `ArCliSuccess` containing a summary of the reset tallies.
+/// - If no tally matches, or on a dry run, returns `ArCliResult::Info` with an `ArCliInfo` containing an informational message.
+/// - If an error occurs while removing a tally, returns `ArCliResult::Error` with an `ArCliError` containing the error message.
+fn delete_tallies(
+    store: &dyn TallyStore,
+    config: &Config,
+    filter: &ResetFilter,
+    dry_run: bool,
+    now: DateTime<Utc>,
+) -> Acr {
+    let statuses = match tally_statuses(store, config, filter.user.as_deref(), now) {
+        Ok(statuses) => statuses,
         Err(e) => {
-            if e.kind().eq(&std::io::ErrorKind::NotFound) {
-                Acr::Info(ArCliInfo {
-                    message: format!("No tally found for user: '{}'", user.yellow()),
-                })
-            } else {
-                Acr::Error(ArCliError {
-                    message: format!("{e}").to_string(),
-                })
+            return Acr::Error(ArCliError {
+                message: format!("{e}"),
+            })
+        }
+    };
+
+    let statuses: Vec<TallyStatus> = statuses
+        .into_iter()
+        .filter(|status| is_selected(status, filter, now))
+        .collect();
+    let names: Vec<String> = statuses.iter().map(|status| status.tally.clone()).collect();
+
+    if names.is_empty() {
+        return Acr::Info(ArCliInfo {
+            message: match &filter.user {
+                Some(user) => format!("No tally found for user: '{}'", user.yellow()),
+                None => "No matching tallies found".to_string(),
+            },
+            users: Vec::new(),
+        });
+    }
+
+    if dry_run {
+        return Acr::Info(ArCliInfo {
+            message: format!(
+                "dry run, {} tally(s) would be reset: {}",
+                names.len(),
+                format_names(&names)
+            ),
+            users: tally_users(names.iter().map(String::as_str)),
+        });
+    }
+
+    let mut cleared = Vec::new();
+    let mut errors = Vec::new();
+    for status in statuses {
+        match store.clear(&status.tally) {
+            Ok(true) => {
+                let event = AuditEvent {
+                    count: Some(status.failures),
+                    ..AuditEvent::from_tally(AuditEventKind::Reset, &status.tally)
+                };
+                if let Err(e) = event.log(config) {
+                    errors.push(format!(
+                        "'{}': error logging audit event: {e}",
+                        status.tally.yellow()
+                    ));
+                }
+                cleared.push(status.tally);
             }
+            // removed concurrently, e.g. by a successful login
+            Ok(false) => (),
+            Err(e) => errors.push(format!("'{}': {e}", status.tally.yellow())),
         }
     }
+
+    let summary = format!(
+        "{} tally(s) reset: {}",
+        cleared.len(),
+        format_names(&cleared)
+    );
+    if errors.is_empty() {
+        Acr::Success(Some(ArCliSuccess {
+            message: summary,
+            users: tally_users(cleared.iter().map(String::as_str)),
+        }))
+    } else {
+        Acr::Error(ArCliError {
+            message: format!(
+                "{} tally(s) could not be reset:\n  {}\n{summary}",
+                errors.len(),
+                errors.join("\n  ")
+            ),
+        })
+    }
+}
+
+/// Checks whether a tally matches the group, lock state and age criteria of a filter. The user
+/// is already matched when the tallies are listed.
+///
+/// # Arguments
+///
+/// - `status`: The status of the tally.
+/// - `filter`: The `ResetFilter` selecting the tallies.
+/// - `now`: The current time.
+fn is_selected(status: &TallyStatus, filter: &ResetFilter, now: DateTime<Utc>) -> bool {
+    if filter.locked_only && !status.locked {
+        return false;
+    }
+
+    // corrupt tallies have no failure time and are only reset without age filter
+    let is_old = |older_than| {
+        status
+            .last_failure
+            .is_some_and(|last_failure| now - last_failure > older_than)
+    };
+    if filter
+        .older_than
+        .is_some_and(|older_than| !is_old(older_than))
+    {
+        return false;
+    }
+
+    match &filter.group {
+        Some(group) => status.user().is_some_and(|user| is_member(&user, group)),
+        None => true,
+    }
+}
+
+/// Checks whether a user is a member of a group, either as primary or supplementary group.
+///
+/// # Arguments
+///
+/// - `user`: Name of the user.
+/// - `group`: Name of the group.
+fn is_member(user: &str, group: &str) -> bool {
+    let Some(user) = uzers::get_user_by_name(user) else {
+        return false;
+    };
+    uzers::get_user_groups(user.name(), user.primary_group_id())
+        .unwrap_or_default()
+        .iter()
+        .any(|member_of| member_of.name() == group)
+}
+
+/// Formats tally names for a summary.
+///
+/// # Arguments
+///
+/// - `names`: The names of the tallies.
+fn format_names(names: &[String]) -> String {
+    names
+        .iter()
+        .map(|name| format!("'{}'", name.yellow()))
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tempdir::TempDir;
+    use crate::cmd::temp_tally_dir;
+    use common::schema::{FailsTable, TallyFile};
+    use common::store::{FileStore, SqliteStore};
+    use std::fs;
+
+    fn delete_tally(store: &dyn TallyStore, user: &str) -> Acr {
+        let filter = ResetFilter {
+            user: Some(user.to_string()),
+            ..ResetFilter::default()
+        };
+        delete_tallies(store, &Config::default(), &filter, false, Utc::now())
+    }
+
+    fn tally(count: i32, instant: DateTime<Utc>) -> TallyFile {
+        TallyFile::new(
+            FailsTable {
+                count,
+                instant: Some(instant),
+                ..FailsTable::default()
+            },
+            Vec::new(),
+        )
+    }
 
     #[test]
     fn test_delete_tally() {
         // Create a temporary directory for testing
-        let temp_dir =
-            TempDir::new("test_delete_tally").expect("Failed to create temporary directory");
+        let temp_dir = temp_tally_dir("test_delete_tally");
 
         // Create a temporary file within the temporary directory
         let temp_tally_path = temp_dir.path().join("test_tally");
         fs::write(&temp_tally_path, "test tally").expect("Failed to create temporary file");
 
         // Load the Config into the reset_user function
-        let _result = delete_tally(&temp_tally_path, "test");
+        let _result = delete_tally(&FileStore::new(temp_dir.path()), "test_tally");
 
         // Assert that the file is deleted successfully
         assert!(!temp_tally_path.exists(), "Tally File not deleted!");
     }
+
+    #[test]
+    fn test_delete_tally_sqlite() {
+        let temp_dir = temp_tally_dir("test_delete_tally_sqlite");
+        let store = SqliteStore::open(temp_dir.path()).expect("Failed to open tally database");
+
+        store
+            .save("test", &TallyFile::new(FailsTable::default(), Vec::new()))
+            .expect("Failed to save tally");
+
+        store
+            .save(
+                "sshd:test",
+                &TallyFile::new(FailsTable::default(), Vec::new()),
+            )
+            .expect("Failed to save tally");
+        store
+            .save(
+                "sshd:other",
+                &TallyFile::new(FailsTable::default(), Vec::new()),
+            )
+            .expect("Failed to save tally");
+
+        assert!(matches!(delete_tally(&store, "test"), Acr::Success(_)));
+        assert!(matches!(delete_tally(&store, "test"), Acr::Info(_)));
+        assert_eq!(
+            store.list().unwrap(),
+            vec!["sshd:other".to_string()],
+            "Tally not deleted!"
+        );
+    }
+
+    #[test]
+    fn test_delete_tallies_filtered() {
+        let temp_dir = temp_tally_dir("test_delete_tallies_filtered");
+        let store = FileStore::new(temp_dir.path());
+        let audit_log = temp_dir.path().join("audit").join("audit.jsonl");
+        let config = Config {
+            audit_log: Some(audit_log.clone()),
+            ..Config::default()
+        };
+        let now = Utc::now();
+
+        store.save("alice", &tally(7, now)).unwrap();
+        store.save("bob", &tally(2, now)).unwrap();
+        store
+            .save("@192.0.2.0%2F24", &tally(7, now - Duration::days(8)))
+            .unwrap();
+
+        // a dry run keeps the tallies
+        let locked_only = ResetFilter {
+            locked_only: true,
+            ..ResetFilter::default()
+        };
+        assert!(matches!(
+            delete_tallies(&store, &config, &locked_only, true, now),
+            Acr::Info(_)
+        ));
+        assert_eq!(store.list().unwrap().len(), 3);
+
+        let older_than = ResetFilter {
+            older_than: Some(Duration::days(7)),
+            ..ResetFilter::default()
+        };
+        assert!(matches!(
+            delete_tallies(&store, &config, &older_than, false, now),
+            Acr::Success(_)
+        ));
+        assert_eq!(store.list().unwrap(), vec!["alice", "bob"]);
+
+        assert!(matches!(
+            delete_tallies(&store, &config, &locked_only, false, now),
+            Acr::Success(_)
+        ));
+        assert_eq!(store.list().unwrap(), vec!["bob"]);
+
+        // every reset tally is audited, a dry run is not
+        let events: Vec<serde_json::Value> = fs::read_to_string(&audit_log)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["event"], "reset");
+        assert_eq!(events[0]["rhost"], "192.0.2.0/24");
+        assert_eq!(events[1]["tally"], "alice");
+        assert_eq!(events[1]["count"], 7);
+
+        // nobody is a member of a group that does not exist
+        let group = ResetFilter {
+            group: Some("authramp-nonexistent".to_string()),
+            ..ResetFilter::default()
+        };
+        assert!(matches!(
+            delete_tallies(&store, &config, &group, false, now),
+            Acr::Info(_)
+        ));
+
+        assert!(matches!(
+            delete_tallies(&store, &config, &ResetFilter::default(), false, now),
+            Acr::Success(_)
+        ));
+        assert!(store.list().unwrap().is_empty());
+    }
 }
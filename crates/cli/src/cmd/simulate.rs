@@ -0,0 +1,294 @@
+//! # Simulate Module
+//!
+//! The `simulate` module provides the `simulate` subcommand of the CLI binary, which previews the
+//! delay schedule of a configuration. It simulates an attacker who guesses the password of one
+//! account as soon as it is unlocked, with the delays calculated by the `lockout` module of the
+//! `common` crate like the PAM module does. Failures expire after `fail_interval`.
+//!
+//! ## License
+//!
+//! pam-authramp
+//! Copyright (C) 2023 github.com/34N0
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::path::Path;
+
+use chrono::{DateTime, Duration, Utc};
+use colored::Colorize;
+use common::config::Config;
+use common::lockout;
+use serde_json::json;
+
+use crate::cmd::{format_columns, format_duration, load_config};
+use crate::{ArCliError, ArCliOutput, ArCliResult as Acr};
+
+/// The `Attempt` struct describes a failed attempt of the simulated attacker.
+#[derive(Debug, PartialEq)]
+struct Attempt {
+    /// Number of the attempt, starting at 1.
+    number: u64,
+    /// Time of the attempt since the start of the attack.
+    elapsed: Duration,
+    /// Delay until the next attempt, `None` if the account is locked until it is reset.
+    delay: Option<Duration>,
+    /// Total time the account was locked until the next attempt.
+    locked_out: Duration,
+}
+
+/// The `Attack` iterator simulates an attacker who fails to authenticate as soon as the account
+/// is unlocked. Every attempt takes at least one second. It ends when the account is locked until
+/// it is reset.
+struct Attack<'a> {
+    config: &'a Config,
+    start: DateTime<Utc>,
+    now: DateTime<Utc>,
+    failures: Vec<DateTime<Utc>>,
+    locked_out: Duration,
+    number: u64,
+}
+
+impl<'a> Attack<'a> {
+    /// Starts an attack on an account without failures.
+    ///
+    /// # Arguments
+    ///
+    /// - `config`: The configuration of the account.
+    fn new(config: &'a Config) -> Self {
+        Attack {
+            config,
+            start: DateTime::UNIX_EPOCH,
+            now: DateTime::UNIX_EPOCH,
+            failures: Vec::new(),
+            locked_out: Duration::zero(),
+            number: 0,
+        }
+    }
+}
+
+impl Iterator for Attack<'_> {
+    type Item = Attempt;
+
+    fn next(&mut self) -> Option<Attempt> {
+        let count = |failures: &[DateTime<Utc>]| i32::try_from(failures.len()).unwrap_or(i32::MAX);
+
+        // failures expire like in the module
+        if let Some((_, failures)) = lockout::unexpired_failures(
+            self.config,
+            count(&self.failures),
+            self.now,
+            &self.failures,
+            self.now,
+        ) {
+            self.failures = failures;
+        }
+        if lockout::is_permanently_locked(self.config, count(&self.failures)) {
+            return None;
+        }
+
+        self.failures.push(self.now);
+        self.number += 1;
+        let elapsed = self.now - self.start;
+
+        let delay = match lockout::locked_until(self.config, count(&self.failures), self.now, None)
+        {
+            Some(locked_until) if locked_until == DateTime::<Utc>::MAX_UTC => None,
+            Some(locked_until) => Some(locked_until - self.now),
+            None => Some(Duration::zero()),
+        };
+        if let Some(delay) = delay {
+            self.locked_out += delay;
+            self.now += delay.max(Duration::seconds(1));
+        }
+
+        Some(Attempt {
+            number: self.number,
+            elapsed,
+            delay,
+            locked_out: self.locked_out,
+        })
+    }
+}
+
+/// Prints the delay schedule of a configuration and estimates the guesses per day an attacker
+/// can make against one account.
+///
+/// # Arguments
+///
+/// - `file`: The configuration file to simulate. If not given, the file in `AUTHRAMP_CONFIG` or
+///   the default configuration file is used.
+/// - `attempts`: Number of failed attempts to print.
+///
+/// # Returns
+///
+/// A `Result` representing the outcome of the operation.
+///
+/// - If successful, returns `ArCliResult::Output` with the schedule and the estimate.
+/// - If the configuration file does not exist, returns `ArCliResult::Error` with an `ArCliError` containing the error message.
+pub fn simulate(file: Option<&str>, attempts: u64) -> Acr {
+    let config = match file {
+        Some(file) if !Path::new(file).exists() => {
+            return Acr::Error(ArCliError {
+                message: format!("configuration file not found: '{}'", file.yellow()),
+            })
+        }
+        Some(file) => Config::load_file(Some(file), None),
+        None => load_config(),
+    };
+
+    let schedule: Vec<Attempt> = Attack::new(&config)
+        .take(usize::try_from(attempts).unwrap_or(usize::MAX))
+        .collect();
+    let (guesses, permanent) = estimate(&config);
+
+    Acr::Output(ArCliOutput {
+        text: format!(
+            "{}\n\n{}",
+            format_schedule(&schedule),
+            format_estimate(guesses, permanent)
+        ),
+        users: Vec::new(),
+        data: json!({
+            "attempts": schedule
+                .iter()
+                .map(|attempt| json!({
+                    "attempt": attempt.number,
+                    "delay_seconds": attempt.delay.map(|delay| delay.num_seconds()),
+                    "locked_out_seconds": attempt.locked_out.num_seconds(),
+                }))
+                .collect::<Vec<_>>(),
+            "guesses_per_day": guesses,
+            "permanent_lock": permanent,
+        }),
+    })
+}
+
+/// Formats the delays of failed attempts as table.
+///
+/// # Arguments
+///
+/// - `schedule`: The failed attempts.
+fn format_schedule(schedule: &[Attempt]) -> String {
+    let rows: Vec<[String; 3]> = schedule
+        .iter()
+        .map(|attempt| {
+            [
+                attempt.number.to_string(),
+                match attempt.delay {
+                    Some(delay) if delay.is_zero() => "-".to_string(),
+                    Some(delay) => format_duration(delay),
+                    None => "until reset".to_string(),
+                },
+                format_duration(attempt.locked_out),
+            ]
+        })
+        .collect();
+
+    format_columns(&["ATTEMPT", "DELAY", "LOCKED OUT"], &rows)
+}
+
+/// Estimates the number of guesses an attacker can make against one account within a day.
+///
+/// # Arguments
+///
+/// - `config`: The configuration.
+///
+/// # Returns
+///
+/// The number of guesses and whether the account is locked until it is reset after them.
+fn estimate(config: &Config) -> (u64, bool) {
+    let day = Duration::days(1);
+    let last = Attack::new(config)
+        .take_while(|attempt| attempt.elapsed < day)
+        .last();
+
+    (
+        last.as_ref().map_or(0, |attempt| attempt.number),
+        last.is_some_and(|attempt| attempt.delay.is_none()),
+    )
+}
+
+/// Formats the estimated guesses of an attacker.
+///
+/// # Arguments
+///
+/// - `guesses`: The number of guesses within a day.
+/// - `permanent`: Whether the account is locked until it is reset after them.
+fn format_estimate(guesses: u64, permanent: bool) -> String {
+    if permanent {
+        format!("An attacker can make {guesses} guesses before the account is locked until it is reset.")
+    } else {
+        format!("An attacker can make about {guesses} guesses per day against one account.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attack() {
+        let config = Config::default();
+        let attempts: Vec<Attempt> = Attack::new(&config).take(8).collect();
+
+        // the free tries are not delayed
+        assert_eq!(attempts[5].delay, Some(Duration::zero()));
+        assert_eq!(attempts[5].elapsed, Duration::seconds(5));
+        assert_eq!(attempts[6].delay, Some(Duration::seconds(30)));
+        assert_eq!(attempts[7].elapsed, Duration::seconds(36));
+        assert_eq!(
+            attempts[7].locked_out,
+            Duration::seconds(30) + attempts[7].delay.unwrap()
+        );
+
+        // the attack ends with a permanent lock
+        let config = Config {
+            permanent_lock_after: Some(10),
+            ..Config::default()
+        };
+        let attempts: Vec<Attempt> = Attack::new(&config).collect();
+        assert_eq!(attempts.len(), 10);
+        assert_eq!(attempts[9].delay, None);
+        assert_eq!(estimate(&config), (10, true));
+    }
+
+    #[test]
+    fn test_fail_interval_forgets_failures() {
+        // failures expire before the account is locked
+        let config = Config {
+            free_tries: 2,
+            fail_interval: Some(1),
+            ..Config::default()
+        };
+        assert!(Attack::new(&config)
+            .take(100)
+            .all(|attempt| attempt.delay == Some(Duration::zero())));
+        assert_eq!(estimate(&config), (86400, false));
+    }
+
+    #[test]
+    fn test_format_schedule() {
+        let config = Config {
+            free_tries: 1,
+            ..Config::default()
+        };
+        assert_eq!(
+            format_schedule(&Attack::new(&config).take(3).collect::<Vec<_>>()),
+            "ATTEMPT  DELAY   LOCKED OUT\n\
+             1        -       0s\n\
+             2        30s     30s\n\
+             3        1m 39s  2m 9s"
+        );
+    }
+}
@@ -0,0 +1,420 @@
+//! # Status Module
+//!
+//! The `status` module provides the `status` subcommand of the CLI binary, which lists the tallies
+//! in the configured tally store. For each tally it shows the failures, the last failure, the unlock
+//! time and whether the account is currently locked. The lockout is calculated with the `lockout`
+//! module of the `common` crate and the overrides of the user, like the PAM module does.
+//!
+//! ## License
+//!
+//! pam-authramp
+//! Copyright (C) 2023 github.com/34N0
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use chrono::{DateTime, Duration, Utc};
+use colored::Colorize;
+use common::config::Config;
+use common::lockout;
+use common::schema::{AdminLock, TallyFile};
+use common::store::{self, StoreError, TallyStore};
+use common::track::TallyKey;
+use serde::Serialize;
+
+use crate::cmd::{format_columns, format_duration, load_config, tally_users};
+use crate::{ArCliError, ArCliInfo, ArCliOutput, ArCliResult as Acr};
+
+/// The `TallyStatus` struct describes the lockout of a single tally.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct TallyStatus {
+    /// Name of the tally in the tally store.
+    pub tally: String,
+    /// Number of failures that are not expired.
+    pub failures: i32,
+    /// Timestamp of the last failure.
+    pub last_failure: Option<DateTime<Utc>>,
+    /// Time the account is unlocked, `None` if it is not locked or locked until it is reset.
+    pub unlock_instant: Option<DateTime<Utc>>,
+    /// Seconds until the account is unlocked.
+    pub remaining_seconds: i64,
+    /// Whether the account is currently locked.
+    pub locked: bool,
+    /// Whether the account is locked until it is reset.
+    pub permanent: bool,
+    /// The active lockout set by an administrator.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin_lock: Option<AdminLock>,
+    /// Why the tally cannot be loaded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl TallyStatus {
+    /// Returns the user of the tally, `None` for remote host tallies.
+    #[must_use]
+    pub fn user(&self) -> Option<String> {
+        TallyKey::from_store_name(&self.tally)
+            .1
+            .user()
+            .map(ToString::to_string)
+    }
+}
+
+/// Lists the tallies in the configured tally store.
+///
+/// # Arguments
+///
+/// - `user`: Only list the tallies of this user, including the tallies of the user in any
+///   `tally_namespace` and from any remote host.
+///
+/// # Returns
+///
+/// A `Result` representing the outcome of the operation.
+///
+/// - If successful, returns `ArCliResult::Output` with the tallies.
+/// - If there are no tallies, returns `ArCliResult::Info` with an `ArCliInfo` containing an informational message.
+/// - If the tally store cannot be read, returns `ArCliResult::Error` with an `ArCliError` containing the error message.
+pub fn status(user: Option<&str>) -> Acr {
+    let config = load_config();
+
+    let statuses = store::open(&config)
+        .and_then(|store| tally_statuses(store.as_ref(), &config, user, Utc::now()));
+
+    let statuses = match statuses {
+        Ok(statuses) => statuses,
+        Err(e) => {
+            return Acr::Error(ArCliError {
+                message: format!("{e}"),
+            })
+        }
+    };
+
+    let data = match serde_json::to_value(&statuses) {
+        Ok(data) => data,
+        Err(e) => {
+            return Acr::Error(ArCliError {
+                message: format!("{e}"),
+            })
+        }
+    };
+    let users = tally_users(statuses.iter().map(|status| status.tally.as_str()));
+
+    if statuses.is_empty() {
+        Acr::Info(ArCliInfo {
+            message: match user {
+                Some(user) => format!("No tally found for user: '{}'", user.yellow()),
+                None => "No tallies found".to_string(),
+            },
+            users,
+        })
+    } else {
+        Acr::Output(ArCliOutput {
+            text: format_table(&statuses),
+            users,
+            data,
+        })
+    }
+}
+
+/// Calculates the status of the tallies in a tally store.
+///
+/// # Arguments
+///
+/// - `store`: The tally store.
+/// - `config`: The configuration the PAM module uses.
+/// - `user`: Only include the tallies of this user.
+/// - `now`: The current time.
+///
+/// # Returns
+///
+/// The `TallyStatus` of each tally, sorted by name.
+///
+/// # Errors
+///
+/// Returns a `StoreError` if the tallies cannot be listed or read.
+pub fn tally_statuses(
+    store: &dyn TallyStore,
+    config: &Config,
+    user: Option<&str>,
+    now: DateTime<Utc>,
+) -> Result<Vec<TallyStatus>, StoreError> {
+    let mut statuses = Vec::new();
+
+    for name in store.list()? {
+        let (namespace, key) = TallyKey::from_store_name(&name);
+        if user.is_some() && key.user() != user {
+            continue;
+        }
+
+        let status = match store.load(&name) {
+            Ok(Some(tally_file)) => {
+                let config = tally_config(config, namespace.as_deref(), &key);
+                tally_status(name, &tally_file, &config, now)
+            }
+            Ok(None) => continue,
+            Err(StoreError::Corrupt { error, .. }) => TallyStatus {
+                tally: name,
+                failures: 0,
+                last_failure: None,
+                unlock_instant: None,
+                remaining_seconds: 0,
+                locked: false,
+                permanent: false,
+                admin_lock: None,
+                error: Some(error.to_string()),
+            },
+            Err(e) => return Err(e),
+        };
+        statuses.push(status);
+    }
+
+    Ok(statuses)
+}
+
+/// Resolves the configuration the PAM module applies to a tally. The overrides of the user are
+/// applied, together with the overrides of the service whose `tally_namespace` holds the tally.
+///
+/// # Arguments
+///
+/// - `config`: The configuration the PAM module uses.
+/// - `namespace`: The namespace of the tally.
+/// - `key`: The key of the tally.
+fn tally_config(config: &Config, namespace: Option<&str>, key: &TallyKey) -> Config {
+    let mut config = config.clone();
+
+    let service = namespace.and_then(|namespace| {
+        config
+            .services
+            .iter()
+            .filter(|(_, overrides)| overrides.tally_namespace.as_deref() == Some(namespace))
+            .map(|(service, _)| service.clone())
+            .min()
+    });
+
+    match key.user().and_then(uzers::get_user_by_name) {
+        Some(user) => config.apply_overrides(&user, service.as_deref()),
+        None => {
+            if let Some(overrides) = service.and_then(|service| config.services.get(&service)) {
+                overrides.clone().apply(&mut config);
+            }
+        }
+    }
+
+    config
+}
+
+/// Calculates the status of a single tally.
+///
+/// # Arguments
+///
+/// - `name`: Name of the tally in the tally store.
+/// - `tally_file`: The stored tally.
+/// - `config`: The configuration that applies to the tally.
+/// - `now`: The current time.
+fn tally_status(
+    name: String,
+    tally_file: &TallyFile,
+    config: &Config,
+    now: DateTime<Utc>,
+) -> TallyStatus {
+    let fails = &tally_file.fails;
+    let failure_instant = fails.instant.unwrap_or_default();
+
+    let failures =
+        lockout::unexpired_failures(config, fails.count, failure_instant, &fails.instants, now)
+            .map_or(fails.count, |(failures, _)| failures);
+
+    let admin_lock = tally_file
+        .lock
+        .clone()
+        .filter(|admin_lock| admin_lock.is_active(now));
+    let locked_until =
+        lockout::locked_until(config, failures, failure_instant, fails.unlock_instant)
+            .max(admin_lock.as_ref().map(AdminLock::locked_until))
+            .filter(|locked_until| *locked_until > now);
+    let permanent = locked_until == Some(DateTime::<Utc>::MAX_UTC);
+
+    TallyStatus {
+        tally: name,
+        failures,
+        last_failure: fails.instant,
+        unlock_instant: locked_until.filter(|_| !permanent),
+        remaining_seconds: locked_until
+            .filter(|_| !permanent)
+            .map_or(0, |locked_until| (locked_until - now).num_seconds()),
+        locked: locked_until.is_some(),
+        permanent,
+        admin_lock,
+        error: None,
+    }
+}
+
+/// Formats the tallies as table.
+///
+/// # Arguments
+///
+/// - `statuses`: The status of the tallies.
+fn format_table(statuses: &[TallyStatus]) -> String {
+    let format_instant = |instant: Option<DateTime<Utc>>| {
+        instant.map_or("-".to_string(), |instant| {
+            instant.format("%Y-%m-%d %H:%M:%S").to_string()
+        })
+    };
+
+    let header = [
+        "TALLY",
+        "FAILURES",
+        "LAST FAILURE",
+        "UNLOCK",
+        "REMAINING",
+        "LOCKED",
+    ];
+
+    let rows: Vec<[String; 6]> = statuses
+        .iter()
+        .map(|status| {
+            let (unlock, remaining) = match (&status.error, status.permanent) {
+                (Some(error), _) => (format!("corrupt: {error}"), "-".to_string()),
+                (None, true) => ("until reset".to_string(), "-".to_string()),
+                (None, false) if status.locked => (
+                    format_instant(status.unlock_instant),
+                    format_duration(Duration::seconds(status.remaining_seconds)),
+                ),
+                (None, false) => ("-".to_string(), "-".to_string()),
+            };
+            [
+                status.tally.clone(),
+                status.failures.to_string(),
+                format_instant(status.last_failure),
+                unlock,
+                remaining,
+                match (status.locked, &status.admin_lock) {
+                    (true, Some(_)) => "yes (admin)",
+                    (true, None) => "yes",
+                    (false, _) => "no",
+                }
+                .to_string(),
+            ]
+        })
+        .collect();
+
+    format_columns(&header, &rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::temp_tally_dir;
+    use common::schema::FailsTable;
+    use common::store::FileStore;
+    use std::fs;
+
+    fn tally(count: i32, instant: DateTime<Utc>) -> TallyFile {
+        TallyFile::new(
+            FailsTable {
+                count,
+                instant: Some(instant),
+                ..FailsTable::default()
+            },
+            Vec::new(),
+        )
+    }
+
+    #[test]
+    fn test_tally_statuses() {
+        let temp_dir = temp_tally_dir("test_tally_statuses");
+        let store = FileStore::new(temp_dir.path());
+        let config = Config {
+            tally_dir: temp_dir.path().to_path_buf(),
+            permanent_lock_after: Some(20),
+            ..Config::default()
+        };
+        let now = Utc::now();
+
+        store.save("alice", &tally(7, now)).unwrap();
+        store
+            .save("sshd:alice@192.0.2.0%2F24", &tally(3, now))
+            .unwrap();
+        store.save("bob", &tally(20, now)).unwrap();
+        store
+            .save("@192.0.2.0%2F24", &tally(7, now - Duration::hours(1)))
+            .unwrap();
+        fs::write(temp_dir.path().join("mallory"), "corrupt").unwrap();
+
+        let statuses = tally_statuses(&store, &config, None, now).unwrap();
+        let names: Vec<&str> = statuses.iter().map(|s| s.tally.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "@192.0.2.0%2F24",
+                "alice",
+                "bob",
+                "mallory",
+                "sshd:alice@192.0.2.0%2F24"
+            ]
+        );
+
+        // the lock of the remote host expired
+        assert!(!statuses[0].locked);
+        assert_eq!(statuses[0].unlock_instant, None);
+
+        // 7 failures lock the account for the base delay
+        assert_eq!(
+            statuses[1],
+            TallyStatus {
+                tally: "alice".to_string(),
+                failures: 7,
+                last_failure: Some(now),
+                unlock_instant: Some(now + Duration::seconds(30)),
+                remaining_seconds: 30,
+                locked: true,
+                permanent: false,
+                admin_lock: None,
+                error: None,
+            }
+        );
+
+        assert!(statuses[2].locked && statuses[2].permanent);
+        assert_eq!(statuses[2].admin_lock, None);
+        assert!(statuses[3].error.is_some());
+        assert!(!statuses[4].locked);
+
+        let statuses = tally_statuses(&store, &config, Some("alice"), now).unwrap();
+        assert_eq!(statuses.len(), 2);
+    }
+
+    #[test]
+    fn test_format_table() {
+        let now = DateTime::parse_from_rfc3339("2024-02-04T00:42:42Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let table = format_table(&[TallyStatus {
+            tally: "alice".to_string(),
+            failures: 7,
+            last_failure: Some(now),
+            unlock_instant: Some(now + Duration::seconds(90)),
+            remaining_seconds: 90,
+            locked: true,
+            permanent: false,
+            admin_lock: None,
+            error: None,
+        }]);
+
+        assert_eq!(
+            table,
+            "TALLY  FAILURES  LAST FAILURE         UNLOCK               REMAINING  LOCKED\n\
+             alice  7         2024-02-04 00:42:42  2024-02-04 00:44:12  1m 30s     yes"
+        );
+    }
+}
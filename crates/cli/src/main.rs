@@ -12,18 +12,27 @@
 //! ```bash
 //! # Reset a locked PAM user
 //! authramp reset --user example_user
+//!
+//! # Reset all accounts that are currently locked
+//! authramp reset --all --locked-only
 //! ```
 //!
 //! # Commands
 //!
-//! - [`reset`](cmd/reset/index.html): Resets a locked PAM user.
+//! - [`reset`](cmd/reset/index.html): Resets locked PAM users.
+//! - [`config`](cmd/config/index.html): Checks and shows the configuration.
+//! - [`lock`](cmd/lock/index.html): Locks a PAM user until a time or until it is reset.
+//! - [`status`](cmd/status/index.html): Lists the tallies and whether the accounts are locked.
+//! - [`simulate`](cmd/simulate/index.html): Previews the delay schedule of a configuration.
 //!
 //! # Structs
 //!
 //! - [`ArCliError`](struct.ArCliError.html): Represents an error result in the `AuthRamp` CLI.
 //! - [`ArCliSuccess`](struct.ArCliSuccess.html): Represents a success result in the `AuthRamp` CLI.
 //! - [`ArCliInfo`](struct.ArCliInfo.html): Represents an informational result in the `AuthRamp` CLI.
+//! - [`ArCliOutput`](struct.ArCliOutput.html): Represents the data printed by a command in the `AuthRamp` CLI.
 //! - [`ArCliResult`](struct.ArCliResult.html): Represents the result of a command execution in the `AuthRamp` CLI.
+//! - [`ArCliReport`](struct.ArCliReport.html): Represents a result printed as JSON with `--output json`.
 //! - [`Cli`](struct.Cli.html): Represents the main CLI struct.
 //! - [`Command`](enum.Command.html): Represents the available subcommands.
 //!
@@ -45,9 +54,10 @@
 //! You should have received a copy of the GNU General Public License
 //! along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use clap::{Parser, Subcommand};
-use cmd::reset;
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
+use cmd::{config, lock, reset, simulate, status};
 use colored::Colorize;
+use serde::Serialize;
 use std::fmt;
 mod cmd;
 
@@ -66,7 +76,8 @@ by 34n0@immerda.ch";
 /// to format the message with colors and text.
 ///
 /// `ArCliResult` is an enum with variants to hold the different structs.
-/// It implements `Display` to delegate to the inner value's implementation.
+/// It implements `Display` to delegate to the inner value's implementation,
+/// and `to_json` to render it as `ArCliReport` for `--output json`.
 
 #[derive(Debug)]
 pub struct ArCliError {
@@ -82,6 +93,7 @@ impl fmt::Display for ArCliError {
 #[derive(Debug)]
 pub struct ArCliSuccess {
     message: String,
+    users: Vec<String>,
 }
 
 impl fmt::Display for ArCliSuccess {
@@ -93,6 +105,7 @@ impl fmt::Display for ArCliSuccess {
 #[derive(Debug)]
 pub struct ArCliInfo {
     message: String,
+    users: Vec<String>,
 }
 
 impl fmt::Display for ArCliInfo {
@@ -101,11 +114,21 @@ impl fmt::Display for ArCliInfo {
     }
 }
 
+/// Output of a command that prints data, e.g. a table. `text` is printed as is, `data` is the
+/// same content for `--output json`.
+#[derive(Debug)]
+pub struct ArCliOutput {
+    text: String,
+    users: Vec<String>,
+    data: serde_json::Value,
+}
+
 #[derive(Debug)]
 pub enum ArCliResult {
     Success(Option<ArCliSuccess>),
     Info(ArCliInfo),
     Error(ArCliError),
+    Output(ArCliOutput),
 }
 
 impl fmt::Display for ArCliResult {
@@ -115,43 +138,261 @@ impl fmt::Display for ArCliResult {
             ArCliResult::Success(None) => Ok(()),
             ArCliResult::Error(ref error) => write!(f, "{error}"),
             ArCliResult::Info(ref info) => write!(f, "{info}"),
+            ArCliResult::Output(ref output) => write!(f, "{}", output.text.trim_end()),
         }
     }
 }
 
+impl ArCliResult {
+    /// Returns the exit status of the binary: 1 for `Error`, 0 otherwise.
+    fn exit_code(&self) -> i32 {
+        i32::from(matches!(self, ArCliResult::Error(_)))
+    }
+
+    /// Renders the result as `ArCliReport` JSON object.
+    fn to_json(&self) -> String {
+        let (status, message, users, data) = match self {
+            ArCliResult::Success(success) => (
+                "success",
+                success.as_ref().map(|success| success.message.as_str()),
+                success.as_ref().map_or(&[][..], |success| &success.users),
+                None,
+            ),
+            ArCliResult::Info(info) => ("info", Some(info.message.as_str()), &info.users[..], None),
+            ArCliResult::Error(error) => ("error", Some(error.message.as_str()), &[][..], None),
+            ArCliResult::Output(output) => ("success", None, &output.users[..], Some(&output.data)),
+        };
+
+        let report = ArCliReport {
+            status,
+            exit_code: self.exit_code(),
+            message,
+            users,
+            data,
+        };
+        serde_json::to_string_pretty(&report).unwrap_or_default()
+    }
+}
+
+/// The JSON object printed for `--output json`. The field names are stable.
+#[derive(Serialize)]
+struct ArCliReport<'a> {
+    /// `success`, `info` or `error`.
+    status: &'a str,
+    /// The exit status of the binary.
+    exit_code: i32,
+    /// The message without colors, `null` if the command only prints data.
+    message: Option<&'a str>,
+    /// The users the command affected or listed.
+    users: &'a [String],
+    /// The data the command printed, `null` if it has none.
+    data: Option<&'a serde_json::Value>,
+}
+
+/// Format the result is printed in, selected with `--output`.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
 #[derive(Parser, Debug)]
 #[command(
     arg_required_else_help = true,
     author = "34n0",
     about = &BANNER,
 )]
-
 struct Cli {
     #[command(subcommand)]
     command: Option<Command>,
+    #[clap(long, short, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
 }
 
 #[derive(Subcommand, Debug)]
 enum Command {
-    #[command(about = "Reset a locked PAM user")]
+    #[command(about = "Reset locked PAM users")]
+    #[command(group = ArgGroup::new("target").required(true).multiple(true))]
     Reset {
+        #[clap(long, short, group = "target")]
+        user: Option<String>,
+        #[clap(long, group = "target", conflicts_with_all = ["user", "group"])]
+        all: bool,
+        #[clap(long, short, group = "target")]
+        group: Option<String>,
+        #[clap(long)]
+        locked_only: bool,
+        #[clap(long, value_parser = cmd::parse_duration)]
+        older_than: Option<chrono::Duration>,
+        #[clap(long)]
+        dry_run: bool,
+    },
+    #[command(about = "Lock a PAM user")]
+    #[command(group = ArgGroup::new("expiry").required(true))]
+    Lock {
         #[clap(long, short)]
         user: String,
+        #[clap(long, group = "expiry", value_parser = cmd::parse_time)]
+        until: Option<chrono::DateTime<chrono::Utc>>,
+        #[clap(long = "for", group = "expiry", value_parser = cmd::parse_duration)]
+        duration: Option<chrono::Duration>,
+        #[clap(long, group = "expiry")]
+        permanent: bool,
+        #[clap(long, short)]
+        reason: Option<String>,
+    },
+    #[command(about = "List the tallies and locked accounts")]
+    Status {
+        #[clap(long, short)]
+        user: Option<String>,
+    },
+    #[command(about = "Preview the delay schedule of a configuration")]
+    Simulate {
+        #[clap(long, short)]
+        config: Option<String>,
+        #[clap(long, short, default_value_t = 20)]
+        attempts: u64,
+    },
+    #[command(about = "Inspect the configuration")]
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    #[command(about = "Validate the configuration file and its drop-in fragments")]
+    Check {
+        #[clap(long, short)]
+        file: Option<String>,
+    },
+    #[command(about = "Print the effective configuration")]
+    Show {
+        #[clap(long, short)]
+        file: Option<String>,
+        #[clap(long, short)]
+        user: Option<String>,
+        #[clap(long, short, requires = "user")]
+        service: Option<String>,
     },
 }
 
 /// Main entry point for the `AuthRamp` CLI binary.
 ///
 /// Initializes the syslog, parses command-line arguments, executes the corresponding subcommand,
-/// and prints the result.
+/// and prints the result as text or JSON. Exits with status 1 if the subcommand failed.
 fn main() {
     //syslog::init_cli_log().unwrap_or_else(|e| println!("{e:?}: Error initializing cli log:"));
 
-    let cli_res = match Cli::parse().command {
-        Some(Command::Reset { user }) => reset::user(&user),
+    let cli = Cli::parse();
+    if cli.output == OutputFormat::Json {
+        colored::control::set_override(false);
+    }
+
+    let cli_res = match cli.command {
+        Some(Command::Reset {
+            user,
+            all: _,
+            group,
+            locked_only,
+            older_than,
+            dry_run,
+        }) => reset::tallies(
+            &reset::ResetFilter {
+                user,
+                group,
+                locked_only,
+                older_than,
+            },
+            dry_run,
+        ),
+        Some(Command::Lock {
+            user,
+            until,
+            duration,
+            permanent: _,
+            reason,
+        }) => match duration {
+            Some(duration) => match chrono::Utc::now().checked_add_signed(duration) {
+                Some(until) => lock::user(&user, Some(until), reason),
+                None => ArCliResult::Error(ArCliError {
+                    message: "the lock duration is too long".to_string(),
+                }),
+            },
+            None => lock::user(&user, until, reason),
+        },
+        Some(Command::Status { user }) => status::status(user.as_deref()),
+        Some(Command::Simulate { config, attempts }) => {
+            simulate::simulate(config.as_deref(), attempts)
+        }
+        Some(Command::Config {
+            command: ConfigCommand::Check { file },
+        }) => config::check(file.as_deref()),
+        Some(Command::Config {
+            command:
+                ConfigCommand::Show {
+                    file,
+                    user,
+                    service,
+                },
+        }) => config::show(file.as_deref(), user.as_deref(), service.as_deref()),
         _ => ArCliResult::Success(None),
     };
 
     // Print the result
-    println!("{cli_res}");
+    match cli.output {
+        OutputFormat::Text => println!("{cli_res}"),
+        OutputFormat::Json => println!("{}", cli_res.to_json()),
+    }
+
+    std::process::exit(cli_res.exit_code());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_to_json() {
+        let result = ArCliResult::Success(Some(ArCliSuccess {
+            message: "1 tally(s) reset".to_string(),
+            users: vec!["alice".to_string()],
+        }));
+        assert_eq!(result.exit_code(), 0);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&result.to_json()).unwrap(),
+            serde_json::json!({
+                "status": "success",
+                "exit_code": 0,
+                "message": "1 tally(s) reset",
+                "users": ["alice"],
+                "data": null,
+            })
+        );
+
+        let result = ArCliResult::Error(ArCliError {
+            message: "user not found".to_string(),
+        });
+        assert_eq!(result.exit_code(), 1);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&result.to_json()).unwrap()["status"],
+            "error"
+        );
+
+        let result = ArCliResult::Output(ArCliOutput {
+            text: "TALLY".to_string(),
+            users: Vec::new(),
+            data: serde_json::json!([]),
+        });
+        let report = serde_json::from_str::<serde_json::Value>(&result.to_json()).unwrap();
+        assert_eq!(report["message"], serde_json::Value::Null);
+        assert_eq!(report["data"], serde_json::json!([]));
+    }
 }
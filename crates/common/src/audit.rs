@@ -0,0 +1,431 @@
+//! # Audit Module
+//!
+//! The `audit` module writes the structured audit log configured with `audit_log`. Unlike the
+//! free-form syslog messages, every event is a JSON object on its own line with stable field
+//! names, so it can be shipped to a SIEM as is. The PAM module logs failures, lockouts, bounced
+//! attempts and unlocks, the CLI binary logs administrative resets and locks.
+//!
+//! With `journald` enabled, the events are also sent to systemd-journald with `AUTHRAMP_*`
+//! fields and a `MESSAGE_ID` per event type, e.g.
+//! `journalctl MESSAGE_ID=966f4af4174e47f899575b95a9ea3bd7` lists the lockouts.
+//!
+//! ```json
+//! {"time":"2024-02-04T00:42:42Z","event":"lockout","tally":"alice","user":"alice","uid":1000,"service":"sshd","rhost":"192.0.2.1","tty":"ssh","count":7,"unlock_time":"2024-02-04T00:43:12Z","permanent":false,"reason":null}
+//! ```
+//!
+//! ## License
+//!
+//! pam-authramp
+//! Copyright (C) 2023 github.com/34N0
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fmt;
+use std::fs::{DirBuilder, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use pam::journal::Journal;
+use pam::LogLevel;
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::settings::Settings;
+use crate::track::TallyKey;
+
+/// The `AuditEventKind` enum defines what happened to an account.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEventKind {
+    /// A failed attempt was added to a tally.
+    Failure,
+    /// A failed attempt locked the account.
+    Lockout,
+    /// An attempt was bounced because the account is locked.
+    Bounce,
+    /// A successful login cleared the failures of a tally.
+    Unlock,
+    /// An administrator reset a tally with `authramp reset`.
+    Reset,
+    /// An administrator locked the account with `authramp lock`.
+    Lock,
+}
+
+impl AuditEventKind {
+    /// Returns the `MESSAGE_ID` of the journal entries of the event type. The ids never change.
+    #[must_use]
+    pub fn message_id(self) -> &'static str {
+        match self {
+            AuditEventKind::Failure => "0839d39f56d04ae19b633ee6b91bcd89",
+            AuditEventKind::Lockout => "966f4af4174e47f899575b95a9ea3bd7",
+            AuditEventKind::Bounce => "371d7f17a9524c979dd2028316a6f843",
+            AuditEventKind::Unlock => "214920a31aa54785bec9d6a802fc3490",
+            AuditEventKind::Reset => "cd49ad7b6f884505827f84dbbf843725",
+            AuditEventKind::Lock => "ad7881da56f048c886b93afe0f0adb22",
+        }
+    }
+
+    /// Returns the priority of the journal entries of the event type.
+    fn level(self) -> LogLevel {
+        match self {
+            AuditEventKind::Failure | AuditEventKind::Unlock => LogLevel::Info,
+            _ => LogLevel::Notice,
+        }
+    }
+}
+
+impl fmt::Display for AuditEventKind {
+    /// Formats the event type like in the audit log.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AuditEventKind::Failure => "failure",
+            AuditEventKind::Lockout => "lockout",
+            AuditEventKind::Bounce => "bounce",
+            AuditEventKind::Unlock => "unlock",
+            AuditEventKind::Reset => "reset",
+            AuditEventKind::Lock => "lock",
+        })
+    }
+}
+
+/// The `AuditEvent` struct is a line of the audit log. Fields that do not apply are `null`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuditEvent {
+    /// Time of the event.
+    pub time: DateTime<Utc>,
+    /// What happened.
+    pub event: AuditEventKind,
+    /// Name of the tally in the tally store.
+    pub tally: Option<String>,
+    /// The user of the account.
+    pub user: Option<String>,
+    /// The uid of the user.
+    pub uid: Option<u32>,
+    /// The PAM service (`PAM_SERVICE`).
+    pub service: Option<String>,
+    /// The remote host (`PAM_RHOST`).
+    pub rhost: Option<String>,
+    /// The terminal (`PAM_TTY`).
+    pub tty: Option<String>,
+    /// Number of failures of the tally.
+    pub count: Option<i32>,
+    /// Time the account is unlocked, `null` if it is not locked or locked until it is reset.
+    pub unlock_time: Option<DateTime<Utc>>,
+    /// Whether the account is locked until it is reset.
+    pub permanent: bool,
+    /// Why an administrator locked the account.
+    pub reason: Option<String>,
+}
+
+impl AuditEvent {
+    /// Creates an event of the PAM module for the user and the PAM items of an attempt.
+    ///
+    /// # Arguments
+    ///
+    /// * `event`: What happened.
+    /// * `settings`: The settings of the attempt.
+    /// * `tally`: Name of the tally in the tally store, if the event concerns a single tally.
+    #[must_use]
+    pub fn from_settings(event: AuditEventKind, settings: &Settings, tally: Option<&str>) -> Self {
+        AuditEvent {
+            time: Utc::now(),
+            event,
+            tally: tally.map(str::to_string),
+            user: settings
+                .user
+                .as_ref()
+                .map(|user| user.name().to_string_lossy().to_string()),
+            uid: settings.user.as_ref().map(uzers::User::uid),
+            service: settings.items.service.clone(),
+            rhost: settings.items.rhost.clone(),
+            tty: settings.items.tty.clone(),
+            count: None,
+            unlock_time: None,
+            permanent: false,
+            reason: None,
+        }
+    }
+
+    /// Creates an event of the CLI binary for a tally. The user and the remote host are taken
+    /// from the name of the tally.
+    ///
+    /// # Arguments
+    ///
+    /// * `event`: What happened.
+    /// * `tally`: Name of the tally in the tally store.
+    #[must_use]
+    pub fn from_tally(event: AuditEventKind, tally: &str) -> Self {
+        let (_, key) = TallyKey::from_store_name(tally);
+        let rhost = match &key {
+            TallyKey::User(_) => None,
+            TallyKey::RHost(rhost) | TallyKey::UserRHost(_, rhost) => Some(rhost.clone()),
+        };
+
+        AuditEvent {
+            time: Utc::now(),
+            event,
+            tally: Some(tally.to_string()),
+            user: key.user().map(str::to_string),
+            uid: key
+                .user()
+                .and_then(uzers::get_user_by_name)
+                .map(|user| user.uid()),
+            service: None,
+            rhost,
+            tty: None,
+            count: None,
+            unlock_time: None,
+            permanent: false,
+            reason: None,
+        }
+    }
+
+    /// Sets the time the account is unlocked as returned by `lockout::locked_until`.
+    ///
+    /// # Arguments
+    ///
+    /// * `locked_until`: The unlock time, `DateTime::<Utc>::MAX_UTC` if the account is locked
+    ///   until it is reset.
+    #[must_use]
+    pub fn locked_until(self, locked_until: Option<DateTime<Utc>>) -> Self {
+        let permanent = locked_until == Some(DateTime::<Utc>::MAX_UTC);
+        AuditEvent {
+            unlock_time: locked_until.filter(|_| !permanent),
+            permanent,
+            ..self
+        }
+    }
+
+    /// Logs the event to the audit log and to the journal, as configured with `audit_log` and
+    /// `journald`.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: The configuration.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first `io::Error` of writing the audit log or sending the journal entry.
+    pub fn log(&self, config: &Config) -> io::Result<()> {
+        let written = self.write(config);
+        if config.journald {
+            Journal::connect().and_then(|journal| self.send(&journal))?;
+        }
+        written
+    }
+
+    /// Sends the event to the journal with `AUTHRAMP_*` fields. Fields that do not apply are
+    /// left out.
+    ///
+    /// # Arguments
+    ///
+    /// * `journal`: The connection to the journal.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `io::Error` if the entry cannot be sent.
+    pub fn send(&self, journal: &Journal) -> io::Result<()> {
+        let event = self.event.to_string();
+        let uid = self.uid.map(|uid| uid.to_string());
+        let count = self.count.map(|count| count.to_string());
+        let unlock_time = self
+            .unlock_time
+            .map(|time| time.to_rfc3339_opts(SecondsFormat::Secs, true));
+
+        let fields: Vec<(&str, &str)> = [
+            ("MESSAGE_ID", Some(self.event.message_id())),
+            ("AUTHRAMP_EVENT", Some(event.as_str())),
+            ("AUTHRAMP_TALLY", self.tally.as_deref()),
+            ("AUTHRAMP_USER", self.user.as_deref()),
+            ("AUTHRAMP_UID", uid.as_deref()),
+            ("AUTHRAMP_SERVICE", self.service.as_deref()),
+            ("AUTHRAMP_RHOST", self.rhost.as_deref()),
+            ("AUTHRAMP_TTY", self.tty.as_deref()),
+            ("AUTHRAMP_FAILURES", count.as_deref()),
+            ("AUTHRAMP_UNLOCK_AT", unlock_time.as_deref()),
+            (
+                "AUTHRAMP_PERMANENT",
+                Some(if self.permanent { "true" } else { "false" }),
+            ),
+            ("AUTHRAMP_REASON", self.reason.as_deref()),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| value.map(|value| (name, value)))
+        .collect();
+
+        journal.send(self.event.level(), &self.message(), &fields)
+    }
+
+    /// Describes the event in a sentence, used as `MESSAGE` of the journal entry.
+    fn message(&self) -> String {
+        let account = match (&self.user, &self.tally) {
+            (Some(user), _) => format!("Account {user:?}"),
+            (None, Some(tally)) => format!("Tally {tally:?}"),
+            (None, None) => "Account".to_string(),
+        };
+        let until = match self.unlock_time {
+            Some(time) => format!("until {time}"),
+            None if self.permanent => "until it is reset".to_string(),
+            None => "for the free tries".to_string(),
+        };
+        let count = self.count.unwrap_or_default();
+
+        match self.event {
+            AuditEventKind::Failure => {
+                format!("{account} failed to authenticate ({count} failures).")
+            }
+            AuditEventKind::Lockout => format!("{account} is locked {until} ({count} failures)."),
+            AuditEventKind::Bounce => {
+                format!("{account} is getting bounced, it is locked {until}.")
+            }
+            AuditEventKind::Unlock => format!("{account} is unlocked ({count} failures cleared)."),
+            AuditEventKind::Reset => {
+                format!("{account} is reset by an administrator ({count} failures cleared).")
+            }
+            AuditEventKind::Lock => format!("{account} is locked by an administrator {until}."),
+        }
+    }
+
+    /// Appends the event to the audit log, if `audit_log` is configured. The file and its
+    /// directory are created readable by root only. Each event is written with a single append,
+    /// so the lines of concurrent attempts do not interleave.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: The configuration.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `io::Error` if the audit log cannot be written.
+    pub fn write(&self, config: &Config) -> io::Result<()> {
+        let Some(path) = &config.audit_log else {
+            return Ok(());
+        };
+
+        let mut line = serde_json::to_string(self)?;
+        line.push('\n');
+
+        if let Some(dir) = path.parent() {
+            DirBuilder::new().recursive(true).mode(0o750).create(dir)?;
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o640)
+            .open(path)?
+            .write_all(line.as_bytes())
+    }
+}
+
+// Unit Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_from_tally() {
+        let event = AuditEvent::from_tally(AuditEventKind::Reset, "sshd:nobody@192.0.2.1");
+        assert_eq!(event.tally.as_deref(), Some("sshd:nobody@192.0.2.1"));
+        assert_eq!(event.user.as_deref(), Some("nobody"));
+        assert_eq!(event.rhost.as_deref(), Some("192.0.2.1"));
+
+        let event = AuditEvent::from_tally(AuditEventKind::Reset, "@192.0.2.1");
+        assert_eq!(event.user, None);
+        assert_eq!(event.uid, None);
+    }
+
+    #[test]
+    fn test_locked_until() {
+        let event = AuditEvent::from_tally(AuditEventKind::Lockout, "alice");
+        let now = Utc::now();
+
+        let locked = event.clone().locked_until(Some(now));
+        assert_eq!(locked.unlock_time, Some(now));
+        assert!(!locked.permanent);
+
+        let locked = event.locked_until(Some(DateTime::<Utc>::MAX_UTC));
+        assert_eq!(locked.unlock_time, None);
+        assert!(locked.permanent);
+    }
+
+    #[test]
+    fn test_send() {
+        let temp_dir = TempDir::new("test_audit_send").unwrap();
+        let path = temp_dir.path().join("socket");
+        let server = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+        let journal = Journal::connect_to(&path).unwrap();
+
+        let unlock_time = DateTime::parse_from_rfc3339("2024-02-04T00:43:12Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let event = AuditEvent {
+            count: Some(7),
+            ..AuditEvent::from_tally(AuditEventKind::Lockout, "alice")
+        }
+        .locked_until(Some(unlock_time));
+        event.send(&journal).unwrap();
+
+        let mut buf = [0; 1024];
+        let len = server.recv(&mut buf).unwrap();
+        let entry = String::from_utf8_lossy(&buf[..len]).to_string();
+        let fields: Vec<&str> = entry.lines().collect();
+
+        assert!(fields.contains(&"PRIORITY=5"));
+        assert!(fields.contains(
+            &"MESSAGE=Account \"alice\" is locked until 2024-02-04 00:43:12 UTC (7 failures)."
+        ));
+        assert!(fields.contains(&"MESSAGE_ID=966f4af4174e47f899575b95a9ea3bd7"));
+        assert!(fields.contains(&"AUTHRAMP_EVENT=lockout"));
+        assert!(fields.contains(&"AUTHRAMP_USER=alice"));
+        assert!(fields.contains(&"AUTHRAMP_FAILURES=7"));
+        assert!(fields.contains(&"AUTHRAMP_UNLOCK_AT=2024-02-04T00:43:12Z"));
+        assert!(fields.contains(&"AUTHRAMP_PERMANENT=false"));
+        // fields that do not apply are left out
+        assert!(!entry.contains("AUTHRAMP_RHOST"));
+        assert!(!entry.contains("AUTHRAMP_REASON"));
+    }
+
+    #[test]
+    fn test_write() {
+        let temp_dir = TempDir::new("test_audit_write").unwrap();
+        let path = temp_dir.path().join("authramp").join("audit.jsonl");
+        let event = AuditEvent {
+            count: Some(7),
+            ..AuditEvent::from_tally(AuditEventKind::Failure, "alice")
+        };
+
+        // nothing is written without audit_log
+        event.write(&Config::default()).unwrap();
+        assert!(!path.exists());
+
+        let config = Config {
+            audit_log: Some(path.clone()),
+            ..Config::default()
+        };
+        event.write(&config).unwrap();
+        event.write(&config).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+
+        let json: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(json["event"], "failure");
+        assert_eq!(json["tally"], "alice");
+        assert_eq!(json["count"], 7);
+        assert_eq!(json["unlock_time"], serde_json::Value::Null);
+    }
+}
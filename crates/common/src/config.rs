@@ -9,6 +9,10 @@
 //! the path to the configuration file. The `Config` struct allows accessing various configuration
 //! settings related to `AuthRamp` behavior.
 //!
+//! Fragments in the drop-in directory next to the configuration file, e.g.
+//! `/etc/security/authramp.conf.d/*.conf`, are merged on top of it in lexical order. Tables are
+//! merged key by key, so a fragment can change single values or add `[User.<name>]` sections.
+//!
 //! # Structs
 //!
 //! - [`Config`](struct.Config.html): Represents the configuration settings for `AuthRamp`.
@@ -31,13 +35,93 @@
 //! You should have received a copy of the GNU General Public License
 //! along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::{fs, path::PathBuf};
+use std::{
+    collections::HashMap,
+    fs, io, iter,
+    path::{Path, PathBuf},
+};
+
+use pam::{LogLevel, PamHandle, PamResultCode};
+use uzers::{os::unix::GroupExt, User};
+
+use crate::curve::DelayCurve;
+use crate::locale::DEFAULT_LOCALE_DIR;
+use crate::overrides::{most_specific_group, GroupMembership, Overrides};
+use crate::track::{IpNetwork, Track};
+use crate::validate::{check_parsed, check_value, syntax_error, ConfigIssue};
+
+/// Path of the configuration file used if no other path is given.
+pub const DEFAULT_CONFIG_FILE_PATH: &str = "/etc/security/authramp.conf";
 
-use pam::PamHandle;
+/// Keys of the `[Configuration]` table. Module arguments can set any of them.
+pub const CONFIG_KEYS: &[&str] = &[
+    "tally_dir",
+    "persistent",
+    "free_tries",
+    "base_delay_seconds",
+    "ramp_multiplier",
+    "even_deny_root",
+    "countdown",
+    "delay_curve",
+    "exponential_base",
+    "exponential_cap",
+    "steps",
+    "max_delay_seconds",
+    "permanent_lock_after",
+    "fail_interval",
+    "max_history",
+    "on_corrupt_tally",
+    "storage",
+    "track",
+    "rhost_ipv4_prefix",
+    "rhost_ipv6_prefix",
+    "trusted_networks",
+    "trusted_ttys",
+    "tally_namespace",
+    "audit_log",
+    "journald",
+    "log_level",
+    "debug",
+    "quiet",
+    "silent",
+    "lock_message",
+    "countdown_message",
+    "permanent_lock_message",
+    "locale_dir",
+];
+
+/// Upper bound for settings in seconds, about 68 years. Keeps them representable as a
+/// `chrono::Duration` and addable to a timestamp.
+pub const MAX_SECONDS: i64 = i32::MAX as i64;
+
+/// Tally directory used with `persistent = true`. Unlike the default tally directory on tmpfs,
+/// it survives reboots.
+const PERSISTENT_TALLY_DIR: &str = "/var/lib/authramp";
+
+/// The `CorruptTallyPolicy` enum defines how a tally file that cannot be loaded is treated.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum CorruptTallyPolicy {
+    /// Abort the authentication with a system error.
+    #[default]
+    FailClosed,
+    /// Ignore the corrupt tally and start over with no failures.
+    FailOpen,
+    /// Treat the account as locked for `max_delay_seconds` after the file was last modified.
+    Lock,
+}
 
-const DEFAULT_CONFIG_FILE_PATH: &str = "/etc/security/authramp.conf";
+/// The `StorageBackend` enum defines where tallies are stored.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum StorageBackend {
+    /// One tally file per user in `tally_dir`.
+    #[default]
+    File,
+    /// A single `SQLite` database in `tally_dir`.
+    Sqlite,
+}
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Config {
     // Directory where tally information is stored.
     pub tally_dir: PathBuf,
@@ -51,6 +135,58 @@ pub struct Config {
     pub even_deny_root: bool,
     // Count down lockout loop,
     pub countdown: bool,
+    // Function used to calculate the delay from the number of failures.
+    pub delay_curve: DelayCurve,
+    // Maximum delay in seconds an account can be locked by the ramp.
+    pub max_delay_seconds: i64,
+    // Number of failures after which the account stays locked until it is reset.
+    pub permanent_lock_after: Option<i32>,
+    // Seconds after which a failure is forgotten.
+    pub fail_interval: Option<i64>,
+    // Number of failure records kept in the tally history.
+    pub max_history: usize,
+    // How to treat tally files that cannot be loaded.
+    pub on_corrupt_tally: CorruptTallyPolicy,
+    // Backend the tallies are stored in.
+    pub storage: StorageBackend,
+    // Keys the failures are counted by.
+    pub track: Vec<Track>,
+    // Prefix length IPv4 remote hosts are aggregated to.
+    pub rhost_ipv4_prefix: u8,
+    // Prefix length IPv6 remote hosts are aggregated to.
+    pub rhost_ipv6_prefix: u8,
+    // Remote host networks exempt from the ramp.
+    pub trusted_networks: Vec<IpNetwork>,
+    // Terminals exempt from the ramp, a trailing '*' matches any suffix.
+    pub trusted_ttys: Vec<String>,
+    // Most verbose level logged to syslog.
+    pub log_level: LogLevel,
+    // Log everything including the effective settings of each attempt, overrides log_level.
+    pub debug: bool,
+    // Only log warnings and errors, overrides log_level.
+    pub quiet: bool,
+    // Don't send messages to the user.
+    pub silent: bool,
+    // Message to users locked until a point in time.
+    pub lock_message: String,
+    // Message to users waiting for the unlock with countdown.
+    pub countdown_message: String,
+    // Message to users locked until the tally is reset.
+    pub permanent_lock_message: String,
+    // Directory of the gettext PO catalogs translating the messages.
+    pub locale_dir: PathBuf,
+    // Namespace the tallies are stored in, tallies are shared by all services if not set.
+    pub tally_namespace: Option<String>,
+    // File the structured audit events are appended to.
+    pub audit_log: Option<PathBuf>,
+    // Send the audit events to systemd-journald.
+    pub journald: bool,
+    // Overrides of the [Service.<name>] sections by PAM service name.
+    pub services: HashMap<String, Overrides>,
+    // Overrides of the [User.<name>] sections by user name.
+    pub users: HashMap<String, Overrides>,
+    // Overrides of the [Group.<name>] sections by group name.
+    pub groups: HashMap<String, Overrides>,
 }
 
 impl Default for Config {
@@ -63,6 +199,33 @@ impl Default for Config {
             ramp_multiplier: 50,
             even_deny_root: false,
             countdown: false,
+            delay_curve: DelayCurve::default(),
+            max_delay_seconds: 86400,
+            permanent_lock_after: None,
+            fail_interval: None,
+            max_history: 10,
+            on_corrupt_tally: CorruptTallyPolicy::default(),
+            storage: StorageBackend::default(),
+            track: vec![Track::User],
+            rhost_ipv4_prefix: 32,
+            rhost_ipv6_prefix: 64,
+            trusted_networks: Vec::new(),
+            trusted_ttys: Vec::new(),
+            log_level: LogLevel::Notice,
+            debug: false,
+            quiet: false,
+            silent: false,
+            lock_message: "Account locked until {until}.".to_string(),
+            countdown_message: "Account locked! Unlocking in {remaining}.".to_string(),
+            permanent_lock_message:
+                "Account locked! Contact your system administrator to unlock it.".to_string(),
+            locale_dir: PathBuf::from(DEFAULT_LOCALE_DIR),
+            tally_namespace: None,
+            audit_log: None,
+            journald: false,
+            services: HashMap::new(),
+            users: HashMap::new(),
+            groups: HashMap::new(),
         }
     }
 }
@@ -86,20 +249,290 @@ impl Config {
     /// if the file is not present or cannot be loaded.
     #[must_use]
     pub fn load_file(path: Option<&str>, pam_h: Option<&mut PamHandle>) -> Config {
-        // Read TOML file using the toml crate
-        let content =
-            fs::read_to_string(PathBuf::from(path.unwrap_or(DEFAULT_CONFIG_FILE_PATH))).ok();
+        Self::load(path, &toml::value::Table::new(), pam_h)
+    }
+
+    /// Loads configuration from a TOML file and merges values given as module arguments on top
+    /// of the `[Configuration]` table, see `load_file`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: An optional string slice specifying the path to the TOML file. If not provided,
+    ///   the default configuration file path is used.
+    /// * `args`: Configuration values that replace the values of the file.
+    /// * `pam_h`: An optional mutable reference to a `PamHandle`. If provided, logs each invalid
+    ///   value once and a message indicating the successful loading of the configuration.
+    ///
+    /// # Returns
+    ///
+    /// A `Config` instance populated with values from the arguments and the configuration file,
+    /// or default values if neither sets them.
+    #[must_use]
+    pub fn load(
+        path: Option<&str>,
+        args: &toml::value::Table,
+        pam_h: Option<&mut PamHandle>,
+    ) -> Config {
+        let path = PathBuf::from(path.unwrap_or(DEFAULT_CONFIG_FILE_PATH));
+        // only the module reports problems, the cli has authramp config check
+        let check = pam_h.is_some();
+        let mut messages = Vec::new();
+
+        // Parse the file and merge the drop-in fragments in lexical order
+        let mut toml_table = toml::value::Table::new();
+        for file in iter::once(path.clone()).chain(drop_in_files(&path)) {
+            match read_toml_table(&file) {
+                Ok(Some((content, fragment))) => {
+                    if check {
+                        messages.extend(
+                            check_parsed(&file, &content, &fragment)
+                                .iter()
+                                .map(|issue| {
+                                    format!("Invalid configuration, ignoring it: {issue}")
+                                }),
+                        );
+                    }
+                    merge_tables(&mut toml_table, fragment);
+                }
+                Ok(None) => (),
+                Err(issue) => {
+                    messages.push(format!("Invalid configuration file, ignoring it: {issue}"));
+                }
+            }
+        }
+
+        // Extract the "Config" section from the TOML table and merge the arguments
+        let mut toml_config = toml_table
+            .get("Configuration")
+            .and_then(toml::Value::as_table)
+            .cloned()
+            .unwrap_or_default();
+        toml_config.extend(args.clone());
+        let toml_config = toml::Value::Table(toml_config);
+
+        if check {
+            for (key, value) in args {
+                if let Err(message) = check_value(key, value, &toml_config) {
+                    messages.push(format!("Invalid module argument, ignoring it: {message}"));
+                }
+            }
+        }
+
+        let config = Self::map_config(&toml_config, &toml_table);
+
+        // when there is no pam_h, there don't need to be logs
+        if let Some(pam_h) = pam_h {
+            for message in messages {
+                let _ = config.log(pam_h, LogLevel::Warning, message);
+            }
+            let _ = config.log(
+                pam_h,
+                LogLevel::Debug,
+                format!("Successfully loaded config: {config:?}"),
+            );
+        }
+        config
+    }
+
+    /// Returns the most verbose level that is logged: `Debug` with `debug`, `Warning` with
+    /// `quiet` and `log_level` otherwise.
+    #[must_use]
+    pub fn log_level(&self) -> LogLevel {
+        if self.debug {
+            LogLevel::Debug
+        } else if self.quiet {
+            LogLevel::Warning
+        } else {
+            self.log_level
+        }
+    }
+
+    /// Logs a message to syslog if its level is logged, see `log_level`.
+    ///
+    /// # Arguments
+    ///
+    /// * `pam_h`: The `PamHandle` the message is logged with.
+    /// * `level`: The level of the message.
+    /// * `message`: The message.
+    ///
+    /// # Errors
+    ///
+    /// Returns the `PamResultCode` of `pam_syslog` if logging fails.
+    pub fn log(
+        &self,
+        pam_h: &PamHandle,
+        level: LogLevel,
+        message: String,
+    ) -> Result<(), PamResultCode> {
+        if level <= self.log_level() {
+            pam_h.log(level, message)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Writes the configuration as configuration file, see `load_file`. Loading the result gives
+    /// the same configuration.
+    ///
+    /// # Returns
+    ///
+    /// The TOML document with the `[Configuration]` table and the override sections.
+    #[must_use]
+    #[allow(clippy::too_many_lines)]
+    pub fn to_toml(&self) -> toml::value::Table {
+        let mut toml_config = toml::value::Table::new();
+        let mut insert = |key: &str, value: toml::Value| {
+            toml_config.insert(key.to_string(), value);
+        };
+
+        insert(
+            "tally_dir",
+            self.tally_dir.to_string_lossy().to_string().into(),
+        );
+        insert("free_tries", self.free_tries.into());
+        insert("base_delay_seconds", self.base_delay_seconds.into());
+        insert("ramp_multiplier", self.ramp_multiplier.into());
+        insert("even_deny_root", self.even_deny_root.into());
+        insert("countdown", self.countdown.into());
+        insert("max_delay_seconds", self.max_delay_seconds.into());
+        if let Some(permanent_lock_after) = self.permanent_lock_after {
+            insert("permanent_lock_after", permanent_lock_after.into());
+        }
+        if let Some(fail_interval) = self.fail_interval {
+            insert("fail_interval", fail_interval.into());
+        }
+        insert(
+            "max_history",
+            i64::try_from(self.max_history).unwrap_or(i64::MAX).into(),
+        );
+        insert(
+            "on_corrupt_tally",
+            match self.on_corrupt_tally {
+                CorruptTallyPolicy::FailClosed => "fail_closed",
+                CorruptTallyPolicy::FailOpen => "fail_open",
+                CorruptTallyPolicy::Lock => "lock",
+            }
+            .into(),
+        );
+        insert(
+            "storage",
+            match self.storage {
+                StorageBackend::File => "file",
+                StorageBackend::Sqlite => "sqlite",
+            }
+            .into(),
+        );
+        insert(
+            "track",
+            self.track
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .into(),
+        );
+        insert(
+            "rhost_ipv4_prefix",
+            i64::from(self.rhost_ipv4_prefix).into(),
+        );
+        insert(
+            "rhost_ipv6_prefix",
+            i64::from(self.rhost_ipv6_prefix).into(),
+        );
+        insert(
+            "trusted_networks",
+            self.trusted_networks
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .into(),
+        );
+        insert("trusted_ttys", self.trusted_ttys.clone().into());
+        if let Some(tally_namespace) = &self.tally_namespace {
+            insert("tally_namespace", tally_namespace.clone().into());
+        }
+        if let Some(audit_log) = &self.audit_log {
+            insert("audit_log", audit_log.to_string_lossy().to_string().into());
+        }
+        insert("journald", self.journald.into());
+        insert(
+            "log_level",
+            match self.log_level {
+                LogLevel::Debug => "debug",
+                LogLevel::Info => "info",
+                LogLevel::Notice => "notice",
+                LogLevel::Warning => "warning",
+                _ => "error",
+            }
+            .into(),
+        );
+        insert("debug", self.debug.into());
+        insert("quiet", self.quiet.into());
+        insert("silent", self.silent.into());
+        insert("lock_message", self.lock_message.clone().into());
+        insert("countdown_message", self.countdown_message.clone().into());
+        insert(
+            "permanent_lock_message",
+            self.permanent_lock_message.clone().into(),
+        );
+        insert(
+            "locale_dir",
+            self.locale_dir.to_string_lossy().to_string().into(),
+        );
+        self.delay_curve.to_toml(&mut toml_config);
+
+        let mut toml_table = toml::value::Table::new();
+        toml_table.insert("Configuration".to_string(), toml_config.into());
+        for (name, sections) in [
+            ("Service", &self.services),
+            ("User", &self.users),
+            ("Group", &self.groups),
+        ] {
+            if !sections.is_empty() {
+                let sections: toml::value::Table = sections
+                    .iter()
+                    .map(|(name, overrides)| (name.clone(), overrides.to_toml().into()))
+                    .collect();
+                toml_table.insert(name.to_string(), sections.into());
+            }
+        }
+        toml_table
+    }
+
+    /// Applies the `[Service.<name>]`, `[Group.<name>]` and `[User.<name>]` overrides to the
+    /// configuration of an authentication attempt. The override of the service is applied first,
+    /// followed by the override of the most specific group of the user. The override of the user
+    /// wins over both.
+    ///
+    /// # Arguments
+    ///
+    /// * `user`: The user the configuration applies to
+    /// * `service`: The `PAM_SERVICE` item, if set
+    pub fn apply_overrides(&mut self, user: &User, service: Option<&str>) {
+        let name = user.name().to_string_lossy();
+
+        if let Some(overrides) = service.and_then(|service| self.services.get(service).cloned()) {
+            overrides.apply(self);
+        }
 
-        // Parse TOML content into a TomlTable
-        let toml_table: Option<toml::value::Table> =
-            content.and_then(|c| toml::de::from_str(&c).ok());
+        if !self.groups.is_empty() {
+            let memberships: Vec<GroupMembership> =
+                uzers::get_user_groups(user.name(), user.primary_group_id())
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|group| GroupMembership {
+                        name: group.name().to_string_lossy().to_string(),
+                        primary: group.gid() == user.primary_group_id(),
+                        members: group.members().len(),
+                    })
+                    .collect();
 
-        // Extract the "Config" section from the TOML table
-        let toml_config = toml_table.and_then(|t| t.get("Configuration").cloned());
+            if let Some(overrides) = most_specific_group(&self.groups, &memberships).cloned() {
+                overrides.apply(self);
+            }
+        }
 
-        match toml_config {
-            Some(toml_config) => Self::map_config(&toml_config, pam_h),
-            None => Config::default(),
+        if let Some(overrides) = self.users.get(name.as_ref()).cloned() {
+            overrides.apply(self);
         }
     }
 
@@ -109,34 +542,60 @@ impl Config {
     ///
     /// * `toml_config`: A reference to a `toml::Value` representing the configuration
     ///   loaded from a TOML file.
-    /// * `pam_h`: An optional mutable reference to a `PamHandle`. If provided, logs
-    ///   a message indicating the successful loading of the configuration.
+    /// * `toml_table`: The whole TOML file, holding the `User` and `Group` sections.
     ///
     /// # Returns
     ///
     /// A `Config` instance populated with values from the TOML configuration, or
     /// default values if any values are missing or cannot be parsed.
-    fn map_config(toml_config: &toml::Value, pam_h: Option<&mut PamHandle>) -> Config {
-        let config = Config {
+    #[allow(clippy::too_many_lines)]
+    fn map_config(toml_config: &toml::Value, toml_table: &toml::value::Table) -> Config {
+        // an invalid curve falls back to the default curve
+        let delay_curve = DelayCurve::from_toml(toml_config);
+        // an invalid track setting falls back to tracking users
+        let track = Track::from_toml(toml_config);
+        // an invalid network list trusts no networks
+        let trusted_networks = IpNetwork::list_from_toml(toml_config, "trusted_networks");
+        // invalid override sections are skipped
+        let (services, _) = Overrides::sections_from_toml(toml_table.get("Service"));
+        let (users, _) = Overrides::sections_from_toml(toml_table.get("User"));
+        let (groups, _) = Overrides::sections_from_toml(toml_table.get("Group"));
+
+        Config {
             tally_dir: toml_config
                 .get("tally_dir")
                 .and_then(|val| val.as_str().map(PathBuf::from))
-                .unwrap_or_else(|| Config::default().tally_dir),
+                .unwrap_or_else(|| {
+                    // persistent only changes the default, an explicit tally_dir wins
+                    if toml_config
+                        .get("persistent")
+                        .and_then(toml::Value::as_bool)
+                        .unwrap_or_default()
+                    {
+                        PathBuf::from(PERSISTENT_TALLY_DIR)
+                    } else {
+                        Config::default().tally_dir
+                    }
+                }),
 
             free_tries: toml_config
                 .get("free_tries")
                 .and_then(toml::Value::as_integer)
-                .map_or_else(|| Config::default().free_tries, |val| val as i32),
+                .and_then(|val| i32::try_from(val).ok())
+                .filter(|val| *val > 0)
+                .unwrap_or_else(|| Config::default().free_tries),
 
             base_delay_seconds: toml_config
                 .get("base_delay_seconds")
                 .and_then(toml::Value::as_integer)
-                .map_or_else(|| Config::default().base_delay_seconds, |val| val as i32),
+                .and_then(|val| i32::try_from(val).ok())
+                .filter(|val| *val >= 0)
+                .unwrap_or_else(|| Config::default().base_delay_seconds),
 
             ramp_multiplier: toml_config
                 .get("ramp_multiplier")
-                .and_then(toml::Value::as_float)
-                .map_or_else(|| Config::default().ramp_multiplier, |val| val as i32),
+                .and_then(ramp_multiplier_from_toml)
+                .unwrap_or_else(|| Config::default().ramp_multiplier),
 
             even_deny_root: toml_config
                 .get("even_deny_root")
@@ -147,15 +606,258 @@ impl Config {
                 .get("countdown")
                 .and_then(toml::Value::as_bool)
                 .unwrap_or_else(|| Config::default().countdown),
-        };
-        // when there is no pam_h, there don't need to be logs
-        if let Some(pam_h) = pam_h {
-            let _ = pam_h.log(
-                pam::LogLevel::Info,
-                format!("Successfully loaded config: {config:?}"),
-            );
+
+            delay_curve: delay_curve.unwrap_or_else(|_| Config::default().delay_curve),
+
+            max_delay_seconds: toml_config
+                .get("max_delay_seconds")
+                .and_then(toml::Value::as_integer)
+                .filter(|val| (1..=MAX_SECONDS).contains(val))
+                .unwrap_or_else(|| Config::default().max_delay_seconds),
+
+            permanent_lock_after: toml_config
+                .get("permanent_lock_after")
+                .and_then(toml::Value::as_integer)
+                .and_then(|val| i32::try_from(val).ok())
+                .filter(|val| *val > 0),
+
+            fail_interval: toml_config
+                .get("fail_interval")
+                .and_then(toml::Value::as_integer)
+                .filter(|val| (1..=MAX_SECONDS).contains(val)),
+
+            max_history: toml_config
+                .get("max_history")
+                .and_then(toml::Value::as_integer)
+                .and_then(|val| usize::try_from(val).ok())
+                .unwrap_or_else(|| Config::default().max_history),
+
+            on_corrupt_tally: toml_config
+                .get("on_corrupt_tally")
+                .and_then(toml::Value::as_str)
+                .and_then(|val| match val {
+                    "fail_closed" => Some(CorruptTallyPolicy::FailClosed),
+                    "fail_open" => Some(CorruptTallyPolicy::FailOpen),
+                    "lock" => Some(CorruptTallyPolicy::Lock),
+                    _ => None,
+                })
+                .unwrap_or_else(|| Config::default().on_corrupt_tally),
+
+            storage: toml_config
+                .get("storage")
+                .and_then(toml::Value::as_str)
+                .and_then(|val| match val {
+                    "file" => Some(StorageBackend::File),
+                    "sqlite" => Some(StorageBackend::Sqlite),
+                    _ => None,
+                })
+                .unwrap_or_else(|| Config::default().storage),
+
+            track: track.unwrap_or_else(|_| Config::default().track),
+
+            rhost_ipv4_prefix: toml_config
+                .get("rhost_ipv4_prefix")
+                .and_then(toml::Value::as_integer)
+                .and_then(|val| u8::try_from(val).ok())
+                .filter(|val| *val <= 32)
+                .unwrap_or_else(|| Config::default().rhost_ipv4_prefix),
+
+            rhost_ipv6_prefix: toml_config
+                .get("rhost_ipv6_prefix")
+                .and_then(toml::Value::as_integer)
+                .and_then(|val| u8::try_from(val).ok())
+                .filter(|val| *val <= 128)
+                .unwrap_or_else(|| Config::default().rhost_ipv6_prefix),
+
+            trusted_networks: trusted_networks
+                .unwrap_or_else(|_| Config::default().trusted_networks),
+
+            trusted_ttys: toml_config
+                .get("trusted_ttys")
+                .and_then(toml::Value::as_array)
+                .map_or_else(
+                    || Config::default().trusted_ttys,
+                    |ttys| {
+                        ttys.iter()
+                            .filter_map(toml::Value::as_str)
+                            .map(|tty| tty.strip_prefix("/dev/").unwrap_or(tty).to_string())
+                            .collect()
+                    },
+                ),
+
+            debug: toml_config
+                .get("debug")
+                .and_then(toml::Value::as_bool)
+                .unwrap_or_else(|| Config::default().debug),
+
+            log_level: toml_config
+                .get("log_level")
+                .and_then(toml::Value::as_str)
+                .and_then(|val| match val {
+                    "error" => Some(LogLevel::Error),
+                    "warning" => Some(LogLevel::Warning),
+                    "notice" => Some(LogLevel::Notice),
+                    "info" => Some(LogLevel::Info),
+                    "debug" => Some(LogLevel::Debug),
+                    _ => None,
+                })
+                .unwrap_or_else(|| Config::default().log_level),
+
+            quiet: toml_config
+                .get("quiet")
+                .and_then(toml::Value::as_bool)
+                .unwrap_or_else(|| Config::default().quiet),
+
+            silent: toml_config
+                .get("silent")
+                .and_then(toml::Value::as_bool)
+                .unwrap_or_else(|| Config::default().silent),
+
+            lock_message: toml_config
+                .get("lock_message")
+                .and_then(toml::Value::as_str)
+                .map_or_else(|| Config::default().lock_message, str::to_string),
+
+            countdown_message: toml_config
+                .get("countdown_message")
+                .and_then(toml::Value::as_str)
+                .map_or_else(|| Config::default().countdown_message, str::to_string),
+
+            permanent_lock_message: toml_config
+                .get("permanent_lock_message")
+                .and_then(toml::Value::as_str)
+                .map_or_else(|| Config::default().permanent_lock_message, str::to_string),
+
+            locale_dir: toml_config
+                .get("locale_dir")
+                .and_then(toml::Value::as_str)
+                .filter(|val| !val.is_empty())
+                .map_or_else(|| Config::default().locale_dir, PathBuf::from),
+
+            tally_namespace: toml_config
+                .get("tally_namespace")
+                .and_then(toml::Value::as_str)
+                .filter(|val| !val.is_empty())
+                .map(str::to_string),
+
+            audit_log: toml_config
+                .get("audit_log")
+                .and_then(toml::Value::as_str)
+                .filter(|val| !val.is_empty())
+                .map(PathBuf::from),
+
+            journald: toml_config
+                .get("journald")
+                .and_then(toml::Value::as_bool)
+                .unwrap_or_else(|| Config::default().journald),
+
+            services,
+
+            users,
+
+            groups,
+        }
+    }
+}
+
+/// Reads and parses a TOML file.
+///
+/// # Arguments
+///
+/// * `path`: Path of the file
+///
+/// # Returns
+///
+/// The content of the file and its TOML table, or `None` if the file does not exist.
+///
+/// # Errors
+///
+/// Returns a `ConfigIssue` if the file cannot be read or parsed, with the line of the syntax
+/// error.
+fn read_toml_table(path: &Path) -> Result<Option<(String, toml::value::Table)>, ConfigIssue> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(ConfigIssue {
+                path: path.to_path_buf(),
+                line: None,
+                message: format!("cannot be read: {e}"),
+            })
+        }
+    };
+
+    match toml::de::from_str(&content) {
+        Ok(toml_table) => Ok(Some((content, toml_table))),
+        Err(e) => Err(syntax_error(path, &content, &e)),
+    }
+}
+
+/// Reads a `ramp_multiplier`, which may be written as an integer or as a float without a
+/// fractional part.
+///
+/// # Arguments
+///
+/// * `value`: The configured value
+///
+/// # Returns
+///
+/// The multiplier, or `None` if it is not a whole number between 0 and `i32::MAX`.
+pub(crate) fn ramp_multiplier_from_toml(value: &toml::Value) -> Option<i32> {
+    let multiplier = match value {
+        toml::Value::Integer(val) => *val,
+        toml::Value::Float(val) if val.fract() == 0.0 && val.abs() <= f64::from(i32::MAX) => {
+            *val as i64
+        }
+        _ => return None,
+    };
+    i32::try_from(multiplier).ok().filter(|val| *val >= 0)
+}
+
+/// Lists the drop-in fragments of a configuration file, which are the `*.conf` files in the
+/// directory named after the file with a `.d` suffix.
+///
+/// # Arguments
+///
+/// * `path`: Path of the configuration file
+///
+/// # Returns
+///
+/// The paths of the fragments in lexical order.
+pub(crate) fn drop_in_files(path: &Path) -> Vec<PathBuf> {
+    let mut drop_in_dir = path.as_os_str().to_os_string();
+    drop_in_dir.push(".d");
+
+    let Ok(entries) = fs::read_dir(drop_in_dir) else {
+        return Vec::new();
+    };
+
+    let mut fragments: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "conf"))
+        .collect();
+    fragments.sort();
+    fragments
+}
+
+/// Merges a TOML table into another. Tables present in both are merged recursively, other values
+/// of the overlay replace the values of the base.
+///
+/// # Arguments
+///
+/// * `base`: The table to merge into
+/// * `overlay`: The table whose values win
+fn merge_tables(base: &mut toml::value::Table, overlay: toml::value::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => {
+                merge_tables(base, overlay);
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
         }
-        config
     }
 }
 
@@ -175,6 +877,30 @@ mod tests {
         assert_eq!(default_config.ramp_multiplier, 50);
         assert!(!default_config.countdown);
         assert!(!default_config.even_deny_root);
+        assert_eq!(default_config.delay_curve, DelayCurve::NLogN);
+        assert_eq!(default_config.max_delay_seconds, 86400);
+        assert!(default_config.permanent_lock_after.is_none());
+        assert!(default_config.fail_interval.is_none());
+        assert_eq!(default_config.max_history, 10);
+        assert_eq!(
+            default_config.on_corrupt_tally,
+            CorruptTallyPolicy::FailClosed
+        );
+        assert_eq!(default_config.storage, StorageBackend::File);
+        assert_eq!(default_config.track, vec![Track::User]);
+        assert_eq!(default_config.rhost_ipv4_prefix, 32);
+        assert_eq!(default_config.rhost_ipv6_prefix, 64);
+        assert!(default_config.trusted_networks.is_empty());
+        assert!(default_config.trusted_ttys.is_empty());
+        assert!(default_config.audit_log.is_none());
+        assert!(!default_config.journald);
+        assert_eq!(default_config.log_level, LogLevel::Notice);
+        assert!(!default_config.quiet);
+        assert_eq!(default_config.lock_message, "Account locked until {until}.");
+        assert_eq!(
+            default_config.locale_dir,
+            PathBuf::from("/usr/share/authramp/locale")
+        );
     }
 
     #[test]
@@ -191,6 +917,22 @@ mod tests {
         ramp_multiplier = 20.0
         even_deny_root = true
         countdown = true
+        max_delay_seconds = 3600
+        permanent_lock_after = 20
+        fail_interval = 900
+        max_history = 0
+        on_corrupt_tally = "lock"
+        storage = "sqlite"
+        track = ["user", "rhost"]
+        rhost_ipv4_prefix = 24
+        rhost_ipv6_prefix = 200
+        trusted_networks = ["10.0.0.0/8", "::1/128"]
+        trusted_ttys = ["/dev/tty1", "ttyS*"]
+        audit_log = "/var/log/authramp/audit.jsonl"
+        journald = true
+        log_level = "info"
+        lock_message = "Locked for {remaining}, {failures} failures"
+        locale_dir = "/etc/security/authramp/locale"
     "#;
         std::fs::write(&conf_file_path, toml_content).unwrap();
 
@@ -204,5 +946,342 @@ mod tests {
         assert_eq!(config.ramp_multiplier, 20);
         assert!(config.even_deny_root);
         assert!(config.countdown);
+        assert_eq!(config.max_delay_seconds, 3600);
+        assert_eq!(config.permanent_lock_after, Some(20));
+        assert_eq!(config.fail_interval, Some(900));
+        assert_eq!(config.max_history, 0);
+        assert_eq!(config.on_corrupt_tally, CorruptTallyPolicy::Lock);
+        assert_eq!(config.storage, StorageBackend::Sqlite);
+        assert_eq!(config.track, vec![Track::User, Track::RHost]);
+        assert_eq!(
+            config.audit_log,
+            Some(PathBuf::from("/var/log/authramp/audit.jsonl"))
+        );
+        assert!(config.journald);
+        assert_eq!(config.log_level, LogLevel::Info);
+        assert_eq!(
+            config.lock_message,
+            "Locked for {remaining}, {failures} failures"
+        );
+        assert_eq!(
+            config.countdown_message,
+            Config::default().countdown_message
+        );
+        assert_eq!(
+            config.locale_dir,
+            PathBuf::from("/etc/security/authramp/locale")
+        );
+        assert_eq!(config.rhost_ipv4_prefix, 24);
+        // out of range
+        assert_eq!(config.rhost_ipv6_prefix, 64);
+        assert_eq!(
+            config.trusted_networks,
+            vec!["10.0.0.0/8".parse().unwrap(), "::1/128".parse().unwrap()]
+        );
+        assert_eq!(config.trusted_ttys, vec!["tty1", "ttyS*"]);
+    }
+
+    #[test]
+    fn test_permanent_lock_after_out_of_range() {
+        let temp_dir = TempDir::new("test_permanent_lock_after_out_of_range").unwrap();
+        let conf_file_path = temp_dir.path().join("config.conf");
+
+        // must not wrap around to a negative threshold
+        std::fs::write(
+            &conf_file_path,
+            "[Configuration]\npermanent_lock_after = 2147483648",
+        )
+        .unwrap();
+        let config = Config::load_file(Some(conf_file_path.to_str().unwrap()), None);
+        assert!(config.permanent_lock_after.is_none());
+    }
+
+    #[test]
+    fn test_max_delay_seconds_out_of_range() {
+        let temp_dir = TempDir::new("test_max_delay_seconds_out_of_range").unwrap();
+        let conf_file_path = temp_dir.path().join("config.conf");
+
+        std::fs::write(
+            &conf_file_path,
+            "[Configuration]\nmax_delay_seconds = 9223372036854775807\n\n[User.alice]\nmax_delay_seconds = 9223372036854775807",
+        )
+        .unwrap();
+        let config = Config::load_file(Some(conf_file_path.to_str().unwrap()), None);
+        assert_eq!(
+            config.max_delay_seconds,
+            Config::default().max_delay_seconds
+        );
+        // the invalid section is skipped
+        assert!(!config.users.contains_key("alice"));
+    }
+
+    #[test]
+    fn test_fail_interval_out_of_range() {
+        let temp_dir = TempDir::new("test_fail_interval_out_of_range").unwrap();
+        let conf_file_path = temp_dir.path().join("config.conf");
+
+        std::fs::write(
+            &conf_file_path,
+            "[Configuration]\nfail_interval = 9223372036854775807\ndelay_curve = \"exponential\"\nexponential_cap = 2147483648",
+        )
+        .unwrap();
+        let config = Config::load_file(Some(conf_file_path.to_str().unwrap()), None);
+        assert_eq!(config.fail_interval, None);
+        assert_eq!(config.delay_curve, DelayCurve::default());
+    }
+
+    #[test]
+    fn test_log_level() {
+        let config = Config {
+            log_level: LogLevel::Info,
+            ..Config::default()
+        };
+        assert_eq!(config.log_level(), LogLevel::Info);
+
+        // debug and quiet override the log level, debug wins
+        let config = Config {
+            quiet: true,
+            ..config
+        };
+        assert_eq!(config.log_level(), LogLevel::Warning);
+        let config = Config {
+            debug: true,
+            ..config
+        };
+        assert_eq!(config.log_level(), LogLevel::Debug);
+    }
+
+    #[test]
+    fn test_build_config_persistent() {
+        let temp_dir = TempDir::new("test_build_config_persistent").unwrap();
+        let conf_file_path = temp_dir.path().join("config.conf");
+
+        std::fs::write(&conf_file_path, "[Configuration]\npersistent = true").unwrap();
+        let config = Config::load_file(Some(conf_file_path.to_str().unwrap()), None);
+        assert_eq!(config.tally_dir, PathBuf::from("/var/lib/authramp"));
+
+        std::fs::write(
+            &conf_file_path,
+            "[Configuration]\npersistent = true\ntally_dir = \"/srv/authramp\"",
+        )
+        .unwrap();
+        let config = Config::load_file(Some(conf_file_path.to_str().unwrap()), None);
+        assert_eq!(config.tally_dir, PathBuf::from("/srv/authramp"));
+    }
+
+    #[test]
+    fn test_to_toml_roundtrip() {
+        let temp_dir = TempDir::new("test_to_toml_roundtrip").unwrap();
+        let conf_file_path = temp_dir.path().join("config.conf");
+
+        let toml_content = r#"
+        [Configuration]
+        tally_dir = "/tmp/tally_dir"
+        free_tries = 10
+        ramp_multiplier = 20
+        delay_curve = "exponential"
+        exponential_base = 1.5
+        permanent_lock_after = 20
+        on_corrupt_tally = "fail_open"
+        track = ["user+rhost"]
+        trusted_networks = ["10.0.0.0/8", "::1"]
+        trusted_ttys = ["tty1"]
+        tally_namespace = "login"
+
+        [Service.sshd]
+        free_tries = 3
+
+        [Group.wheel]
+        delay_curve = "steps"
+        steps = [5, 10]
+    "#;
+        std::fs::write(&conf_file_path, toml_content).unwrap();
+        let config = Config::load_file(Some(conf_file_path.to_str().unwrap()), None);
+
+        std::fs::write(&conf_file_path, toml::to_string(&config.to_toml()).unwrap()).unwrap();
+        assert_eq!(
+            Config::load_file(Some(conf_file_path.to_str().unwrap()), None),
+            config
+        );
+        assert!(crate::validate::check_file(&conf_file_path)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_load_drop_ins() {
+        let temp_dir = TempDir::new("test_load_drop_ins").unwrap();
+        let conf_file_path = temp_dir.path().join("authramp.conf");
+        let drop_in_dir = temp_dir.path().join("authramp.conf.d");
+        fs::create_dir(&drop_in_dir).unwrap();
+
+        fs::write(
+            &conf_file_path,
+            "[Configuration]\nfree_tries = 10\nbase_delay_seconds = 15\n\n[User.alice]\nfree_tries = 1",
+        )
+        .unwrap();
+        fs::write(
+            drop_in_dir.join("20-ramp.conf"),
+            "[Configuration]\nfree_tries = 4\n\n[User.alice]\nbase_delay_seconds = 5",
+        )
+        .unwrap();
+        fs::write(
+            drop_in_dir.join("10-ramp.conf"),
+            "[Configuration]\nfree_tries = 3\ncountdown = true",
+        )
+        .unwrap();
+        // ignored
+        fs::write(
+            drop_in_dir.join("30-ramp.conf.rpmsave"),
+            "[Configuration]\nfree_tries = 0",
+        )
+        .unwrap();
+        fs::write(drop_in_dir.join("40-broken.conf"), "[Configuration").unwrap();
+
+        let config = Config::load_file(Some(conf_file_path.to_str().unwrap()), None);
+        assert_eq!(config.free_tries, 4);
+        assert_eq!(config.base_delay_seconds, 15);
+        assert!(config.countdown);
+        assert_eq!(
+            config.users["alice"],
+            Overrides {
+                free_tries: Some(1),
+                base_delay_seconds: Some(5),
+                ..Overrides::default()
+            }
+        );
+
+        // fragments apply without the main file
+        fs::remove_file(&conf_file_path).unwrap();
+        let config = Config::load_file(Some(conf_file_path.to_str().unwrap()), None);
+        assert_eq!(config.free_tries, 4);
+        assert_eq!(config.base_delay_seconds, 30);
+    }
+
+    #[test]
+    fn test_read_toml_table_syntax_error() {
+        let temp_dir = TempDir::new("test_read_toml_table_syntax_error").unwrap();
+        let conf_file_path = temp_dir.path().join("authramp.conf");
+
+        assert_eq!(read_toml_table(&conf_file_path), Ok(None));
+
+        fs::write(
+            &conf_file_path,
+            "[Configuration]\nfree_tries = 3\nfree_tries = 4",
+        )
+        .unwrap();
+        let issue = read_toml_table(&conf_file_path).unwrap_err();
+        assert_eq!(issue.path, conf_file_path);
+        assert_eq!(issue.line, Some(3));
+
+        // falls back to the defaults
+        let config = Config::load_file(Some(conf_file_path.to_str().unwrap()), None);
+        assert_eq!(config.free_tries, Config::default().free_tries);
+    }
+
+    #[test]
+    fn test_apply_overrides() {
+        let temp_dir = TempDir::new("test_apply_overrides").unwrap();
+        let conf_file_path = temp_dir.path().join("config.conf");
+
+        let toml_content = r#"
+        [Configuration]
+        free_tries = 10
+        base_delay_seconds = 15
+
+        [Group.root]
+        free_tries = 1
+        base_delay_seconds = 300
+
+        [User.root]
+        base_delay_seconds = 60
+
+        [User.test_user]
+        delay_curve = "linear"
+
+        [Service.sshd]
+        free_tries = 3
+        base_delay_seconds = 600
+        tally_namespace = "ssh"
+
+        [Service.sudo]
+        delay_curve = "steps"
+        steps = [5]
+    "#;
+        std::fs::write(&conf_file_path, toml_content).unwrap();
+        let config = Config::load_file(Some(conf_file_path.to_str().unwrap()), None);
+        assert_eq!(config.services.len(), 2);
+        assert_eq!(config.users.len(), 2);
+        assert_eq!(config.groups.len(), 1);
+        // overrides are not applied globally
+        assert_eq!(config.free_tries, 10);
+
+        // the user override wins over the group override
+        let mut config = Config::load_file(Some(conf_file_path.to_str().unwrap()), None);
+        config.apply_overrides(&User::new(0, "root", 0), None);
+        assert_eq!(config.free_tries, 1);
+        assert_eq!(config.base_delay_seconds, 60);
+
+        // the group override wins over the service override
+        let mut config = Config::load_file(Some(conf_file_path.to_str().unwrap()), None);
+        config.apply_overrides(&User::new(0, "root", 0), Some("sshd"));
+        assert_eq!(config.free_tries, 1);
+        assert_eq!(config.base_delay_seconds, 60);
+        assert_eq!(config.tally_namespace, Some("ssh".to_string()));
+
+        let mut config = Config::load_file(Some(conf_file_path.to_str().unwrap()), None);
+        config.apply_overrides(&User::new(9999, "test_user", 9999), None);
+        assert_eq!(config.free_tries, 10);
+        assert_eq!(config.base_delay_seconds, 15);
+        assert_eq!(config.delay_curve, DelayCurve::Linear);
+        assert!(config.tally_namespace.is_none());
+
+        let mut config = Config::load_file(Some(conf_file_path.to_str().unwrap()), None);
+        config.apply_overrides(&User::new(9999, "test_user", 9999), Some("sshd"));
+        assert_eq!(config.free_tries, 3);
+        assert_eq!(config.base_delay_seconds, 600);
+        assert_eq!(config.delay_curve, DelayCurve::Linear);
+
+        let mut config = Config::load_file(Some(conf_file_path.to_str().unwrap()), None);
+        config.apply_overrides(&User::new(1000, "alice", 1000), Some("sudo"));
+        assert_eq!(config.delay_curve, DelayCurve::Steps(vec![5]));
+        assert!(config.tally_namespace.is_none());
+    }
+
+    #[test]
+    fn test_build_config_delay_curve() {
+        let temp_dir = TempDir::new("test_build_config_delay_curve").unwrap();
+        let conf_file_path = temp_dir.path().join("config.conf");
+
+        let toml_content = r#"
+        [Configuration]
+        delay_curve = "steps"
+        steps = [30, 60, 300, 900, 3600]
+    "#;
+        std::fs::write(&conf_file_path, toml_content).unwrap();
+
+        let config = Config::load_file(Some(conf_file_path.to_str().unwrap()), None);
+        assert_eq!(
+            config.delay_curve,
+            DelayCurve::Steps(vec![30, 60, 300, 900, 3600])
+        );
+    }
+
+    #[test]
+    fn test_build_config_invalid_delay_curve() {
+        let temp_dir = TempDir::new("test_build_config_invalid_delay_curve").unwrap();
+        let conf_file_path = temp_dir.path().join("config.conf");
+
+        let toml_content = r#"
+        [Configuration]
+        free_tries = 3
+        delay_curve = "steps"
+        steps = []
+    "#;
+        std::fs::write(&conf_file_path, toml_content).unwrap();
+
+        let config = Config::load_file(Some(conf_file_path.to_str().unwrap()), None);
+        assert_eq!(config.free_tries, 3);
+        assert_eq!(config.delay_curve, DelayCurve::NLogN);
     }
 }
@@ -0,0 +1,367 @@
+//! # Curve Module
+//!
+//! The `curve` module defines the delay curves used to compute how long an account stays locked
+//! after it exhausted its free tries.
+//!
+//! # Curves
+//!
+//! With `n` being the number of failures beyond `free_tries`, `b` the `base_delay_seconds` and
+//! `r` the `ramp_multiplier`, the following curves are available:
+//!
+//! - `nlogn` (default): `r * n * ln(n) + b`
+//! - `linear`: `r * (n - 1) + b`
+//! - `exponential`: `b * exponential_base^(n - 1)`, capped at `exponential_cap` seconds
+//! - `fibonacci`: `b * fib(n)` with `fib = 1, 1, 2, 3, 5, 8, ...`
+//! - `steps`: the `n`-th entry of the `steps` table, the last entry is repeated
+//!
+//! ## License
+//!
+//! pam-authramp
+//! Copyright (C) 2023 github.com/34N0
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+/// Upper bound for any computed delay in seconds. Keeps the result representable as a
+/// `chrono::Duration` and addable to a timestamp.
+const MAX_CURVE_SECONDS: i64 = i32::MAX as i64;
+
+/// Default base of the `exponential` curve.
+const DEFAULT_EXPONENTIAL_BASE: f64 = 2.0;
+
+/// Default cap of the `exponential` curve in seconds.
+const DEFAULT_EXPONENTIAL_CAP: i64 = 86400;
+
+/// The `DelayCurve` enum represents the function that maps the number of failures beyond the
+/// free tries to a lockout delay.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum DelayCurve {
+    /// `ramp_multiplier * (n - 1) + base_delay_seconds`, starting at the base delay.
+    Linear,
+    /// `base_delay_seconds * base^(n - 1)`, capped at `cap` seconds.
+    Exponential { base: f64, cap: i64 },
+    /// `ramp_multiplier * n * ln(n) + base_delay_seconds`. The original authramp formula.
+    #[default]
+    NLogN,
+    /// `base_delay_seconds * fib(n)`.
+    Fibonacci,
+    /// Explicit delay table in seconds. The last step is repeated once the table is exhausted.
+    Steps(Vec<i64>),
+}
+
+impl DelayCurve {
+    /// Reads and validates the delay curve from the `[Configuration]` table.
+    ///
+    /// # Arguments
+    ///
+    /// * `toml_config`: A reference to a `toml::Value` representing the configuration table.
+    ///
+    /// # Returns
+    ///
+    /// The configured `DelayCurve`, `DelayCurve::NLogN` if `delay_curve` is not set.
+    ///
+    /// # Errors
+    ///
+    /// Returns a message describing the problem if the curve name is unknown or its
+    /// parameters are invalid.
+    pub fn from_toml(toml_config: &toml::Value) -> Result<DelayCurve, String> {
+        let Some(name) = toml_config.get("delay_curve") else {
+            return Ok(DelayCurve::default());
+        };
+
+        let name = name
+            .as_str()
+            .ok_or_else(|| "delay_curve must be a string".to_string())?;
+
+        match name {
+            "linear" => Ok(DelayCurve::Linear),
+            "nlogn" => Ok(DelayCurve::NLogN),
+            "fibonacci" => Ok(DelayCurve::Fibonacci),
+            "exponential" => {
+                let base = match toml_config.get("exponential_base") {
+                    None => DEFAULT_EXPONENTIAL_BASE,
+                    Some(val) => val
+                        .as_float()
+                        .or_else(|| {
+                            val.as_integer()
+                                .and_then(|i| i32::try_from(i).ok())
+                                .map(f64::from)
+                        })
+                        .ok_or_else(|| "exponential_base must be a number".to_string())?,
+                };
+                if base.is_nan() || base < 1.0 {
+                    return Err(format!("exponential_base must be >= 1, got {base}"));
+                }
+
+                let cap = match toml_config.get("exponential_cap") {
+                    None => DEFAULT_EXPONENTIAL_CAP,
+                    Some(val) => val
+                        .as_integer()
+                        .ok_or_else(|| "exponential_cap must be an integer".to_string())?,
+                };
+                if !(1..=MAX_CURVE_SECONDS).contains(&cap) {
+                    return Err(format!(
+                        "exponential_cap must be between 1 and {MAX_CURVE_SECONDS}, got {cap}"
+                    ));
+                }
+
+                Ok(DelayCurve::Exponential { base, cap })
+            }
+            "steps" => {
+                let steps = toml_config
+                    .get("steps")
+                    .and_then(toml::Value::as_array)
+                    .ok_or_else(|| "delay_curve 'steps' requires a steps array".to_string())?
+                    .iter()
+                    .map(|step| match step.as_integer() {
+                        Some(s) if (0..=MAX_CURVE_SECONDS).contains(&s) => Ok(s),
+                        _ => Err(format!(
+                            "steps must be integers between 0 and {MAX_CURVE_SECONDS}, got {step}"
+                        )),
+                    })
+                    .collect::<Result<Vec<i64>, String>>()?;
+
+                if steps.is_empty() {
+                    return Err("steps must not be empty".to_string());
+                }
+
+                Ok(DelayCurve::Steps(steps))
+            }
+            other => Err(format!(
+                "unknown delay_curve '{other}', expected one of: linear, exponential, nlogn, fibonacci, steps"
+            )),
+        }
+    }
+
+    /// Writes the delay curve and its parameters to a configuration table, see `from_toml`.
+    ///
+    /// # Arguments
+    ///
+    /// * `toml_config`: The table to write `delay_curve` and the curve parameters to.
+    pub fn to_toml(&self, toml_config: &mut toml::value::Table) {
+        let name = match self {
+            DelayCurve::Linear => "linear",
+            DelayCurve::NLogN => "nlogn",
+            DelayCurve::Fibonacci => "fibonacci",
+            DelayCurve::Exponential { base, cap } => {
+                toml_config.insert("exponential_base".to_string(), toml::Value::Float(*base));
+                toml_config.insert("exponential_cap".to_string(), toml::Value::Integer(*cap));
+                "exponential"
+            }
+            DelayCurve::Steps(steps) => {
+                toml_config.insert(
+                    "steps".to_string(),
+                    toml::Value::Array(steps.iter().copied().map(toml::Value::Integer).collect()),
+                );
+                "steps"
+            }
+        };
+        toml_config.insert(
+            "delay_curve".to_string(),
+            toml::Value::String(name.to_string()),
+        );
+    }
+
+    /// Calculates the delay in seconds for the given number of failures beyond the free tries.
+    ///
+    /// # Arguments
+    ///
+    /// * `n`: Number of failures beyond `free_tries`. No delay is applied for `n <= 0`.
+    /// * `base_delay_seconds`: Base delay applied to each authentication failure.
+    /// * `ramp_multiplier`: Multiplier used by the `linear` and `nlogn` curves.
+    ///
+    /// # Returns
+    ///
+    /// The delay in seconds, clamped to a representable range.
+    #[must_use]
+    pub fn seconds(&self, n: i32, base_delay_seconds: i32, ramp_multiplier: i32) -> i64 {
+        if n <= 0 {
+            return 0;
+        }
+
+        let seconds = match self {
+            DelayCurve::Linear => {
+                i64::from(ramp_multiplier) * (i64::from(n) - 1) + i64::from(base_delay_seconds)
+            }
+            DelayCurve::Exponential { base, cap } => {
+                ((f64::from(base_delay_seconds) * base.powi(n - 1)) as i64).min(*cap)
+            }
+            DelayCurve::NLogN => {
+                (f64::from(ramp_multiplier) * f64::from(n) * f64::from(n).ln()
+                    + f64::from(base_delay_seconds)) as i64
+            }
+            DelayCurve::Fibonacci => {
+                let (mut prev, mut cur) = (0_i64, 1_i64);
+                for _ in 1..n {
+                    (prev, cur) = (cur, prev.saturating_add(cur));
+                    if cur >= MAX_CURVE_SECONDS {
+                        break;
+                    }
+                }
+                cur.saturating_mul(i64::from(base_delay_seconds))
+            }
+            DelayCurve::Steps(steps) => {
+                let idx = usize::try_from(n - 1).unwrap_or_default();
+                steps
+                    .get(idx)
+                    .or_else(|| steps.last())
+                    .copied()
+                    .unwrap_or_default()
+            }
+        };
+
+        seconds.clamp(0, MAX_CURVE_SECONDS)
+    }
+}
+
+// Unit Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The formula used by `Tally::get_delay` before delay curves were introduced.
+    fn legacy_delay(fails: i32, free_tries: i32, base: i32, multiplier: i32) -> i64 {
+        (f64::from(multiplier)
+            * (f64::from(fails) - f64::from(free_tries))
+            * ((f64::from(fails) - f64::from(free_tries)).ln())
+            + f64::from(base)) as i64
+    }
+
+    fn parse(toml_str: &str) -> Result<DelayCurve, String> {
+        DelayCurve::from_toml(&toml::from_str::<toml::Value>(toml_str).unwrap())
+    }
+
+    #[test]
+    fn test_to_toml_roundtrip() {
+        for curve in [
+            DelayCurve::Linear,
+            DelayCurve::NLogN,
+            DelayCurve::Fibonacci,
+            DelayCurve::Exponential {
+                base: 1.5,
+                cap: 3600,
+            },
+            DelayCurve::Steps(vec![30, 60]),
+        ] {
+            let mut toml_config = toml::value::Table::new();
+            curve.to_toml(&mut toml_config);
+            assert_eq!(
+                DelayCurve::from_toml(&toml::Value::Table(toml_config)),
+                Ok(curve)
+            );
+        }
+    }
+
+    #[test]
+    fn test_nlogn_matches_legacy_formula() {
+        for fails in 7..500 {
+            assert_eq!(
+                DelayCurve::NLogN.seconds(fails - 6, 30, 50),
+                legacy_delay(fails, 6, 30, 50),
+                "delay mismatch at {fails} failures"
+            );
+        }
+    }
+
+    #[test]
+    fn test_no_delay_within_free_tries() {
+        for curve in [
+            DelayCurve::Linear,
+            DelayCurve::NLogN,
+            DelayCurve::Fibonacci,
+            DelayCurve::Exponential {
+                base: 2.0,
+                cap: 3600,
+            },
+            DelayCurve::Steps(vec![30]),
+        ] {
+            assert_eq!(curve.seconds(0, 30, 50), 0);
+            assert_eq!(curve.seconds(-3, 30, 50), 0);
+        }
+    }
+
+    #[test]
+    fn test_linear() {
+        assert_eq!(DelayCurve::Linear.seconds(1, 30, 50), 30);
+        assert_eq!(DelayCurve::Linear.seconds(2, 30, 50), 80);
+        assert_eq!(DelayCurve::Linear.seconds(10, 30, 50), 480);
+    }
+
+    #[test]
+    fn test_exponential() {
+        let curve = DelayCurve::Exponential {
+            base: 2.0,
+            cap: 3600,
+        };
+        assert_eq!(curve.seconds(1, 30, 50), 30);
+        assert_eq!(curve.seconds(2, 30, 50), 60);
+        assert_eq!(curve.seconds(5, 30, 50), 480);
+        assert_eq!(curve.seconds(8, 30, 50), 3600);
+        assert_eq!(curve.seconds(i32::MAX, 30, 50), 3600);
+    }
+
+    #[test]
+    fn test_fibonacci() {
+        let delays: Vec<i64> = (1..=7)
+            .map(|n| DelayCurve::Fibonacci.seconds(n, 10, 50))
+            .collect();
+        assert_eq!(delays, vec![10, 10, 20, 30, 50, 80, 130]);
+        assert_eq!(
+            DelayCurve::Fibonacci.seconds(10_000, 10, 50),
+            MAX_CURVE_SECONDS
+        );
+    }
+
+    #[test]
+    fn test_steps() {
+        let curve = DelayCurve::Steps(vec![30, 60, 300, 900, 3600]);
+        assert_eq!(curve.seconds(1, 0, 0), 30);
+        assert_eq!(curve.seconds(3, 0, 0), 300);
+        assert_eq!(curve.seconds(5, 0, 0), 3600);
+        assert_eq!(curve.seconds(50, 0, 0), 3600);
+    }
+
+    #[test]
+    fn test_from_toml() {
+        assert_eq!(parse("free_tries = 6"), Ok(DelayCurve::NLogN));
+        assert_eq!(parse(r#"delay_curve = "linear""#), Ok(DelayCurve::Linear));
+        assert_eq!(
+            parse(r#"delay_curve = "fibonacci""#),
+            Ok(DelayCurve::Fibonacci)
+        );
+        assert_eq!(
+            parse("delay_curve = \"exponential\"\nexponential_base = 3\nexponential_cap = 600"),
+            Ok(DelayCurve::Exponential {
+                base: 3.0,
+                cap: 600
+            })
+        );
+        assert_eq!(
+            parse("delay_curve = \"steps\"\nsteps = [30, 60, 300, 900, 3600]"),
+            Ok(DelayCurve::Steps(vec![30, 60, 300, 900, 3600]))
+        );
+    }
+
+    #[test]
+    fn test_from_toml_invalid() {
+        assert!(parse(r#"delay_curve = "quadratic""#).is_err());
+        assert!(parse("delay_curve = 3").is_err());
+        assert!(parse(r#"delay_curve = "steps""#).is_err());
+        assert!(parse("delay_curve = \"steps\"\nsteps = []").is_err());
+        assert!(parse("delay_curve = \"steps\"\nsteps = [30, -1]").is_err());
+        assert!(parse("delay_curve = \"exponential\"\nexponential_base = 0.5").is_err());
+        assert!(parse("delay_curve = \"exponential\"\nexponential_cap = 0").is_err());
+        assert!(parse("delay_curve = \"exponential\"\nexponential_cap = 2147483648").is_err());
+        assert!(parse("delay_curve = \"steps\"\nsteps = [30, 2147483648]").is_err());
+    }
+}
@@ -5,18 +5,58 @@
 //!
 //! # Modules
 //!
+//! ## `audit`
+//!
+//! The `audit` module writes the structured audit log, one JSON event per line.
+//!
 //! ## `config`
 //!
 //! The `config` module provides functionality for loading and accessing configuration settings
 //! used by the `AuthRamp` PAM module and CLI binary. It includes a `Config` struct that represents
 //! the configuration settings for `AuthRamp`.
 //!
+//! ## `curve`
+//!
+//! The `curve` module defines the `DelayCurve` enumeration which maps the number of failures
+//! beyond the free tries to a lockout delay.
+//!
+//! ## `locale`
+//!
+//! The `locale` module translates the messages to locked out users with gettext PO catalogs.
+//!
+//! ## `lockout`
+//!
+//! The `lockout` module calculates until when the failures of a tally lock the account.
+//!
+//! ## `overrides`
+//!
+//! The `overrides` module defines the `[User.<name>]` and `[Group.<name>]` sections, which
+//! override the global policy for single users and groups.
+//!
+//! ## `schema`
+//!
+//! The `schema` module defines the versioned format in which tallies are stored.
+//!
 //! ## `settings`
 //!
 //! The `settings` module provides functionality for managing and accessing settings used by the
 //! `AuthRamp` PAM module. It includes a `Settings` struct that encapsulates configuration settings,
 //! user information, and other contextual information required for `AuthRamp`'s operation.
 //!
+//! ## `store`
+//!
+//! The `store` module provides the `TallyStore` trait and its storage backends, which persist the
+//! tallies of the PAM module and let the CLI binary inspect and reset them.
+//!
+//! ## `track`
+//!
+//! The `track` module defines the keys failures are counted by: users, remote hosts or both.
+//!
+//! ## `validate`
+//!
+//! The `validate` module checks configuration files strictly and reports unknown keys, wrong
+//! types and out-of-range values with their line numbers.
+//!
 //! ## `syslog`
 //!
 //! The `syslog` module provides functionality for initializing syslog logging in both the PAM module
@@ -47,5 +87,14 @@
 //! along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 pub mod actions;
+pub mod audit;
 pub mod config;
+pub mod curve;
+pub mod locale;
+pub mod lockout;
+pub mod overrides;
+pub mod schema;
 pub mod settings;
+pub mod store;
+pub mod track;
+pub mod validate;
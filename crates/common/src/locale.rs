@@ -0,0 +1,701 @@
+//! # Locale Module
+//!
+//! The `locale` module translates the messages the PAM module sends to locked out users. The
+//! translations are gettext PO catalogs in `locale_dir`, one file per language like `de.po` or
+//! `pt_BR.po`, selected by the `LC_ALL`, `LC_MESSAGES` or `LANG` variable of the PAM environment.
+//! The message ids are the English templates, so a catalog translates the default messages as
+//! well as the `lock_message`, `countdown_message` and `permanent_lock_message` of the
+//! configuration file. Plural forms are selected with the `Plural-Forms` header of the catalog.
+//!
+//! ```po
+//! msgid ""
+//! msgstr ""
+//! "Plural-Forms: nplurals=2; plural=(n != 1);\n"
+//!
+//! msgid "Account locked! Unlocking in {remaining}."
+//! msgstr "Konto gesperrt! Entsperrung in {remaining}."
+//!
+//! msgid "{n} minute"
+//! msgid_plural "{n} minutes"
+//! msgstr[0] "{n} Minute"
+//! msgstr[1] "{n} Minuten"
+//! ```
+//!
+//! ## License
+//!
+//! pam-authramp
+//! Copyright (C) 2023 github.com/34N0
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Directory the catalogs are read from if `locale_dir` is not set.
+pub const DEFAULT_LOCALE_DIR: &str = "/usr/share/authramp/locale";
+
+/// Placeholders the lockout message templates can use.
+pub const MESSAGE_PLACEHOLDERS: &[&str] = &["remaining", "until", "failures", "user"];
+
+/// A message catalog of one language. The default catalog has no translations, it returns the
+/// message ids and selects plural forms like English.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Catalog {
+    // Translations by message id, one per plural form.
+    messages: HashMap<String, Vec<String>>,
+    // Rule selecting the plural form, `n != 1` if the catalog has none.
+    plural: Option<PluralExpr>,
+}
+
+impl Catalog {
+    /// Loads the catalog of a locale.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir`: Directory holding the `.po` files.
+    /// * `locale`: The locale like `de_DE.UTF-8`, looked up as `de_DE.po` and then `de.po`.
+    ///
+    /// # Returns
+    ///
+    /// The catalog, or the default catalog if there is no locale or no catalog for it.
+    ///
+    /// # Errors
+    ///
+    /// Returns a message describing the problem if the catalog cannot be read or parsed.
+    pub fn load(dir: &Path, locale: Option<&str>) -> Result<Catalog, String> {
+        for name in locale.map(catalog_names).unwrap_or_default() {
+            let path = dir.join(format!("{name}.po"));
+            match fs::read_to_string(&path) {
+                Ok(content) => {
+                    return Catalog::parse(&content).map_err(|e| format!("{}: {e}", path.display()))
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+                Err(e) => return Err(format!("{}: {e}", path.display())),
+            }
+        }
+        Ok(Catalog::default())
+    }
+
+    /// Parses a PO catalog. Fuzzy entries and entries with a context are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `content`: The content of the `.po` file.
+    ///
+    /// # Errors
+    ///
+    /// Returns a message with the line of the problem if the catalog is malformed.
+    pub fn parse(content: &str) -> Result<Catalog, String> {
+        let mut catalog = Catalog::default();
+        let mut entry = PoEntry::default();
+        let mut field = None;
+
+        for (number, line) in content.lines().enumerate() {
+            let error = |message: &str| format!("line {}: {message}", number + 1);
+            let line = line.trim();
+
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('"') {
+                let string = unquote(line).ok_or_else(|| error("invalid string"))?;
+                entry
+                    .field(field.ok_or_else(|| error("string without keyword"))?)
+                    .push_str(&string);
+                continue;
+            }
+
+            // comments, msgctxt and msgid start the next entry
+            let starts_entry =
+                line.starts_with('#') || line.starts_with("msgctxt") || line.starts_with("msgid ");
+            if starts_entry && entry.msgid.is_some() {
+                catalog.add(std::mem::take(&mut entry))?;
+                field = None;
+            }
+
+            if let Some(flags) = line.strip_prefix("#,") {
+                entry.fuzzy |= flags.split(',').any(|flag| flag.trim() == "fuzzy");
+                continue;
+            }
+            if line.starts_with('#') {
+                continue;
+            }
+
+            let (keyword, string) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| error("expected a keyword and a string"))?;
+            let string = unquote(string.trim()).ok_or_else(|| error("invalid string"))?;
+            let next = match keyword {
+                "msgctxt" => PoField::Context,
+                "msgid" => PoField::Id,
+                "msgid_plural" => PoField::IdPlural,
+                "msgstr" => PoField::Str(0),
+                _ => keyword
+                    .strip_prefix("msgstr[")
+                    .and_then(|index| index.strip_suffix(']'))
+                    .and_then(|index| index.parse().ok())
+                    .map(PoField::Str)
+                    .ok_or_else(|| error(&format!("unknown keyword {keyword}")))?,
+            };
+            if let PoField::Str(index) = next {
+                if index != entry.msgstr.len() {
+                    return Err(error("msgstr out of order"));
+                }
+                entry.msgstr.push(String::new());
+            }
+            *entry.field(next) = string;
+            field = Some(next);
+        }
+
+        if entry.msgid.is_some() {
+            catalog.add(entry)?;
+        }
+        Ok(catalog)
+    }
+
+    /// Adds an entry to the catalog, the entry with an empty message id is the header.
+    fn add(&mut self, entry: PoEntry) -> Result<(), String> {
+        let Some(msgid) = entry.msgid else {
+            return Ok(());
+        };
+        if entry.fuzzy || entry.context.is_some() {
+            return Ok(());
+        }
+
+        if msgid.is_empty() {
+            let plural = entry
+                .msgstr
+                .first()
+                .into_iter()
+                .flat_map(|header| header.lines())
+                .filter_map(|line| line.strip_prefix("Plural-Forms:"))
+                .flat_map(|forms| forms.split(';'))
+                .find_map(|form| form.trim().strip_prefix("plural="));
+            if let Some(plural) = plural {
+                self.plural = Some(PluralExpr::parse(plural)?);
+            }
+        } else {
+            self.messages.insert(msgid, entry.msgstr);
+        }
+        Ok(())
+    }
+
+    /// Translates a message.
+    ///
+    /// # Arguments
+    ///
+    /// * `msgid`: The English message.
+    ///
+    /// # Returns
+    ///
+    /// The translation, or the message itself if it is not translated.
+    #[must_use]
+    pub fn gettext<'a>(&'a self, msgid: &'a str) -> &'a str {
+        self.messages
+            .get(msgid)
+            .and_then(|msgstr| msgstr.first())
+            .filter(|msgstr| !msgstr.is_empty())
+            .map_or(msgid, String::as_str)
+    }
+
+    /// Translates a message with plural forms.
+    ///
+    /// # Arguments
+    ///
+    /// * `msgid`: The English singular.
+    /// * `msgid_plural`: The English plural.
+    /// * `n`: The number the form is selected by.
+    ///
+    /// # Returns
+    ///
+    /// The plural form of the translation, or the English form if it is not translated.
+    #[must_use]
+    pub fn ngettext<'a>(&'a self, msgid: &'a str, msgid_plural: &'a str, n: u64) -> &'a str {
+        let index = self
+            .plural
+            .as_ref()
+            .map_or(u64::from(n != 1), |plural| plural.eval(n));
+        self.messages
+            .get(msgid)
+            .and_then(|msgstr| msgstr.get(usize::try_from(index).ok()?))
+            .filter(|msgstr| !msgstr.is_empty())
+            .map_or(if n == 1 { msgid } else { msgid_plural }, String::as_str)
+    }
+}
+
+/// Replaces the `{name}` placeholders of a template. Unknown placeholders are kept as is.
+///
+/// # Arguments
+///
+/// * `template`: The template.
+/// * `values`: The values by placeholder name.
+#[must_use]
+pub fn render(template: &str, values: &[(&str, &str)]) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest.find('}').and_then(|end| {
+            values
+                .iter()
+                .find(|(name, _)| *name == &rest[1..end])
+                .map(|(_, value)| (end, value))
+        });
+        if let Some((end, value)) = value {
+            rendered.push_str(value);
+            rest = &rest[end + 1..];
+        } else {
+            rendered.push('{');
+            rest = &rest[1..];
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Returns the names of the `{name}` placeholders of a template.
+///
+/// # Arguments
+///
+/// * `template`: The template.
+pub fn placeholders(template: &str) -> impl Iterator<Item = &str> {
+    template.split('{').skip(1).filter_map(|part| {
+        part.split_once('}').map(|(name, _)| name).filter(|name| {
+            !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        })
+    })
+}
+
+/// Returns the names of the catalogs of a locale, most specific first. `de_DE.UTF-8@euro` is
+/// looked up as `de_DE` and `de`, `C` and `POSIX` have no catalog.
+///
+/// # Arguments
+///
+/// * `locale`: The locale.
+fn catalog_names(locale: &str) -> Vec<String> {
+    let language = locale.split(['.', '@']).next().unwrap_or_default();
+    let valid = language
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if language.is_empty() || !valid || language == "C" || language == "POSIX" {
+        return Vec::new();
+    }
+
+    let mut names = vec![language.to_string()];
+    if let Some((language, _)) = language.split_once('_') {
+        names.push(language.to_string());
+    }
+    names
+}
+
+/// Unquotes a PO string and resolves its escape sequences.
+fn unquote(string: &str) -> Option<String> {
+    let inner = string.strip_prefix('"')?.strip_suffix('"')?;
+    let mut unquoted = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unquoted.push(c);
+            continue;
+        }
+        unquoted.push(match chars.next()? {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            c @ ('"' | '\\') => c,
+            _ => return None,
+        });
+    }
+    Some(unquoted)
+}
+
+/// The fields of a PO entry, continuation strings are appended to the last one.
+#[derive(Debug, Clone, Copy)]
+enum PoField {
+    Context,
+    Id,
+    IdPlural,
+    Str(usize),
+}
+
+/// A PO entry while it is parsed.
+#[derive(Debug, Default)]
+struct PoEntry {
+    context: Option<String>,
+    msgid: Option<String>,
+    msgid_plural: Option<String>,
+    msgstr: Vec<String>,
+    fuzzy: bool,
+}
+
+impl PoEntry {
+    /// Returns the string of a field.
+    fn field(&mut self, field: PoField) -> &mut String {
+        match field {
+            PoField::Context => self.context.get_or_insert_with(String::new),
+            PoField::Id => self.msgid.get_or_insert_with(String::new),
+            PoField::IdPlural => self.msgid_plural.get_or_insert_with(String::new),
+            PoField::Str(index) => &mut self.msgstr[index],
+        }
+    }
+}
+
+/// Operators of plural expressions, in the order of their precedence.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PluralOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl PluralOp {
+    fn from_token(token: &str) -> Option<PluralOp> {
+        Some(match token {
+            "||" => PluralOp::Or,
+            "&&" => PluralOp::And,
+            "==" => PluralOp::Eq,
+            "!=" => PluralOp::Ne,
+            "<" => PluralOp::Lt,
+            "<=" => PluralOp::Le,
+            ">" => PluralOp::Gt,
+            ">=" => PluralOp::Ge,
+            "+" => PluralOp::Add,
+            "-" => PluralOp::Sub,
+            "*" => PluralOp::Mul,
+            "/" => PluralOp::Div,
+            "%" => PluralOp::Rem,
+            _ => return None,
+        })
+    }
+
+    fn precedence(self) -> u8 {
+        match self {
+            PluralOp::Or => 1,
+            PluralOp::And => 2,
+            PluralOp::Eq | PluralOp::Ne => 3,
+            PluralOp::Lt | PluralOp::Le | PluralOp::Gt | PluralOp::Ge => 4,
+            PluralOp::Add | PluralOp::Sub => 5,
+            PluralOp::Mul | PluralOp::Div | PluralOp::Rem => 6,
+        }
+    }
+}
+
+/// The C expression of a `Plural-Forms` header, e.g. `n==1 ? 0 : n%10>=2 && n%10<=4 ? 1 : 2`.
+#[derive(Debug, Clone, PartialEq)]
+enum PluralExpr {
+    N,
+    Number(u64),
+    Not(Box<PluralExpr>),
+    Binary(PluralOp, Box<PluralExpr>, Box<PluralExpr>),
+    Condition(Box<PluralExpr>, Box<PluralExpr>, Box<PluralExpr>),
+}
+
+impl PluralExpr {
+    /// Parses a plural expression.
+    fn parse(expr: &str) -> Result<PluralExpr, String> {
+        let mut tokens = Vec::new();
+        let mut rest = expr.trim_start();
+        while !rest.is_empty() {
+            let len = if rest.starts_with(|c: char| c.is_ascii_digit()) {
+                rest.find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(rest.len())
+            } else if ["==", "!=", "<=", ">=", "&&", "||"]
+                .iter()
+                .any(|op| rest.starts_with(op))
+            {
+                2
+            } else if rest.starts_with(|c| "n<>+-*/%!?:()".contains(c)) {
+                1
+            } else {
+                return Err(format!("invalid plural expression '{expr}'"));
+            };
+            tokens.push(&rest[..len]);
+            rest = rest[len..].trim_start();
+        }
+
+        let mut parser = PluralParser { tokens, pos: 0 };
+        let parsed = parser.condition()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(parsed),
+            Some(token) => Err(format!("unexpected '{token}' in plural expression")),
+        }
+    }
+
+    /// Evaluates the expression like C, with `1` for true and `0` for false.
+    fn eval(&self, n: u64) -> u64 {
+        match self {
+            PluralExpr::N => n,
+            PluralExpr::Number(number) => *number,
+            PluralExpr::Not(expr) => u64::from(expr.eval(n) == 0),
+            PluralExpr::Condition(condition, then, otherwise) => {
+                if condition.eval(n) == 0 {
+                    otherwise.eval(n)
+                } else {
+                    then.eval(n)
+                }
+            }
+            PluralExpr::Binary(op, left, right) => {
+                let (left, right) = (left.eval(n), right.eval(n));
+                match op {
+                    PluralOp::Or => u64::from(left != 0 || right != 0),
+                    PluralOp::And => u64::from(left != 0 && right != 0),
+                    PluralOp::Eq => u64::from(left == right),
+                    PluralOp::Ne => u64::from(left != right),
+                    PluralOp::Lt => u64::from(left < right),
+                    PluralOp::Le => u64::from(left <= right),
+                    PluralOp::Gt => u64::from(left > right),
+                    PluralOp::Ge => u64::from(left >= right),
+                    PluralOp::Add => left.wrapping_add(right),
+                    PluralOp::Sub => left.wrapping_sub(right),
+                    PluralOp::Mul => left.wrapping_mul(right),
+                    PluralOp::Div => left.checked_div(right).unwrap_or_default(),
+                    PluralOp::Rem => left.checked_rem(right).unwrap_or_default(),
+                }
+            }
+        }
+    }
+}
+
+/// Recursive descent parser of plural expressions.
+struct PluralParser<'a> {
+    tokens: Vec<&'a str>,
+    pos: usize,
+}
+
+impl PluralParser<'_> {
+    fn next(&mut self) -> Option<&str> {
+        let token = self.tokens.get(self.pos).copied();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            _ => Err(format!("expected '{expected}' in plural expression")),
+        }
+    }
+
+    fn condition(&mut self) -> Result<PluralExpr, String> {
+        let condition = self.binary(1)?;
+        if self.tokens.get(self.pos) != Some(&"?") {
+            return Ok(condition);
+        }
+        self.pos += 1;
+        let then = self.condition()?;
+        self.expect(":")?;
+        let otherwise = self.condition()?;
+        Ok(PluralExpr::Condition(
+            Box::new(condition),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+
+    fn binary(&mut self, min_precedence: u8) -> Result<PluralExpr, String> {
+        let mut left = self.unary()?;
+        while let Some(op) = self
+            .tokens
+            .get(self.pos)
+            .and_then(|token| PluralOp::from_token(token))
+            .filter(|op| op.precedence() >= min_precedence)
+        {
+            self.pos += 1;
+            let right = self.binary(op.precedence() + 1)?;
+            left = PluralExpr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<PluralExpr, String> {
+        match self.next() {
+            Some("!") => Ok(PluralExpr::Not(Box::new(self.unary()?))),
+            Some("n") => Ok(PluralExpr::N),
+            Some("(") => {
+                let expr = self.condition()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Some(token) => token
+                .parse()
+                .map(PluralExpr::Number)
+                .map_err(|_| format!("unexpected '{token}' in plural expression")),
+            None => Err("incomplete plural expression".to_string()),
+        }
+    }
+}
+
+// Unit Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    const POLISH: &str = r#"
+# Polish translation
+msgid ""
+msgstr ""
+"Content-Type: text/plain; charset=UTF-8\n"
+"Plural-Forms: nplurals=3; plural=(n==1 ? 0 : n%10>=2 && n%10<=4 && (n%100<10 || "
+"n%100>=20) ? 1 : 2);\n"
+
+msgid "Account locked until {until}."
+msgstr "Konto zablokowane do {until}."
+
+#, fuzzy
+msgid "Account locked! Unlocking in {remaining}."
+msgstr "Konto zablokowane!"
+
+msgctxt "menu"
+msgid "Lock"
+msgstr "Zablokuj"
+
+msgid "{n} minute"
+msgid_plural "{n} minutes"
+msgstr[0] "{n} minuta"
+msgstr[1] "{n} minuty"
+msgstr[2] "{n} minut"
+
+msgid "Say \"hi\""
+msgstr ""
+"Powiedz "
+"\"cześć\""
+"#;
+
+    #[test]
+    fn test_parse_catalog() {
+        let catalog = Catalog::parse(POLISH).unwrap();
+        assert_eq!(
+            catalog.gettext("Account locked until {until}."),
+            "Konto zablokowane do {until}."
+        );
+        assert_eq!(catalog.gettext("Say \"hi\""), "Powiedz \"cześć\"");
+        // fuzzy and context entries are not used
+        assert_eq!(
+            catalog.gettext("Account locked! Unlocking in {remaining}."),
+            "Account locked! Unlocking in {remaining}."
+        );
+        assert_eq!(catalog.gettext("Lock"), "Lock");
+
+        let minutes = |n| catalog.ngettext("{n} minute", "{n} minutes", n);
+        assert_eq!(minutes(1), "{n} minuta");
+        assert_eq!(minutes(3), "{n} minuty");
+        assert_eq!(minutes(5), "{n} minut");
+        assert_eq!(minutes(12), "{n} minut");
+        assert_eq!(minutes(22), "{n} minuty");
+
+        assert!(Catalog::parse("msgid \"a\"\nmsgstr[1] \"b\"").is_err());
+        assert!(Catalog::parse("msgid \"a\"\nmsgstr \"b\\q\"").is_err());
+        assert!(Catalog::parse("\"a\"").is_err());
+        assert!(Catalog::parse("msgid \"\"\nmsgstr \"Plural-Forms: plural=n ?;\\n\"").is_err());
+    }
+
+    #[test]
+    fn test_example_catalog() {
+        let catalog = Catalog::parse(include_str!("../../../examples/locale/de.po")).unwrap();
+        let config = crate::config::Config::default();
+        for template in [
+            &config.lock_message,
+            &config.countdown_message,
+            &config.permanent_lock_message,
+        ] {
+            assert_ne!(catalog.gettext(template), template);
+        }
+        assert_eq!(catalog.ngettext("{n} hour", "{n} hours", 2), "{n} Stunden");
+    }
+
+    #[test]
+    fn test_default_catalog() {
+        let catalog = Catalog::default();
+        assert_eq!(catalog.gettext("Account locked."), "Account locked.");
+        assert_eq!(catalog.ngettext("{n} hour", "{n} hours", 1), "{n} hour");
+        assert_eq!(catalog.ngettext("{n} hour", "{n} hours", 0), "{n} hours");
+        assert_eq!(catalog.ngettext("{n} hour", "{n} hours", 2), "{n} hours");
+    }
+
+    #[test]
+    fn test_plural_expr() {
+        let french = PluralExpr::parse("n > 1").unwrap();
+        assert_eq!(french.eval(0), 0);
+        assert_eq!(french.eval(2), 1);
+
+        let arabic = PluralExpr::parse(
+            "(n==0 ? 0 : n==1 ? 1 : n==2 ? 2 : n%100>=3 && n%100<=10 ? 3 : n%100>=11 ? 4 : 5)",
+        )
+        .unwrap();
+        let forms: Vec<u64> = [0, 1, 2, 5, 11, 100]
+            .iter()
+            .map(|n| arabic.eval(*n))
+            .collect();
+        assert_eq!(forms, vec![0, 1, 2, 3, 4, 5]);
+
+        assert_eq!(PluralExpr::parse("!(n % 0) + 2 * 3").unwrap().eval(7), 7);
+        assert!(PluralExpr::parse("n ==").is_err());
+        assert!(PluralExpr::parse("(n").is_err());
+        assert!(PluralExpr::parse("n 1").is_err());
+        assert!(PluralExpr::parse("x").is_err());
+    }
+
+    #[test]
+    fn test_load_catalog() {
+        let temp_dir = TempDir::new("test_load_catalog").unwrap();
+        std::fs::write(
+            temp_dir.path().join("de.po"),
+            "msgid \"Account locked.\"\nmsgstr \"Konto gesperrt.\"\n",
+        )
+        .unwrap();
+
+        for locale in ["de_AT.UTF-8", "de", "de_DE@euro"] {
+            let catalog = Catalog::load(temp_dir.path(), Some(locale)).unwrap();
+            assert_eq!(catalog.gettext("Account locked."), "Konto gesperrt.");
+        }
+        for locale in [None, Some("C"), Some("fr_FR.UTF-8"), Some("../de")] {
+            assert_eq!(
+                Catalog::load(temp_dir.path(), locale).unwrap(),
+                Catalog::default()
+            );
+        }
+
+        std::fs::write(temp_dir.path().join("fr.po"), "msgid").unwrap();
+        assert!(Catalog::load(temp_dir.path(), Some("fr")).is_err());
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            render(
+                "Locked for {remaining}, {failures} failures {unknown} {",
+                &[("remaining", "5 minutes"), ("failures", "7")]
+            ),
+            "Locked for 5 minutes, 7 failures {unknown} {"
+        );
+        assert_eq!(
+            placeholders("{remaining} {x y} {} {failures}").collect::<Vec<_>>(),
+            vec!["remaining", "failures"]
+        );
+    }
+}
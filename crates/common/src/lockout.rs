@@ -0,0 +1,342 @@
+//! # Lockout Module
+//!
+//! The `lockout` module calculates the lockout of an account from its failures and the
+//! configuration. The PAM module bounces attempts with it and the CLI binary reports the status
+//! of the tallies with it, so both agree on when an account is unlocked.
+//!
+//! ## License
+//!
+//! pam-authramp
+//! Copyright (C) 2023 github.com/34N0
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::config::Config;
+use crate::schema::{FailureRecord, TallyFile};
+
+/// Maximum number of failure timestamps stored for the `fail_interval` window. Older failures
+/// are only counted and expire with the oldest stored timestamp.
+pub const MAX_FAILURE_INSTANTS: usize = 100;
+
+/// Calculates the delay based on the number of authentication failures.
+/// Uses the configured delay curve, which defaults to the authramp formula:
+/// `delay=ramp_multiplier×(fails` − `free_tries)×ln(fails` − `free_tries)+base_delay_seconds`
+///
+/// The delay is capped at `max_delay_seconds`.
+///
+/// # Arguments
+/// - `config`: The `AuthRamp` configuration
+/// - `failures_count`: Number of authentication failures
+///
+/// # Returns
+/// Calculated delay as a `Duration`
+#[must_use]
+pub fn delay(config: &Config, failures_count: i32) -> Duration {
+    let delay = config.delay_curve.seconds(
+        failures_count - config.free_tries,
+        config.base_delay_seconds,
+        config.ramp_multiplier,
+    );
+    Duration::seconds(delay.min(config.max_delay_seconds))
+}
+
+/// Checks whether the account reached `permanent_lock_after` failures and stays locked
+/// until it is reset with `authramp reset`.
+///
+/// # Arguments
+/// - `config`: The `AuthRamp` configuration
+/// - `failures_count`: Number of authentication failures
+///
+/// # Returns
+/// `true` if the account is permanently locked
+#[must_use]
+pub fn is_permanently_locked(config: &Config, failures_count: i32) -> bool {
+    config
+        .permanent_lock_after
+        .is_some_and(|limit| failures_count >= limit)
+}
+
+/// Returns the time until which the failures lock the account, `DateTime::<Utc>::MAX_UTC` if
+/// it is permanently locked, or `None` if it is within the free tries.
+///
+/// # Arguments
+/// - `config`: The `AuthRamp` configuration
+/// - `failures_count`: Number of authentication failures
+/// - `failure_instant`: Timestamp of the last failure
+/// - `unlock_instant`: The stored unlock time, calculated from the delay if not set
+#[must_use]
+pub fn locked_until(
+    config: &Config,
+    failures_count: i32,
+    failure_instant: DateTime<Utc>,
+    unlock_instant: Option<DateTime<Utc>>,
+) -> Option<DateTime<Utc>> {
+    if is_permanently_locked(config, failures_count) {
+        Some(DateTime::<Utc>::MAX_UTC)
+    } else if failures_count > config.free_tries {
+        Some(unlock_instant.unwrap_or(failure_instant + delay(config, failures_count)))
+    } else {
+        None
+    }
+}
+
+/// Returns the failures within the `fail_interval` window.
+///
+/// Failures without a stored timestamp, as in tallies written before `fail_interval` was
+/// configured, are treated as if they happened at the oldest stored timestamp, or at the last
+/// failure instant if none is stored.
+///
+/// # Arguments
+/// - `config`: The `AuthRamp` configuration
+/// - `failures_count`: Number of authentication failures
+/// - `failure_instant`: Timestamp of the last failure
+/// - `failure_instants`: Timestamps of the stored failures
+/// - `now`: The current time
+///
+/// # Returns
+/// The number of failures that are not expired and the timestamps of the stored ones among
+/// them, or `None` if failures never expire.
+#[must_use]
+pub fn unexpired_failures(
+    config: &Config,
+    failures_count: i32,
+    failure_instant: DateTime<Utc>,
+    failure_instants: &[DateTime<Utc>],
+    now: DateTime<Utc>,
+) -> Option<(i32, Vec<DateTime<Utc>>)> {
+    // a window reaching past the supported time range never expires anything
+    let window_start = Duration::try_seconds(config.fail_interval?)
+        .and_then(|fail_interval| now.checked_sub_signed(fail_interval))?;
+
+    let stored = i32::try_from(failure_instants.len()).unwrap_or(i32::MAX);
+    let unstamped = failures_count.saturating_sub(stored).max(0);
+    let unstamped_instant = failure_instants
+        .iter()
+        .min()
+        .copied()
+        .unwrap_or(failure_instant);
+
+    let instants: Vec<_> = failure_instants
+        .iter()
+        .filter(|instant| **instant >= window_start)
+        .copied()
+        .collect();
+    let mut count = i32::try_from(instants.len()).unwrap_or(i32::MAX);
+    if unstamped_instant >= window_start {
+        count = count.saturating_add(unstamped);
+    }
+
+    Some((count, instants))
+}
+
+/// Records an authentication failure in a tally: forgets the failures older than
+/// `fail_interval`, counts the failure, adds it to the history bounded by `max_history` and
+/// locks the account for the delay of the new count.
+///
+/// # Arguments
+/// - `config`: The `AuthRamp` configuration
+/// - `tally`: The tally of the account
+/// - `record`: The failure
+pub fn record_failure(config: &Config, tally: &mut TallyFile, record: FailureRecord) {
+    let fails = &mut tally.fails;
+    if let Some((count, instants)) = unexpired_failures(
+        config,
+        fails.count,
+        fails.instant.unwrap_or_default(),
+        &fails.instants,
+        record.instant,
+    ) {
+        fails.count = count;
+        fails.instants = instants;
+    }
+
+    fails.count = fails.count.saturating_add(1);
+    fails.instant = Some(record.instant);
+    if config.fail_interval.is_some() {
+        push_failure_instant(&mut fails.instants, record.instant);
+    } else {
+        fails.instants.clear();
+    }
+    fails.unlock_instant = Some(record.instant + delay(config, fails.count));
+
+    tally.history.push(record);
+    let excess = tally.history.len().saturating_sub(config.max_history);
+    tally.history.drain(..excess);
+}
+
+/// Stores the timestamp of a failure for the `fail_interval` window. Beyond
+/// `MAX_FAILURE_INSTANTS` the oldest timestamps are dropped, their failures stay in the count
+/// and are dated at the oldest remaining timestamp by `unexpired_failures`, so they never expire
+/// early.
+///
+/// # Arguments
+/// - `failure_instants`: Timestamps of the stored failures, oldest first
+/// - `failure_instant`: Timestamp of the new failure
+fn push_failure_instant(failure_instants: &mut Vec<DateTime<Utc>>, failure_instant: DateTime<Utc>) {
+    failure_instants.push(failure_instant);
+    let excess = failure_instants.len().saturating_sub(MAX_FAILURE_INSTANTS);
+    failure_instants.drain(..excess);
+}
+
+// Unit Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::FailsTable;
+
+    #[test]
+    fn test_locked_until() {
+        let config = Config {
+            permanent_lock_after: Some(10),
+            ..Config::default()
+        };
+        let instant = Utc::now();
+
+        assert_eq!(locked_until(&config, 6, instant, None), None);
+        assert_eq!(
+            locked_until(&config, 7, instant, None),
+            Some(instant + Duration::seconds(30))
+        );
+        assert_eq!(
+            locked_until(&config, 7, instant, Some(instant)),
+            Some(instant)
+        );
+        assert_eq!(
+            locked_until(&config, 10, instant, Some(instant)),
+            Some(DateTime::<Utc>::MAX_UTC)
+        );
+    }
+
+    #[test]
+    fn test_unexpired_failures() {
+        let now = Utc::now();
+        let old = now - Duration::seconds(120);
+
+        assert_eq!(
+            unexpired_failures(&Config::default(), 2, old, &[], now),
+            None
+        );
+
+        let config = Config {
+            fail_interval: Some(60),
+            ..Config::default()
+        };
+        assert_eq!(
+            unexpired_failures(&config, 2, now, &[old, now], now),
+            Some((1, vec![now]))
+        );
+        // legacy tallies without timestamps expire at once
+        assert_eq!(
+            unexpired_failures(&config, 2, now, &[], now),
+            Some((2, Vec::new()))
+        );
+        assert_eq!(
+            unexpired_failures(&config, 2, old, &[], now),
+            Some((0, Vec::new()))
+        );
+        assert_eq!(
+            unexpired_failures(&config, i32::MAX, now, &[], now),
+            Some((i32::MAX, Vec::new()))
+        );
+        // failures without timestamps expire with the oldest stored one
+        assert_eq!(
+            unexpired_failures(&config, 3, now, &[old, now], now),
+            Some((1, vec![now]))
+        );
+        assert_eq!(
+            unexpired_failures(&config, 3, now, &[now], now),
+            Some((3, vec![now]))
+        );
+    }
+
+    #[test]
+    fn test_record_failure() {
+        let config = Config {
+            fail_interval: Some(900),
+            max_history: 2,
+            ..Config::default()
+        };
+        let now = Utc::now();
+        let old = now - Duration::seconds(1000);
+        let failure = |instant| FailureRecord {
+            instant,
+            service: None,
+            rhost: None,
+            tty: None,
+            ruser: None,
+        };
+
+        let mut tally = TallyFile::new(
+            FailsTable {
+                count: 2,
+                instant: Some(old),
+                unlock_instant: None,
+                instants: vec![old, old],
+            },
+            vec![failure(old), failure(old)],
+        );
+        for _ in 0..4 {
+            record_failure(&config, &mut tally, failure(now));
+        }
+
+        // the failures from before the window are forgotten
+        assert_eq!(tally.fails.count, 4);
+        assert_eq!(tally.fails.instant, Some(now));
+        assert_eq!(tally.fails.instants, vec![now; 4]);
+        assert_eq!(tally.fails.unlock_instant, Some(now + delay(&config, 4)));
+        assert_eq!(tally.history, vec![failure(now), failure(now)]);
+
+        // timestamps are only stored for the fail_interval window
+        record_failure(&Config::default(), &mut tally, failure(now));
+        assert_eq!(tally.fails.count, 5);
+        assert!(tally.fails.instants.is_empty());
+    }
+
+    #[test]
+    fn test_push_failure_instant() {
+        let config = Config {
+            fail_interval: Some(900),
+            ..Config::default()
+        };
+        let now = Utc::now();
+        let old = now - Duration::seconds(1000);
+
+        let mut instants = Vec::new();
+        push_failure_instant(&mut instants, old);
+        for _ in 0..MAX_FAILURE_INSTANTS {
+            push_failure_instant(&mut instants, now);
+        }
+        assert_eq!(instants.len(), MAX_FAILURE_INSTANTS);
+        assert!(instants.iter().all(|instant| *instant == now));
+
+        // the dropped failure is dated at the oldest stored one
+        let count = i32::try_from(MAX_FAILURE_INSTANTS).unwrap() + 1;
+        assert_eq!(
+            unexpired_failures(&config, count, now, &instants, now),
+            Some((count, instants))
+        );
+    }
+
+    #[test]
+    fn test_unexpired_failures_huge_interval() {
+        let now = Utc::now();
+        let config = Config {
+            fail_interval: Some(i64::MAX),
+            ..Config::default()
+        };
+        assert_eq!(unexpired_failures(&config, 2, now, &[], now), None);
+    }
+}
@@ -0,0 +1,425 @@
+//! # Overrides Module
+//!
+//! The `overrides` module defines policy overrides for single PAM services, users and groups.
+//! They are configured in `[Service.<name>]`, `[User.<name>]` and `[Group.<name>]` sections of the
+//! configuration file:
+//!
+//! ```toml
+//! [Service.sshd]
+//! free_tries = 3
+//! tally_namespace = "sshd"
+//!
+//! [User.backup]
+//! free_tries = 1
+//!
+//! [Group.wheel]
+//! base_delay_seconds = 300
+//! delay_curve = "exponential"
+//! max_delay_seconds = 604800
+//! ```
+//!
+//! An override replaces the global value of `free_tries`, `base_delay_seconds`,
+//! `ramp_multiplier`, `delay_curve`, `max_delay_seconds` and `tally_namespace`. Overrides are
+//! resolved with the precedence user > most specific group > service (`PAM_SERVICE`) > global.
+//! The primary group of a user is the most specific one, followed by the supplementary group with
+//! the fewest members.
+//!
+//! ## License
+//!
+//! pam-authramp
+//! Copyright (C) 2023 github.com/34N0
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+use crate::config::{ramp_multiplier_from_toml, Config, MAX_SECONDS};
+use crate::curve::DelayCurve;
+use crate::validate::check_value;
+
+/// Keys of the `[Service.<name>]`, `[User.<name>]` and `[Group.<name>]` sections.
+pub const OVERRIDE_KEYS: &[&str] = &[
+    "free_tries",
+    "base_delay_seconds",
+    "ramp_multiplier",
+    "delay_curve",
+    "exponential_base",
+    "exponential_cap",
+    "steps",
+    "max_delay_seconds",
+    "tally_namespace",
+];
+
+/// The `Overrides` struct holds the settings a `[Service.<name>]`, `[User.<name>]` or
+/// `[Group.<name>]` section replaces. Settings that are not set keep their global value.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Overrides {
+    // Number of allowed free authentication attempts before applying delays.
+    pub free_tries: Option<i32>,
+    // Base delay applied to each authentication failure.
+    pub base_delay_seconds: Option<i32>,
+    // Multiplier for the delay calculation based on the number of failures.
+    pub ramp_multiplier: Option<i32>,
+    // Function used to calculate the delay from the number of failures.
+    pub delay_curve: Option<DelayCurve>,
+    // Maximum delay in seconds an account can be locked by the ramp.
+    pub max_delay_seconds: Option<i64>,
+    // Namespace the tallies are stored in.
+    pub tally_namespace: Option<String>,
+}
+
+impl Overrides {
+    /// Reads the overrides of a single section.
+    ///
+    /// # Arguments
+    ///
+    /// * `section`: A reference to a `toml::Value` representing the section.
+    ///
+    /// # Returns
+    ///
+    /// The `Overrides` of the section.
+    ///
+    /// # Errors
+    ///
+    /// Returns a message describing the problem if the section is no table or holds an invalid
+    /// value.
+    pub fn from_toml(section: &toml::Value) -> Result<Overrides, String> {
+        let Some(entries) = section.as_table() else {
+            return Err("must be a table".to_string());
+        };
+
+        // unknown keys are only reported by the validation
+        for (key, value) in entries {
+            if OVERRIDE_KEYS.contains(&key.as_str()) {
+                check_value(key, value, section)?;
+            }
+        }
+
+        let integer = |key: &str| -> Result<Option<i64>, String> {
+            section
+                .get(key)
+                .map(|val| {
+                    val.as_integer()
+                        .ok_or_else(|| format!("{key} must be an integer"))
+                })
+                .transpose()
+        };
+
+        let to_i32 = |key: &str, val: i64| -> Result<i32, String> {
+            i32::try_from(val).map_err(|_| format!("{key} is out of range"))
+        };
+
+        Ok(Overrides {
+            free_tries: integer("free_tries")?
+                .map(|val| to_i32("free_tries", val))
+                .transpose()?,
+            base_delay_seconds: integer("base_delay_seconds")?
+                .map(|val| to_i32("base_delay_seconds", val))
+                .transpose()?,
+            ramp_multiplier: section
+                .get("ramp_multiplier")
+                .map(|val| {
+                    ramp_multiplier_from_toml(val)
+                        .ok_or_else(|| "ramp_multiplier must be a whole number".to_string())
+                })
+                .transpose()?,
+            delay_curve: section
+                .get("delay_curve")
+                .map(|_| DelayCurve::from_toml(section))
+                .transpose()?,
+            max_delay_seconds: integer("max_delay_seconds")?
+                .map(|val| {
+                    if (1..=MAX_SECONDS).contains(&val) {
+                        Ok(val)
+                    } else {
+                        Err(format!(
+                            "max_delay_seconds must be between 1 and {MAX_SECONDS}, got {val}"
+                        ))
+                    }
+                })
+                .transpose()?,
+            tally_namespace: section
+                .get("tally_namespace")
+                .map(|val| {
+                    val.as_str()
+                        .filter(|val| !val.is_empty())
+                        .map(str::to_string)
+                        .ok_or_else(|| "tally_namespace must be a non-empty string".to_string())
+                })
+                .transpose()?,
+        })
+    }
+
+    /// Reads all sections of a table like `Service`, `User` or `Group`.
+    ///
+    /// # Arguments
+    ///
+    /// * `sections`: The table holding one section per name, `None` if it is not configured.
+    ///
+    /// # Returns
+    ///
+    /// The valid sections by name and a message for each invalid section, which is skipped.
+    #[must_use]
+    pub fn sections_from_toml(
+        sections: Option<&toml::Value>,
+    ) -> (HashMap<String, Overrides>, Vec<String>) {
+        let mut overrides = HashMap::new();
+        let mut errors = Vec::new();
+
+        let Some(sections) = sections else {
+            return (overrides, errors);
+        };
+
+        let Some(sections) = sections.as_table() else {
+            errors.push("must be a table of sections".to_string());
+            return (overrides, errors);
+        };
+
+        for (name, section) in sections {
+            match Overrides::from_toml(section) {
+                Ok(section) => {
+                    overrides.insert(name.clone(), section);
+                }
+                Err(e) => errors.push(format!("{name}: {e}")),
+            }
+        }
+
+        (overrides, errors)
+    }
+
+    /// Writes the set overrides to a section table, see `from_toml`.
+    ///
+    /// # Returns
+    ///
+    /// The section table.
+    #[must_use]
+    pub fn to_toml(&self) -> toml::value::Table {
+        let mut section = toml::value::Table::new();
+        if let Some(free_tries) = self.free_tries {
+            section.insert("free_tries".to_string(), free_tries.into());
+        }
+        if let Some(base_delay_seconds) = self.base_delay_seconds {
+            section.insert("base_delay_seconds".to_string(), base_delay_seconds.into());
+        }
+        if let Some(ramp_multiplier) = self.ramp_multiplier {
+            section.insert("ramp_multiplier".to_string(), ramp_multiplier.into());
+        }
+        if let Some(delay_curve) = &self.delay_curve {
+            delay_curve.to_toml(&mut section);
+        }
+        if let Some(max_delay_seconds) = self.max_delay_seconds {
+            section.insert("max_delay_seconds".to_string(), max_delay_seconds.into());
+        }
+        if let Some(tally_namespace) = &self.tally_namespace {
+            section.insert(
+                "tally_namespace".to_string(),
+                tally_namespace.clone().into(),
+            );
+        }
+        section
+    }
+
+    /// Replaces the settings of a configuration with the set overrides.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: The configuration to modify
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(free_tries) = self.free_tries {
+            config.free_tries = free_tries;
+        }
+        if let Some(base_delay_seconds) = self.base_delay_seconds {
+            config.base_delay_seconds = base_delay_seconds;
+        }
+        if let Some(ramp_multiplier) = self.ramp_multiplier {
+            config.ramp_multiplier = ramp_multiplier;
+        }
+        if let Some(delay_curve) = &self.delay_curve {
+            config.delay_curve = delay_curve.clone();
+        }
+        if let Some(max_delay_seconds) = self.max_delay_seconds {
+            config.max_delay_seconds = max_delay_seconds;
+        }
+        if let Some(tally_namespace) = &self.tally_namespace {
+            config.tally_namespace = Some(tally_namespace.clone());
+        }
+    }
+}
+
+/// The `GroupMembership` struct describes a group of a user for choosing the most specific
+/// group override.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupMembership {
+    // Name of the group.
+    pub name: String,
+    // Whether the group is the primary group of the user.
+    pub primary: bool,
+    // Number of supplementary members of the group.
+    pub members: usize,
+}
+
+/// Chooses the group override that applies to a user.
+///
+/// # Arguments
+///
+/// * `groups`: The group overrides by group name
+/// * `memberships`: The groups of the user
+///
+/// # Returns
+///
+/// The override of the primary group if there is one, otherwise the override of the
+/// supplementary group with the fewest members. Ties are broken by group name.
+#[must_use]
+pub fn most_specific_group<'a, S: BuildHasher>(
+    groups: &'a HashMap<String, Overrides, S>,
+    memberships: &[GroupMembership],
+) -> Option<&'a Overrides> {
+    memberships
+        .iter()
+        .filter(|membership| groups.contains_key(&membership.name))
+        .min_by(|a, b| {
+            b.primary
+                .cmp(&a.primary)
+                .then(a.members.cmp(&b.members))
+                .then(a.name.cmp(&b.name))
+        })
+        .and_then(|membership| groups.get(&membership.name))
+}
+
+// Unit Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sections(toml_str: &str) -> (HashMap<String, Overrides>, Vec<String>) {
+        let toml_table: toml::Value = toml::from_str(toml_str).unwrap();
+        Overrides::sections_from_toml(toml_table.get("User"))
+    }
+
+    #[test]
+    fn test_sections_from_toml() {
+        let (overrides, errors) = sections(
+            r#"
+            [User.alice]
+            free_tries = 2
+            ramp_multiplier = 10
+            delay_curve = "linear"
+
+            [User.bob]
+            max_delay_seconds = 600
+            base_delay_seconds = 5
+
+            [User.carol]
+            tally_namespace = "sshd"
+
+            [User.mallory]
+            free_tries = "many"
+            "#,
+        );
+
+        assert_eq!(
+            overrides["alice"],
+            Overrides {
+                free_tries: Some(2),
+                ramp_multiplier: Some(10),
+                delay_curve: Some(DelayCurve::Linear),
+                ..Overrides::default()
+            }
+        );
+        assert_eq!(
+            overrides["bob"],
+            Overrides {
+                base_delay_seconds: Some(5),
+                max_delay_seconds: Some(600),
+                ..Overrides::default()
+            }
+        );
+        assert_eq!(overrides["carol"].tally_namespace, Some("sshd".to_string()));
+        // invalid sections are skipped
+        assert!(!overrides.contains_key("mallory"));
+        assert_eq!(
+            errors,
+            vec!["mallory: free_tries must be an integer, got \"many\""]
+        );
+
+        // sections survive a roundtrip
+        let section = toml::Value::Table(overrides["alice"].to_toml());
+        assert_eq!(
+            Overrides::from_toml(&section),
+            Ok(overrides["alice"].clone())
+        );
+
+        let (overrides, errors) = sections("");
+        assert!(overrides.is_empty());
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut config = Config::default();
+        Overrides {
+            free_tries: Some(0),
+            delay_curve: Some(DelayCurve::Fibonacci),
+            ..Overrides::default()
+        }
+        .apply(&mut config);
+
+        assert_eq!(config.free_tries, 0);
+        assert_eq!(config.delay_curve, DelayCurve::Fibonacci);
+        assert_eq!(
+            config.base_delay_seconds,
+            Config::default().base_delay_seconds
+        );
+    }
+
+    #[test]
+    fn test_most_specific_group() {
+        let group = |free_tries| Overrides {
+            free_tries: Some(free_tries),
+            ..Overrides::default()
+        };
+        let groups: HashMap<String, Overrides> = [
+            ("users".to_string(), group(1)),
+            ("wheel".to_string(), group(2)),
+            ("admins".to_string(), group(3)),
+        ]
+        .into_iter()
+        .collect();
+        let membership = |name: &str, primary, members| GroupMembership {
+            name: name.to_string(),
+            primary,
+            members,
+        };
+
+        // the primary group wins
+        let memberships = [
+            membership("wheel", false, 2),
+            membership("users", true, 100),
+        ];
+        assert_eq!(most_specific_group(&groups, &memberships), Some(&group(1)));
+
+        // otherwise the smallest group wins
+        let memberships = [
+            membership("alice", true, 0),
+            membership("users", false, 100),
+            membership("wheel", false, 2),
+            membership("admins", false, 5),
+        ];
+        assert_eq!(most_specific_group(&groups, &memberships), Some(&group(2)));
+
+        let memberships = [membership("alice", true, 0)];
+        assert_eq!(most_specific_group(&groups, &memberships), None);
+    }
+}
@@ -0,0 +1,363 @@
+//! # Schema Module
+//!
+//! The `schema` module defines the on-disk format of the tally file. The file is a TOML document
+//! with a `schema_version`, a `[Fails]` table, an optional `[Lock]` table and an optional list of
+//! `[[History]]` tables.
+//!
+//! ```toml
+//! schema_version = 3
+//!
+//! [Fails]
+//! count = 7
+//! instant = "2024-02-04T00:42:42.983474044Z"
+//! unlock_instant = "2024-02-04T00:43:12.983474044Z"
+//!
+//! [Lock]
+//! instant = "2024-02-04T08:00:00Z"
+//! until = "2024-02-05T08:00:00Z"
+//! reason = "leaver"
+//!
+//! [[History]]
+//! instant = "2024-02-04T00:42:42.983474044Z"
+//! service = "sshd"
+//! rhost = "192.0.2.1"
+//! ```
+//!
+//! ## Versions
+//!
+//! - `1`: Files written before the schema was versioned. They have no `schema_version` key and
+//!   are migrated to the current version when they are loaded.
+//! - `2`: Files without the `[Lock]` table. They are loaded as the current version.
+//! - `3`: The current version, adds the `[Lock]` table of administrative lockouts.
+//!
+//! ## License
+//!
+//! pam-authramp
+//! Copyright (C) 2023 github.com/34N0
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fmt;
+
+use crate::settings::PamItems;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// The schema version written by this module.
+pub const SCHEMA_VERSION: i64 = 3;
+
+/// The last schema version without the `[Lock]` table.
+const NO_LOCK_SCHEMA_VERSION: i64 = 2;
+
+/// The schema version of tally files without a `schema_version` key.
+const LEGACY_SCHEMA_VERSION: i64 = 1;
+
+/// The `TallyFile` struct represents the content of a tally file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TallyFile {
+    /// Version of the tally file schema.
+    pub schema_version: i64,
+    /// The failures of the account.
+    #[serde(rename = "Fails")]
+    pub fails: FailsTable,
+    /// The lockout set by an administrator.
+    #[serde(rename = "Lock", default, skip_serializing_if = "Option::is_none")]
+    pub lock: Option<AdminLock>,
+    /// The most recent failure records, oldest first.
+    #[serde(rename = "History", default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<FailureRecord>,
+}
+
+/// The `FailsTable` struct represents the `[Fails]` table of a tally file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FailsTable {
+    /// Number of authentication failures.
+    pub count: i32,
+    /// Timestamp of the last authentication failure.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instant: Option<DateTime<Utc>>,
+    /// Time when the account will be unlocked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unlock_instant: Option<DateTime<Utc>>,
+    /// Timestamps of the failures within the `fail_interval` window.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub instants: Vec<DateTime<Utc>>,
+}
+
+/// The `AdminLock` struct represents the `[Lock]` table of a tally file, a lockout set with
+/// `authramp lock`. A successful login does not clear it, only `authramp reset` does.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdminLock {
+    /// Time the lock was set.
+    pub instant: DateTime<Utc>,
+    /// Time the account is unlocked. The account stays locked until it is reset if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<DateTime<Utc>>,
+    /// Why the account was locked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl AdminLock {
+    /// Returns the time until which the lock applies, `DateTime::<Utc>::MAX_UTC` if the account
+    /// stays locked until it is reset.
+    #[must_use]
+    pub fn locked_until(&self) -> DateTime<Utc> {
+        self.until.unwrap_or(DateTime::<Utc>::MAX_UTC)
+    }
+
+    /// Checks whether the lock applies at the given time.
+    ///
+    /// # Arguments
+    /// - `now`: The current time
+    #[must_use]
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.locked_until() > now
+    }
+}
+
+/// The `FailureRecord` struct describes a single authentication failure.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FailureRecord {
+    /// Timestamp of the failure.
+    pub instant: DateTime<Utc>,
+    /// PAM service that reported the failure.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    /// Remote host of the failed attempt (`PAM_RHOST`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rhost: Option<String>,
+    /// Terminal of the failed attempt (`PAM_TTY`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tty: Option<String>,
+    /// Remote user of the failed attempt (`PAM_RUSER`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ruser: Option<String>,
+}
+
+impl FailureRecord {
+    /// Creates a `FailureRecord` from the PAM items of the current authentication attempt.
+    ///
+    /// # Arguments
+    /// - `instant`: Timestamp of the failure
+    /// - `items`: PAM items of the failed attempt
+    #[must_use]
+    pub fn new(instant: DateTime<Utc>, items: &PamItems) -> Self {
+        FailureRecord {
+            instant,
+            service: items.service.clone(),
+            rhost: items.rhost.clone(),
+            tty: items.tty.clone(),
+            ruser: items.ruser.clone(),
+        }
+    }
+}
+
+/// The `SchemaError` enum represents the reasons a tally file cannot be loaded.
+#[derive(Debug)]
+pub enum SchemaError {
+    /// The file is not valid TOML or does not match the schema.
+    Parse(toml::de::Error),
+    /// The `schema_version` key is not an integer.
+    InvalidVersion,
+    /// The file was written by a newer, unknown schema version.
+    UnsupportedVersion(i64),
+    /// The file could not be serialized.
+    Serialize(toml::ser::Error),
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaError::Parse(e) => write!(f, "invalid tally file: {}", e.message()),
+            SchemaError::InvalidVersion => write!(f, "schema_version must be an integer"),
+            SchemaError::UnsupportedVersion(version) => {
+                write!(f, "unsupported tally schema version {version}")
+            }
+            SchemaError::Serialize(e) => write!(f, "could not serialize tally file: {e}"),
+        }
+    }
+}
+
+impl TallyFile {
+    /// Creates a `TallyFile` of the current schema version.
+    ///
+    /// # Arguments
+    /// - `fails`: The `[Fails]` table
+    /// - `history`: The failure records
+    #[must_use]
+    pub fn new(fails: FailsTable, history: Vec<FailureRecord>) -> Self {
+        TallyFile {
+            schema_version: SCHEMA_VERSION,
+            fails,
+            lock: None,
+            history,
+        }
+    }
+
+    /// Parses a tally file and migrates it to the current schema version.
+    ///
+    /// # Arguments
+    /// - `content`: The content of the tally file
+    ///
+    /// # Errors
+    /// Returns a `SchemaError` if the content is not a valid tally file of a known version.
+    pub fn parse(content: &str) -> Result<Self, SchemaError> {
+        let mut table: toml::Table = toml::from_str(content).map_err(SchemaError::Parse)?;
+
+        let version = match table.remove("schema_version") {
+            None => LEGACY_SCHEMA_VERSION,
+            Some(toml::Value::Integer(version)) => version,
+            Some(_) => return Err(SchemaError::InvalidVersion),
+        };
+
+        match version {
+            // version 1 only lacks the schema_version key, version 2 the optional [Lock] table
+            LEGACY_SCHEMA_VERSION | NO_LOCK_SCHEMA_VERSION | SCHEMA_VERSION => {
+                table.insert(
+                    "schema_version".to_string(),
+                    toml::Value::Integer(SCHEMA_VERSION),
+                );
+                toml::Value::Table(table)
+                    .try_into()
+                    .map_err(SchemaError::Parse)
+            }
+            other => Err(SchemaError::UnsupportedVersion(other)),
+        }
+    }
+
+    /// Serializes the tally file to TOML.
+    ///
+    /// # Errors
+    /// Returns a `SchemaError` if the tally file cannot be serialized.
+    pub fn to_toml(&self) -> Result<String, SchemaError> {
+        toml::to_string(self).map_err(SchemaError::Serialize)
+    }
+}
+
+// Unit Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_legacy_tally_file() {
+        let tally_file = TallyFile::parse(
+            r#"
+            [Fails]
+            count = 42
+            instant = "2023-01-01T00:00:00Z"
+            unlock_instant = "2023-01-02 00:00:00.123 UTC"
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(tally_file.schema_version, SCHEMA_VERSION);
+        assert_eq!(tally_file.fails.count, 42);
+        assert_eq!(
+            tally_file.fails.instant.unwrap(),
+            DateTime::parse_from_rfc3339("2023-01-01T00:00:00Z").unwrap()
+        );
+        assert_eq!(
+            tally_file.fails.unlock_instant.unwrap(),
+            DateTime::parse_from_rfc3339("2023-01-02T00:00:00.123Z").unwrap()
+        );
+        assert!(tally_file.history.is_empty());
+    }
+
+    #[test]
+    fn test_parse_reset_legacy_tally_file() {
+        let tally_file = TallyFile::parse("[Fails]\ncount = 0").unwrap();
+        assert_eq!(tally_file.fails, FailsTable::default());
+    }
+
+    #[test]
+    fn test_parse_tally_file_without_lock() {
+        let tally_file = TallyFile::parse("schema_version = 2\n[Fails]\ncount = 3").unwrap();
+        assert_eq!(tally_file.schema_version, SCHEMA_VERSION);
+        assert_eq!(tally_file.fails.count, 3);
+        assert_eq!(tally_file.lock, None);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let tally_file = TallyFile::new(
+            FailsTable {
+                count: 2,
+                instant: Some(Utc::now()),
+                unlock_instant: Some(Utc::now()),
+                instants: vec![Utc::now(), Utc::now()],
+            },
+            vec![FailureRecord {
+                instant: Utc::now(),
+                service: Some("sshd".to_string()),
+                rhost: Some("192.0.2.1".to_string()),
+                tty: None,
+                ruser: Some("\"quoted\"".to_string()),
+            }],
+        );
+
+        let toml_str = tally_file.to_toml().unwrap();
+        assert!(toml_str.contains("schema_version = 3"));
+        assert!(!toml_str.contains("[Lock]"));
+        assert_eq!(TallyFile::parse(&toml_str).unwrap(), tally_file);
+
+        let tally_file = TallyFile {
+            lock: Some(AdminLock {
+                instant: Utc::now(),
+                until: None,
+                reason: Some("leaver".to_string()),
+            }),
+            ..tally_file
+        };
+        let toml_str = tally_file.to_toml().unwrap();
+        assert!(toml_str.contains("[Lock]"));
+        assert_eq!(TallyFile::parse(&toml_str).unwrap(), tally_file);
+    }
+
+    #[test]
+    fn test_parse_corrupt_tally_files() {
+        // not TOML
+        assert!(matches!(
+            TallyFile::parse("[Fails"),
+            Err(SchemaError::Parse(_))
+        ));
+        // missing [Fails] table
+        assert!(matches!(
+            TallyFile::parse("count = 3"),
+            Err(SchemaError::Parse(_))
+        ));
+        // wrong types are no longer silently defaulted
+        assert!(matches!(
+            TallyFile::parse("[Fails]\ncount = \"three\""),
+            Err(SchemaError::Parse(_))
+        ));
+        assert!(matches!(
+            TallyFile::parse("[Fails]\ncount = 3\ninstant = \"yesterday\""),
+            Err(SchemaError::Parse(_))
+        ));
+        assert!(matches!(
+            TallyFile::parse("schema_version = \"2\"\n[Fails]\ncount = 3"),
+            Err(SchemaError::InvalidVersion)
+        ));
+        assert!(matches!(
+            TallyFile::parse("schema_version = 4\n[Fails]\ncount = 3"),
+            Err(SchemaError::UnsupportedVersion(4))
+        ));
+    }
+}
@@ -26,13 +26,127 @@
 //! along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use crate::actions::Actions;
-use crate::config::Config;
-use pam::{PamFlag, PamHandle, PamResultCode};
-use std::collections::HashMap;
+use crate::config::{Config, CONFIG_KEYS};
+use crate::track::parse_rhost;
+use pam::items::{RHost, RUser, Service, Tty};
+use pam::{PamFlag, PamHandle, PamResultCode, PAM_SILENT};
 use std::ffi::CStr;
 
 use uzers::User;
 
+// PamItems struct holds the string items describing where an authentication attempt comes from
+// and the locale of the user
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PamItems {
+    // PAM_SERVICE
+    pub service: Option<String>,
+    // PAM_RHOST
+    pub rhost: Option<String>,
+    // PAM_TTY
+    pub tty: Option<String>,
+    // PAM_RUSER
+    pub ruser: Option<String>,
+    // Locale of the PAM environment, from LC_ALL, LC_MESSAGES or LANG
+    pub locale: Option<String>,
+}
+
+impl PamItems {
+    /// Reads the service, remote host, tty and remote user items and the locale of the PAM
+    /// environment from the PAM handle. Items that are not set or not valid UTF-8 are `None`.
+    ///
+    /// # Arguments
+    ///
+    /// * `pam_h`: A reference to the `PamHandle`.
+    ///
+    /// # Returns
+    ///
+    /// The `PamItems` read from the handle.
+    #[must_use]
+    pub fn from_handle(pam_h: &PamHandle) -> Self {
+        fn to_string(item: Option<&CStr>) -> Option<String> {
+            item.and_then(|i| i.to_str().ok())
+                .filter(|i| !i.is_empty())
+                .map(str::to_string)
+        }
+
+        PamItems {
+            service: to_string(pam_h.get_item::<Service>().ok().flatten().map(|i| i.0)),
+            rhost: to_string(pam_h.get_item::<RHost>().ok().flatten().map(|i| i.0)),
+            tty: to_string(pam_h.get_item::<Tty>().ok().flatten().map(|i| i.0)),
+            ruser: to_string(pam_h.get_item::<RUser>().ok().flatten().map(|i| i.0)),
+            locale: ["LC_ALL", "LC_MESSAGES", "LANG"]
+                .iter()
+                .filter_map(|name| pam_h.getenv(name))
+                .find(|locale| !locale.is_empty()),
+        }
+    }
+}
+
+// ModuleArgs struct holds the module arguments of the pam.d line
+#[derive(Debug, Default, PartialEq)]
+struct ModuleArgs {
+    // Action word
+    action: Option<Actions>,
+    // Path of the configuration file
+    conf: Option<String>,
+    // Configuration values
+    values: toml::value::Table,
+    // Arguments that are not understood
+    unknown: Vec<String>,
+}
+
+impl ModuleArgs {
+    /// Parses the module arguments.
+    ///
+    /// # Arguments
+    ///
+    /// * `args`: The PAM module arguments.
+    ///
+    /// # Returns
+    ///
+    /// The `ModuleArgs`. Values are parsed as TOML values, e.g. `free_tries=3` is an integer,
+    /// and taken as string if they are no valid TOML value.
+    fn parse(args: &[&CStr]) -> Self {
+        let mut module_args = ModuleArgs::default();
+
+        for carg in args {
+            let Ok(arg) = carg.to_str() else {
+                module_args.unknown.push(carg.to_string_lossy().to_string());
+                continue;
+            };
+
+            let (key, value) = match arg.split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None => (arg, None),
+            };
+
+            match (key, value) {
+                ("preauth", None) => module_args.action = Some(Actions::PREAUTH),
+                ("authsucc", None) => module_args.action = Some(Actions::AUTHSUCC),
+                ("authfail", None) => module_args.action = Some(Actions::AUTHFAIL),
+                ("conf", Some(path)) if !path.is_empty() => {
+                    module_args.conf = Some(path.to_string());
+                }
+                (key, value) if CONFIG_KEYS.contains(&key) => {
+                    let value = match value {
+                        None => toml::Value::Boolean(true),
+                        Some(value) => {
+                            toml::from_str::<toml::value::Table>(&format!("v = {value}"))
+                                .ok()
+                                .and_then(|mut table| table.remove("v"))
+                                .unwrap_or_else(|| toml::Value::String(value.to_string()))
+                        }
+                    };
+                    module_args.values.insert(key.to_string(), value);
+                }
+                _ => module_args.unknown.push(arg.to_string()),
+            }
+        }
+
+        module_args
+    }
+}
+
 // Settings struct represents the configuration loaded from default values, configuration file and parameters
 #[derive(Debug)]
 pub struct Settings<'a> {
@@ -42,6 +156,8 @@ pub struct Settings<'a> {
     pub action: Option<Actions>,
     // PAM user
     pub user: Option<User>,
+    // PAM items
+    pub items: PamItems,
     // Config
     pub config: Config,
 }
@@ -52,6 +168,7 @@ impl Default for Settings<'_> {
         Settings {
             action: Some(Actions::AUTHSUCC),
             user: None,
+            items: PamItems::default(),
             pam_hook: "auth",
             config: Config::load_file(None, None),
         }
@@ -60,16 +177,23 @@ impl Default for Settings<'_> {
 
 impl Settings<'_> {
     /// Constructs a `Settings` instance based on input parameters, including user
-    /// information, PAM flags, and an optional configuration file path.
+    /// information, PAM flags, and the module arguments.
+    ///
+    /// Module arguments are the action (`preauth`, `authfail` or `authsucc`), `conf=<path>` to
+    /// load another configuration file and `key=value` pairs that replace the values of the
+    /// `[Configuration]` table. A `key` without value sets a boolean option like `debug`, `quiet`,
+    /// `silent` or `countdown`. Unknown arguments are logged and ignored.
     ///
     /// # Arguments
     ///
     /// * `user`: An optional `User` instance representing the user associated with
     ///   the PAM session.
     /// * `args`: A vector of `CStr` references representing the PAM module arguments.
-    /// * `_flags`: PAM flags indicating the context of the PAM operation (unused).
-    /// * `config_file`: An optional `PathBuf` specifying the path to the TOML file. If
-    ///   not provided, the default configuration file path is used.
+    /// * `flags`: PAM flags indicating the context of the PAM operation. `PAM_SILENT` suppresses
+    ///   messages to the user.
+    /// * `pam_hook`: Name of the PAM hook.
+    /// * `pam_h`: An optional mutable reference to the `PamHandle`, used to read the PAM items
+    ///   and for logging.
     ///
     /// # Returns
     ///
@@ -82,41 +206,58 @@ impl Settings<'_> {
     pub fn build<'a>(
         user: Option<User>,
         args: &[&CStr],
-        _flags: PamFlag,
+        flags: PamFlag,
         pam_hook: &'a str,
-        pam_h: Option<&mut PamHandle>,
+        mut pam_h: Option<&mut PamHandle>,
     ) -> Result<Settings<'a>, PamResultCode> {
-        // Init default settings.
-        let mut settings = Settings {
-            config: Config::load_file(None, pam_h),
-            ..Settings::default()
-        };
+        // Read PAM items
+        let items = pam_h
+            .as_deref()
+            .map(PamItems::from_handle)
+            .unwrap_or_default();
 
-        // create possible action collection
-        let action_map: HashMap<&str, Actions> = [
-            ("preauth", Actions::PREAUTH),
-            ("authsucc", Actions::AUTHSUCC),
-            ("authfail", Actions::AUTHFAIL),
-        ]
-        .iter()
-        .copied()
-        .collect();
+        // Parse module arguments
+        let args = ModuleArgs::parse(args);
 
-        // map argument to action
-        settings.action = args.iter().find_map(|&carg| {
-            carg.to_str()
-                .ok()
-                .and_then(|arg| action_map.get(arg).copied())
-        });
+        let mut config = Config::load(args.conf.as_deref(), &args.values, pam_h.as_deref_mut());
 
-        // set default action if none is provided
-        settings.action.get_or_insert(Actions::AUTHSUCC);
+        if let Some(pam_h) = pam_h.as_deref_mut() {
+            for arg in &args.unknown {
+                let _ = config.log(
+                    pam_h,
+                    pam::LogLevel::Warning,
+                    format!("Unknown module argument, ignoring it: {arg}"),
+                );
+            }
+        }
+
+        // the application asked for no messages
+        if flags & PAM_SILENT != 0 {
+            config.silent = true;
+        }
 
         // get user
-        settings.user = Some(user.ok_or(PamResultCode::PAM_USER_UNKNOWN)?);
+        let user = user.ok_or(PamResultCode::PAM_USER_UNKNOWN)?;
+
+        // apply the service, user and group overrides
+        config.apply_overrides(&user, items.service.as_deref());
+
+        let settings = Settings {
+            pam_hook,
+            // map argument to action, set default action if none is provided
+            action: Some(args.action.unwrap_or(Actions::AUTHSUCC)),
+            user: Some(user),
+            items,
+            config,
+        };
 
-        // pam hook
-        settings.pam_hook = pam_hook;
+        if let Some(pam_h) = pam_h {
+            let _ = settings.config.log(
+                pam_h,
+                pam::LogLevel::Debug,
+                format!("Effective settings: {settings:?}"),
+            );
+        }
 
         Ok(settings)
     }
@@ -148,6 +289,40 @@ impl Settings<'_> {
     pub fn get_user(&self) -> Result<&User, PamResultCode> {
         self.user.as_ref().ok_or(PamResultCode::PAM_USER_UNKNOWN)
     }
+
+    /// Checks whether the authentication attempt comes from a trusted source, i.e. a remote host
+    /// in `trusted_networks` or a terminal in `trusted_ttys`.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the attempt is exempt from the ramp.
+    #[must_use]
+    pub fn is_trusted_source(&self) -> bool {
+        let trusted_rhost = self
+            .items
+            .rhost
+            .as_deref()
+            .and_then(parse_rhost)
+            .is_some_and(|ip| {
+                self.config
+                    .trusted_networks
+                    .iter()
+                    .any(|network| network.contains(ip))
+            });
+
+        let trusted_tty = self.items.tty.as_deref().is_some_and(|tty| {
+            let tty = tty.strip_prefix("/dev/").unwrap_or(tty);
+            self.config
+                .trusted_ttys
+                .iter()
+                .any(|pattern| match pattern.strip_suffix('*') {
+                    Some(prefix) => tty.starts_with(prefix),
+                    None => tty == pattern,
+                })
+        });
+
+        trusted_rhost || trusted_tty
+    }
 }
 
 // Unit Tests
@@ -184,4 +359,115 @@ mod tests {
         assert!(result.is_err());
         assert_eq!(result.unwrap_err(), PamResultCode::PAM_USER_UNKNOWN);
     }
+
+    #[test]
+    fn test_parse_module_args() {
+        let cargs: Vec<std::ffi::CString> = [
+            "authfail",
+            "conf=/etc/security/authramp-ssh.conf",
+            "free_tries=3",
+            "tally_dir=/srv/authramp",
+            "debug",
+            "countdown=false",
+            "track=[\"user\",\"rhost\"]",
+            "nullok",
+            "conf=",
+        ]
+        .iter()
+        .map(|arg| std::ffi::CString::new(*arg).unwrap())
+        .collect();
+        let args: Vec<&CStr> = cargs.iter().map(std::ffi::CString::as_c_str).collect();
+
+        let module_args = ModuleArgs::parse(&args);
+        assert_eq!(module_args.action, Some(Actions::AUTHFAIL));
+        assert_eq!(
+            module_args.conf.as_deref(),
+            Some("/etc/security/authramp-ssh.conf")
+        );
+        assert_eq!(
+            module_args.values.get("free_tries"),
+            Some(&toml::Value::Integer(3))
+        );
+        assert_eq!(
+            module_args.values.get("tally_dir"),
+            Some(&toml::Value::String("/srv/authramp".to_string()))
+        );
+        assert_eq!(
+            module_args.values.get("debug"),
+            Some(&toml::Value::Boolean(true))
+        );
+        assert_eq!(
+            module_args.values.get("countdown"),
+            Some(&toml::Value::Boolean(false))
+        );
+        assert!(module_args.values.get("track").unwrap().is_array());
+        assert_eq!(module_args.unknown, vec!["nullok", "conf="]);
+    }
+
+    #[test]
+    fn test_build_settings_module_args() {
+        let temp_dir = tempdir::TempDir::new("test_build_settings_module_args").unwrap();
+        let conf_file_path = temp_dir.path().join("config.conf");
+        std::fs::write(
+            &conf_file_path,
+            "[Configuration]\nfree_tries = 10\nbase_delay_seconds = 15",
+        )
+        .unwrap();
+
+        let conf_arg =
+            std::ffi::CString::new(format!("conf={}", conf_file_path.display())).unwrap();
+        let args = [c"preauth", conf_arg.as_c_str(), c"free_tries=3", c"silent"];
+
+        let settings = Settings::build(
+            Some(User::new(9999, "test_user", 9999)),
+            &args,
+            0,
+            "test",
+            None,
+        )
+        .unwrap();
+        assert_eq!(settings.action, Some(Actions::PREAUTH));
+        // arguments win over the file
+        assert_eq!(settings.config.free_tries, 3);
+        assert_eq!(settings.config.base_delay_seconds, 15);
+        assert!(settings.config.silent);
+
+        let settings = Settings::build(
+            Some(User::new(9999, "test_user", 9999)),
+            &args[..2],
+            PAM_SILENT,
+            "test",
+            None,
+        )
+        .unwrap();
+        assert_eq!(settings.config.free_tries, 10);
+        assert!(settings.config.silent);
+    }
+
+    #[test]
+    fn test_is_trusted_source() {
+        let settings = |rhost: Option<&str>, tty: Option<&str>| Settings {
+            items: PamItems {
+                rhost: rhost.map(str::to_string),
+                tty: tty.map(str::to_string),
+                ..PamItems::default()
+            },
+            config: Config {
+                trusted_networks: vec!["10.0.0.0/8".parse().unwrap(), "::1".parse().unwrap()],
+                trusted_ttys: vec!["tty1".to_string(), "ttyS*".to_string()],
+                ..Config::default()
+            },
+            ..Settings::default()
+        };
+
+        assert!(settings(Some("10.1.2.3"), None).is_trusted_source());
+        assert!(settings(Some("::ffff:10.1.2.3"), None).is_trusted_source());
+        assert!(settings(Some("::1"), Some("pts/0")).is_trusted_source());
+        assert!(settings(None, Some("/dev/tty1")).is_trusted_source());
+        assert!(settings(None, Some("ttyS0")).is_trusted_source());
+
+        assert!(!settings(Some("192.0.2.1"), Some("pts/0")).is_trusted_source());
+        assert!(!settings(Some("bastion"), Some("tty10")).is_trusted_source());
+        assert!(!settings(None, None).is_trusted_source());
+    }
 }
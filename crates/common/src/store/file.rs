@@ -0,0 +1,287 @@
+//! # File Store Module
+//!
+//! The `file` module implements the default `TallyStore` backend, which keeps one TOML tally file
+//! per user in `tally_dir`.
+//!
+//! Updates hold an exclusive `flock` on the tally file for the whole read-modify-write cycle and
+//! replace the file atomically, so concurrent authentication attempts cannot lose failures.
+//!
+//! ## License
+//!
+//! pam-authramp
+//! Copyright (C) 2023 github.com/34N0
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Write},
+    os::unix::{
+        fs::{MetadataExt, OpenOptionsExt, PermissionsExt},
+        io::AsRawFd,
+    },
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+
+use super::{create_tally_dir, StoreError, TallyLock, TallyStore, TALLY_FILE_MODE};
+use crate::schema::TallyFile;
+
+/// Maximum length of a file name in bytes.
+const NAME_MAX: usize = 255;
+
+/// Length the name of the temporary file adds to the name of the tally file.
+const TEMP_NAME_EXTRA_LEN: usize = ".".len() + ".tmp".len();
+
+/// The `FileStore` struct stores one tally file per user in the tally directory.
+#[derive(Debug)]
+pub struct FileStore {
+    // Directory containing the tally files.
+    tally_dir: PathBuf,
+}
+
+impl FileStore {
+    /// Creates a `FileStore` for the given tally directory.
+    ///
+    /// # Arguments
+    /// - `tally_dir`: Directory containing the tally files
+    #[must_use]
+    pub fn new(tally_dir: &Path) -> Self {
+        FileStore {
+            tally_dir: tally_dir.to_path_buf(),
+        }
+    }
+
+    /// Returns the path of the tally file of a user.
+    ///
+    /// # Arguments
+    /// - `user`: Name of the user
+    ///
+    /// # Errors
+    ///
+    /// Returns an `io::Error` if the name of the tally file or of its temporary file exceeds
+    /// `NAME_MAX`.
+    pub fn tally_file(&self, user: &str) -> io::Result<PathBuf> {
+        if user.len() + TEMP_NAME_EXTRA_LEN > NAME_MAX {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("tally name {user} is too long for a file name"),
+            ));
+        }
+        Ok(self.tally_dir.join(user))
+    }
+
+    /// Atomically replaces the tally file with the given content.
+    ///
+    /// The content is written to a temporary file in the tally directory, flushed to disk and
+    /// renamed over the tally file. The tally file is owned by the effective user of the
+    /// process and only accessible by it.
+    ///
+    /// # Arguments
+    /// - `tally_file`: A reference to the tally file `Path`.
+    /// - `content`: The TOML content of the tally file.
+    fn write_tally_file(&self, tally_file: &Path, content: &str) -> io::Result<()> {
+        let mut temp_name = OsString::from(".");
+        temp_name.push(tally_file.file_name().unwrap_or_default());
+        temp_name.push(".tmp");
+        let temp_file = self.tally_dir.join(temp_name);
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(TALLY_FILE_MODE)
+            .open(&temp_file)?;
+        file.write_all(content.as_bytes())?;
+        fs::set_permissions(&temp_file, fs::Permissions::from_mode(TALLY_FILE_MODE))?;
+
+        file.sync_all()?;
+        fs::rename(&temp_file, tally_file)?;
+        File::open(&self.tally_dir)?.sync_all()
+    }
+}
+
+impl TallyStore for FileStore {
+    /// Opens the tally file and acquires an exclusive lock on it.
+    ///
+    /// Because updates replace the tally file, the lock is retried until it is held on the
+    /// file that is currently linked in the tally directory. An empty tally file is created as
+    /// lock target if `create` is set.
+    fn lock(&self, user: &str, create: bool) -> Result<Option<TallyLock<'_>>, StoreError> {
+        let tally_file = self.tally_file(user)?;
+
+        if create {
+            create_tally_dir(&self.tally_dir)?;
+        }
+
+        loop {
+            let file = match OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(create)
+                .truncate(false)
+                .mode(TALLY_FILE_MODE)
+                .open(&tally_file)
+            {
+                Ok(file) => file,
+                Err(e) if !create && e.kind() == ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+
+            // the file might have been replaced while waiting for the lock
+            let locked_meta = file.metadata()?;
+            match fs::metadata(&tally_file) {
+                Ok(meta) if meta.dev() == locked_meta.dev() && meta.ino() == locked_meta.ino() => {
+                    return Ok(Some(TallyLock::File(file)));
+                }
+                Err(e) if !create && e.kind() == ErrorKind::NotFound => return Ok(None),
+                // retry on the file that replaced it
+                _ => {}
+            }
+        }
+    }
+
+    /// Loads the tally file of a user. An empty file is a lock target created by a concurrent
+    /// update and does not hold a tally yet.
+    fn load(&self, user: &str) -> Result<Option<TallyFile>, StoreError> {
+        let tally_file = self.tally_file(user)?;
+
+        let content = match fs::read_to_string(&tally_file) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        if content.is_empty() {
+            return Ok(None);
+        }
+
+        TallyFile::parse(&content)
+            .map(Some)
+            .map_err(|error| StoreError::Corrupt {
+                error,
+                modified: fs::metadata(&tally_file)
+                    .and_then(|meta| meta.modified())
+                    .map(DateTime::<Utc>::from)
+                    .ok(),
+            })
+    }
+
+    fn save(&self, user: &str, tally: &TallyFile) -> Result<(), StoreError> {
+        let content = tally
+            .to_toml()
+            .map_err(|e| StoreError::Io(io::Error::new(ErrorKind::InvalidData, e.to_string())))?;
+        Ok(self.write_tally_file(&self.tally_file(user)?, &content)?)
+    }
+
+    fn clear(&self, user: &str) -> Result<bool, StoreError> {
+        match fs::remove_file(self.tally_file(user)?) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Lists the tally files in the tally directory. Hidden files are temporary files of
+    /// concurrent updates and are skipped.
+    fn list(&self) -> Result<Vec<String>, StoreError> {
+        let entries = match fs::read_dir(&self.tally_dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut users = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            if let Some(name) = entry.file_name().to_str() {
+                if !name.starts_with('.') {
+                    users.push(name.to_string());
+                }
+            }
+        }
+        users.sort();
+
+        Ok(users)
+    }
+}
+
+// Unit Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::FailsTable;
+    use crate::store::temp_tally_dir;
+
+    #[test]
+    fn test_file_store_roundtrip() {
+        let temp_dir = temp_tally_dir("test_file_store_roundtrip");
+        let tally_dir = temp_dir.path().join("authramp");
+        let store = FileStore::new(&tally_dir);
+
+        assert!(store.load("alice").unwrap().is_none());
+        assert!(store.list().unwrap().is_empty());
+        assert!(store.lock("alice", false).unwrap().is_none());
+
+        let tally = TallyFile::new(
+            FailsTable {
+                count: 3,
+                instant: Some(Utc::now()),
+                ..FailsTable::default()
+            },
+            Vec::new(),
+        );
+        {
+            let _lock = store.lock("alice", true).unwrap();
+            // the empty lock target does not hold a tally
+            assert!(store.load("alice").unwrap().is_none());
+            store.save("alice", &tally).unwrap();
+        }
+
+        assert_eq!(store.load("alice").unwrap(), Some(tally));
+        assert_eq!(store.list().unwrap(), vec!["alice".to_string()]);
+
+        assert!(store.clear("alice").unwrap());
+        assert!(!store.clear("alice").unwrap());
+        assert!(!store.tally_file("alice").unwrap().exists());
+
+        let long_name = "a".repeat(NAME_MAX);
+        assert!(store.tally_file(&long_name).is_err());
+        assert!(store.lock(&long_name, true).is_err());
+    }
+
+    #[test]
+    fn test_file_store_corrupt_tally() {
+        let temp_dir = temp_tally_dir("test_file_store_corrupt_tally");
+        let store = FileStore::new(temp_dir.path());
+
+        fs::write(store.tally_file("bob").unwrap(), "[Fails").unwrap();
+        assert!(matches!(
+            store.load("bob"),
+            Err(StoreError::Corrupt {
+                modified: Some(_),
+                ..
+            })
+        ));
+    }
+}
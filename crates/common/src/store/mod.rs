@@ -0,0 +1,435 @@
+//! # Store Module
+//!
+//! The `store` module persists the tallies of the `AuthRamp` PAM module. The `TallyStore` trait
+//! abstracts the storage backend, which is selected with the `storage` setting:
+//!
+//! - `file` (default): One versioned TOML tally file per user in `tally_dir`.
+//! - `sqlite`: A single `SQLite` database `tally.db` in `tally_dir`. Hosts with thousands of users
+//!   don't fill a directory with tiny files.
+//!
+//! Both backends store the tally in the format defined by the `schema` module, so corrupt tallies
+//! are detected the same way.
+//!
+//! ## Tally Directory
+//!
+//! The tally directory is created with mode `0700` and the tallies with mode `0600`. A store is
+//! only opened if the tally directory is owned by root and not writable by its group or others,
+//! since anyone who can write to it can reset or forge lockouts.
+//!
+//! ## License
+//!
+//! pam-authramp
+//! Copyright (C) 2023 github.com/34N0
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+pub mod file;
+pub mod sqlite;
+
+use std::{
+    fmt,
+    fs::{self, DirBuilder, File},
+    io,
+    os::unix::fs::{DirBuilderExt, MetadataExt},
+    path::Path,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::config::{Config, StorageBackend};
+use crate::lockout;
+use crate::schema::{FailsTable, FailureRecord, SchemaError, TallyFile};
+
+pub use file::FileStore;
+pub use sqlite::SqliteStore;
+
+/// Mode of a newly created tally directory.
+const TALLY_DIR_MODE: u32 = 0o700;
+
+/// Mode of the tally files and the tally database.
+const TALLY_FILE_MODE: u32 = 0o600;
+
+/// Owner the tally directory must have, root unless the tests change it.
+static TALLY_DIR_OWNER: AtomicU32 = AtomicU32::new(0);
+
+/// An exclusive lock on the tally of a user. The lock is released when it is dropped.
+pub enum TallyLock<'a> {
+    /// `flock` on the tally file.
+    File(File),
+    /// Immediate transaction on the database, committed when dropped.
+    Sqlite(rusqlite::Transaction<'a>),
+}
+
+/// The `StoreError` enum represents the errors of a tally store.
+#[derive(Debug)]
+pub enum StoreError {
+    /// Reading or writing the tally failed.
+    Io(io::Error),
+    /// The database returned an error.
+    Sqlite(rusqlite::Error),
+    /// The tally directory cannot be trusted.
+    InsecureTallyDir(String),
+    /// The stored tally cannot be loaded.
+    Corrupt {
+        error: SchemaError,
+        // Time the corrupt tally was last written, if known.
+        modified: Option<DateTime<Utc>>,
+    },
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Io(e) => write!(f, "{e}"),
+            StoreError::Sqlite(e) => write!(f, "database error: {e}"),
+            StoreError::InsecureTallyDir(reason) => write!(f, "insecure tally directory: {reason}"),
+            StoreError::Corrupt { error, .. } => write!(f, "{error}"),
+        }
+    }
+}
+
+impl From<io::Error> for StoreError {
+    fn from(e: io::Error) -> Self {
+        StoreError::Io(e)
+    }
+}
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(e: rusqlite::Error) -> Self {
+        StoreError::Sqlite(e)
+    }
+}
+
+/// The `TallyStore` trait is implemented by the storage backends of the tallies.
+///
+/// Tallies are updated in a read-modify-write cycle: `lock`, `load`, `save` and drop the lock,
+/// as `update` and `record_failure` do.
+pub trait TallyStore {
+    /// Acquires an exclusive lock on the tally of a user.
+    ///
+    /// # Arguments
+    /// - `user`: Name of the user
+    /// - `create`: Whether to prepare the storage for a new tally if there is none.
+    ///
+    /// # Returns
+    /// The `TallyLock`, or `None` if the user has no tally and `create` is not set.
+    ///
+    /// # Errors
+    /// Returns a `StoreError` if the lock cannot be acquired.
+    fn lock(&self, user: &str, create: bool) -> Result<Option<TallyLock<'_>>, StoreError>;
+
+    /// Loads the tally of a user.
+    ///
+    /// # Arguments
+    /// - `user`: Name of the user
+    ///
+    /// # Returns
+    /// The stored `TallyFile`, or `None` if the user has no tally.
+    ///
+    /// # Errors
+    /// Returns `StoreError::Corrupt` if the stored tally cannot be parsed, or another
+    /// `StoreError` if it cannot be read.
+    fn load(&self, user: &str) -> Result<Option<TallyFile>, StoreError>;
+
+    /// Replaces the tally of a user. Must only be called while holding the lock of the user.
+    ///
+    /// # Arguments
+    /// - `user`: Name of the user
+    /// - `tally`: The new tally
+    ///
+    /// # Errors
+    /// Returns a `StoreError` if the tally cannot be written.
+    fn save(&self, user: &str, tally: &TallyFile) -> Result<(), StoreError>;
+
+    /// Removes the tally of a user.
+    ///
+    /// # Arguments
+    /// - `user`: Name of the user
+    ///
+    /// # Returns
+    /// `true` if a tally was removed, `false` if the user had no tally.
+    ///
+    /// # Errors
+    /// Returns a `StoreError` if the tally cannot be removed.
+    fn clear(&self, user: &str) -> Result<bool, StoreError>;
+
+    /// Lists the users with a stored tally.
+    ///
+    /// # Returns
+    /// The sorted user names.
+    ///
+    /// # Errors
+    /// Returns a `StoreError` if the tallies cannot be listed.
+    fn list(&self) -> Result<Vec<String>, StoreError>;
+
+    /// Updates the tally of a user while holding its lock, so concurrent updates are not lost.
+    /// Creates the tally if the user has none.
+    ///
+    /// # Arguments
+    /// - `user`: Name of the user
+    /// - `update`: Called with the stored tally, returns the tally to save
+    ///
+    /// # Returns
+    /// The saved tally.
+    ///
+    /// # Errors
+    /// Returns a `StoreError` if the tally cannot be locked, read or written.
+    fn update(
+        &self,
+        user: &str,
+        update: &mut dyn FnMut(Option<TallyFile>) -> TallyFile,
+    ) -> Result<TallyFile, StoreError> {
+        let _lock = self.lock(user, true)?;
+        let tally = update(self.load(user)?);
+        self.save(user, &tally)?;
+        Ok(tally)
+    }
+
+    /// Records an authentication failure of a user, see `lockout::record_failure`.
+    ///
+    /// The PAM module updates the tally itself under the lock, as it applies `on_corrupt_tally`
+    /// to a tally that cannot be loaded.
+    ///
+    /// # Arguments
+    /// - `user`: Name of the user
+    /// - `config`: The `AuthRamp` configuration
+    /// - `record`: The failure
+    ///
+    /// # Returns
+    /// The saved tally.
+    ///
+    /// # Errors
+    /// Returns a `StoreError` if the tally cannot be locked, read or written.
+    fn record_failure(
+        &self,
+        user: &str,
+        config: &Config,
+        record: FailureRecord,
+    ) -> Result<TallyFile, StoreError> {
+        self.update(user, &mut |tally| {
+            let mut tally =
+                tally.unwrap_or_else(|| TallyFile::new(FailsTable::default(), Vec::new()));
+            lockout::record_failure(config, &mut tally, record.clone());
+            tally
+        })
+    }
+}
+
+/// Opens the tally store configured with the `storage` setting.
+///
+/// # Arguments
+/// - `config`: The `AuthRamp` configuration
+///
+/// # Errors
+/// Returns a `StoreError` if the tally directory is insecure or the store cannot be opened.
+pub fn open(config: &Config) -> Result<Box<dyn TallyStore>, StoreError> {
+    if config.tally_dir.exists() {
+        verify_tally_dir(&config.tally_dir)?;
+    }
+
+    Ok(match config.storage {
+        StorageBackend::File => Box::new(FileStore::new(&config.tally_dir)),
+        StorageBackend::Sqlite => Box::new(SqliteStore::open(&config.tally_dir)?),
+    })
+}
+
+/// Sets the owner the tally directory must have instead of root. Lets tests use temporary
+/// directories without running as root, the module and the cli never call it.
+///
+/// # Arguments
+/// - `uid`: The uid owning trusted tally directories
+#[doc(hidden)]
+pub fn set_tally_dir_owner(uid: u32) {
+    TALLY_DIR_OWNER.store(uid, Ordering::Relaxed);
+}
+
+/// Verifies that the tally directory is a directory owned by root and not writable by its group
+/// or others.
+///
+/// # Arguments
+/// - `tally_dir`: The tally directory
+///
+/// # Errors
+/// Returns `StoreError::InsecureTallyDir` if the directory cannot be trusted, or
+/// `StoreError::Io` if its metadata cannot be read.
+pub fn verify_tally_dir(tally_dir: &Path) -> Result<(), StoreError> {
+    let meta = fs::symlink_metadata(tally_dir)?;
+    let display = tally_dir.display();
+
+    if !meta.is_dir() {
+        return Err(StoreError::InsecureTallyDir(format!(
+            "{display} is not a directory"
+        )));
+    }
+
+    let owner = TALLY_DIR_OWNER.load(Ordering::Relaxed);
+    if meta.uid() != owner {
+        return Err(StoreError::InsecureTallyDir(format!(
+            "{display} is owned by uid {}, expected {}",
+            meta.uid(),
+            if owner == 0 {
+                "root".to_string()
+            } else {
+                format!("uid {owner}")
+            }
+        )));
+    }
+
+    if meta.mode() & 0o022 != 0 {
+        return Err(StoreError::InsecureTallyDir(format!(
+            "{display} is writable by its group or others"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Creates the tally directory with all intermediate directories if it does not exist and
+/// verifies it.
+///
+/// # Arguments
+/// - `tally_dir`: The tally directory
+///
+/// # Errors
+/// Returns a `StoreError` if the directory cannot be created or cannot be trusted.
+fn create_tally_dir(tally_dir: &Path) -> Result<(), StoreError> {
+    DirBuilder::new()
+        .recursive(true)
+        .mode(TALLY_DIR_MODE)
+        .create(tally_dir)?;
+    verify_tally_dir(tally_dir)
+}
+
+/// Creates a temporary tally directory, trusted without running as root.
+#[cfg(test)]
+pub(crate) fn temp_tally_dir(prefix: &str) -> tempdir::TempDir {
+    set_tally_dir_owner(uzers::get_effective_uid());
+    tempdir::TempDir::new(prefix).unwrap()
+}
+
+// Unit Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{chown, PermissionsExt};
+
+    #[test]
+    fn test_create_tally_dir() {
+        let temp_dir = temp_tally_dir("test_create_tally_dir");
+        let tally_dir = temp_dir.path().join("lib").join("authramp");
+
+        create_tally_dir(&tally_dir).unwrap();
+        let mode = fs::metadata(&tally_dir).unwrap().mode();
+        assert_eq!(mode & 0o777, TALLY_DIR_MODE);
+
+        // an existing directory keeps its permissions
+        fs::set_permissions(&tally_dir, fs::Permissions::from_mode(0o755)).unwrap();
+        create_tally_dir(&tally_dir).unwrap();
+        let mode = fs::metadata(&tally_dir).unwrap().mode();
+        assert_eq!(mode & 0o777, 0o755);
+    }
+
+    #[test]
+    fn test_verify_tally_dir() {
+        let temp_dir = temp_tally_dir("test_verify_tally_dir");
+        let tally_dir = temp_dir.path().join("authramp");
+        fs::create_dir(&tally_dir).unwrap();
+        verify_tally_dir(&tally_dir).unwrap();
+
+        // world-writable or group-writable
+        for mode in [0o777, 0o770] {
+            fs::set_permissions(&tally_dir, fs::Permissions::from_mode(mode)).unwrap();
+            assert!(matches!(
+                verify_tally_dir(&tally_dir),
+                Err(StoreError::InsecureTallyDir(_))
+            ));
+        }
+        fs::set_permissions(&tally_dir, fs::Permissions::from_mode(0o700)).unwrap();
+
+        // not a directory
+        let file = temp_dir.path().join("file");
+        fs::write(&file, "").unwrap();
+        assert!(matches!(
+            verify_tally_dir(&file),
+            Err(StoreError::InsecureTallyDir(_))
+        ));
+
+        // a symlink to a trusted directory
+        let link = temp_dir.path().join("link");
+        std::os::unix::fs::symlink(&tally_dir, &link).unwrap();
+        assert!(matches!(
+            verify_tally_dir(&link),
+            Err(StoreError::InsecureTallyDir(_))
+        ));
+
+        // owned by another user, only root can give the directory away
+        if uzers::get_effective_uid() == 0 {
+            chown(&tally_dir, Some(65534), None).unwrap();
+            assert!(matches!(
+                verify_tally_dir(&tally_dir),
+                Err(StoreError::InsecureTallyDir(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_open_refuses_insecure_tally_dir() {
+        let temp_dir = temp_tally_dir("test_open_refuses_insecure_tally_dir");
+        fs::set_permissions(temp_dir.path(), fs::Permissions::from_mode(0o777)).unwrap();
+
+        for storage in [StorageBackend::File, StorageBackend::Sqlite] {
+            let config = Config {
+                tally_dir: temp_dir.path().to_path_buf(),
+                storage,
+                ..Config::default()
+            };
+            assert!(matches!(
+                open(&config),
+                Err(StoreError::InsecureTallyDir(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_record_failure() {
+        let temp_dir = temp_tally_dir("test_record_failure");
+
+        for storage in [StorageBackend::File, StorageBackend::Sqlite] {
+            let config = Config {
+                tally_dir: temp_dir.path().join(format!("{storage:?}")),
+                storage,
+                ..Config::default()
+            };
+            let store = open(&config).unwrap();
+            let record = FailureRecord {
+                instant: Utc::now(),
+                service: Some("sshd".to_string()),
+                rhost: None,
+                tty: None,
+                ruser: None,
+            };
+
+            store
+                .record_failure("alice", &config, record.clone())
+                .unwrap();
+            let tally = store
+                .record_failure("alice", &config, record.clone())
+                .unwrap();
+            assert_eq!(tally.fails.count, 2);
+            assert_eq!(tally.history, vec![record.clone(), record]);
+            assert_eq!(store.load("alice").unwrap(), Some(tally));
+        }
+    }
+}
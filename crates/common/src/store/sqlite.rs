@@ -0,0 +1,216 @@
+//! # `SQLite` Store Module
+//!
+//! The `sqlite` module implements a `TallyStore` backend that keeps the tallies of all users in a
+//! single `SQLite` database `tally.db` in `tally_dir`.
+//!
+//! Each row holds the TOML tally of a user, see the `schema` module. Updates run in an immediate
+//! transaction, which serializes concurrent authentication attempts across processes.
+//!
+//! ## License
+//!
+//! pam-authramp
+//! Copyright (C) 2023 github.com/34N0
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::{
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, DropBehavior, OptionalExtension, Transaction};
+
+use super::{create_tally_dir, StoreError, TallyLock, TallyStore, TALLY_FILE_MODE};
+use crate::schema::TallyFile;
+
+/// File name of the database in the tally directory.
+pub const DATABASE_FILE: &str = "tally.db";
+
+/// How long to wait for a concurrent update to release the database.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The `SqliteStore` struct stores the tallies of all users in a single `SQLite` database.
+#[derive(Debug)]
+pub struct SqliteStore {
+    // Path of the database file.
+    path: PathBuf,
+    // Connection to the database.
+    conn: Connection,
+}
+
+impl SqliteStore {
+    /// Opens or creates the tally database in the given tally directory.
+    ///
+    /// # Arguments
+    /// - `tally_dir`: Directory containing the database
+    ///
+    /// # Errors
+    /// Returns a `StoreError` if the tally directory is insecure or the database cannot be
+    /// created.
+    pub fn open(tally_dir: &Path) -> Result<Self, StoreError> {
+        create_tally_dir(tally_dir)?;
+
+        let path = tally_dir.join(DATABASE_FILE);
+        let created = !path.exists();
+        let conn = Connection::open(&path)?;
+        if created {
+            fs::set_permissions(&path, fs::Permissions::from_mode(TALLY_FILE_MODE))?;
+        }
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS tallies (
+                user TEXT PRIMARY KEY NOT NULL,
+                tally TEXT NOT NULL,
+                modified INTEGER NOT NULL
+            )",
+        )?;
+
+        Ok(SqliteStore { path, conn })
+    }
+
+    /// Returns the path of the database file.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl TallyStore for SqliteStore {
+    /// Starts an immediate transaction, which blocks other writers until the lock is dropped.
+    /// Rows are created on `save`, so a lock is returned even if the user has no tally.
+    fn lock(&self, _user: &str, _create: bool) -> Result<Option<TallyLock<'_>>, StoreError> {
+        let mut transaction =
+            Transaction::new_unchecked(&self.conn, rusqlite::TransactionBehavior::Immediate)?;
+        transaction.set_drop_behavior(DropBehavior::Commit);
+        Ok(Some(TallyLock::Sqlite(transaction)))
+    }
+
+    fn load(&self, user: &str) -> Result<Option<TallyFile>, StoreError> {
+        let row: Option<(String, i64)> = self
+            .conn
+            .query_row(
+                "SELECT tally, modified FROM tallies WHERE user = ?1",
+                params![user],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+
+        row.map(|(content, modified)| {
+            TallyFile::parse(&content).map_err(|error| StoreError::Corrupt {
+                error,
+                modified: DateTime::<Utc>::from_timestamp(modified, 0),
+            })
+        })
+        .transpose()
+    }
+
+    fn save(&self, user: &str, tally: &TallyFile) -> Result<(), StoreError> {
+        let content = tally.to_toml().map_err(|e| {
+            StoreError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                e.to_string(),
+            ))
+        })?;
+        self.conn.execute(
+            "INSERT OR REPLACE INTO tallies (user, tally, modified) VALUES (?1, ?2, ?3)",
+            params![user, content, Utc::now().timestamp()],
+        )?;
+        Ok(())
+    }
+
+    fn clear(&self, user: &str) -> Result<bool, StoreError> {
+        let removed = self
+            .conn
+            .execute("DELETE FROM tallies WHERE user = ?1", params![user])?;
+        Ok(removed > 0)
+    }
+
+    fn list(&self) -> Result<Vec<String>, StoreError> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT user FROM tallies ORDER BY user")?;
+        let users = statement
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(users)
+    }
+}
+
+// Unit Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::FailsTable;
+    use crate::store::temp_tally_dir;
+
+    #[test]
+    fn test_sqlite_store_roundtrip() {
+        let temp_dir = temp_tally_dir("test_sqlite_store_roundtrip");
+        let tally_dir = temp_dir.path().join("authramp");
+        let store = SqliteStore::open(&tally_dir).unwrap();
+        assert!(store.path().exists());
+
+        assert!(store.load("alice").unwrap().is_none());
+
+        let tally = TallyFile::new(
+            FailsTable {
+                count: 3,
+                instant: Some(Utc::now()),
+                ..FailsTable::default()
+            },
+            Vec::new(),
+        );
+        {
+            let _lock = store.lock("alice", true).unwrap();
+            store.save("alice", &tally).unwrap();
+            store.save("bob", &tally).unwrap();
+        }
+
+        // the tallies are visible to other connections
+        let store = SqliteStore::open(&tally_dir).unwrap();
+        assert_eq!(store.load("alice").unwrap(), Some(tally));
+        assert_eq!(
+            store.list().unwrap(),
+            vec!["alice".to_string(), "bob".to_string()]
+        );
+
+        assert!(store.clear("alice").unwrap());
+        assert!(!store.clear("alice").unwrap());
+        assert_eq!(store.list().unwrap(), vec!["bob".to_string()]);
+    }
+
+    #[test]
+    fn test_sqlite_store_corrupt_tally() {
+        let temp_dir = temp_tally_dir("test_sqlite_store_corrupt_tally");
+        let store = SqliteStore::open(temp_dir.path()).unwrap();
+
+        store
+            .conn
+            .execute(
+                "INSERT INTO tallies (user, tally, modified) VALUES ('bob', '[Fails', 0)",
+                [],
+            )
+            .unwrap();
+        assert!(matches!(
+            store.load("bob"),
+            Err(StoreError::Corrupt {
+                modified: Some(_),
+                ..
+            })
+        ));
+    }
+}
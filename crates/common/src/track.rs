@@ -0,0 +1,590 @@
+//! # Track Module
+//!
+//! The `track` module defines which tallies an authentication attempt is counted in. The `track`
+//! setting lists the tracked keys:
+//!
+//! - `user` (default): One tally per user.
+//! - `rhost`: One tally per remote host (`PAM_RHOST`), so spraying many usernames from one
+//!   address hits the ramp.
+//! - `user+rhost`: One tally per user and remote host, so a single attacker cannot lock out a
+//!   user logging in from elsewhere.
+//!
+//! Remote host addresses are aggregated into networks with `rhost_ipv4_prefix` and
+//! `rhost_ipv6_prefix`. Remote hosts that are no IP address are tracked by name.
+//!
+//! Attempts from `trusted_networks` or `trusted_ttys` are not tracked at all.
+//!
+//! ## License
+//!
+//! pam-authramp
+//! Copyright (C) 2023 github.com/34N0
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fmt::{self, Write as _};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use pam::PamResultCode;
+
+use crate::settings::Settings;
+
+/// The `Track` enum represents a key the failures are counted by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Track {
+    /// Count the failures of each user.
+    User,
+    /// Count the failures from each remote host.
+    RHost,
+    /// Count the failures of each user from each remote host.
+    UserRHost,
+}
+
+impl Track {
+    /// Reads the `track` setting from the `[Configuration]` table.
+    ///
+    /// # Arguments
+    ///
+    /// * `toml_config`: A reference to a `toml::Value` representing the configuration table.
+    ///
+    /// # Returns
+    ///
+    /// The tracked keys, `["user"]` if `track` is not set.
+    ///
+    /// # Errors
+    ///
+    /// Returns a message describing the problem if `track` is not a non-empty list of known keys.
+    pub fn from_toml(toml_config: &toml::Value) -> Result<Vec<Track>, String> {
+        let Some(track) = toml_config.get("track") else {
+            return Ok(vec![Track::User]);
+        };
+
+        let keys = track
+            .as_array()
+            .ok_or_else(|| "track must be a list".to_string())?
+            .iter()
+            .map(|key| match key.as_str() {
+                Some("user") => Ok(Track::User),
+                Some("rhost") => Ok(Track::RHost),
+                Some("user+rhost") => Ok(Track::UserRHost),
+                _ => Err(format!(
+                    "unknown track key {key}, expected one of: user, rhost, user+rhost"
+                )),
+            })
+            .collect::<Result<Vec<Track>, String>>()?;
+
+        if keys.is_empty() {
+            return Err("track must not be empty".to_string());
+        }
+
+        Ok(keys)
+    }
+}
+
+/// The `TallyKey` enum identifies a tally in the tally store.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TallyKey {
+    /// Tally of a user.
+    User(String),
+    /// Tally of a remote host or network.
+    RHost(String),
+    /// Tally of a user from a remote host or network.
+    UserRHost(String, String),
+}
+
+impl TallyKey {
+    /// Builds the keys of the tallies the current authentication attempt is counted in.
+    /// Keys involving the remote host are skipped if `PAM_RHOST` is not set.
+    ///
+    /// # Arguments
+    ///
+    /// * `settings`: Settings for the authramp module
+    ///
+    /// # Returns
+    ///
+    /// The `TallyKey`s in the order of the `track` setting, or a `PAM_SYSTEM_ERR` if there is
+    /// no user.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PamResultCode` if the user is not set.
+    pub fn for_settings(settings: &Settings) -> Result<Vec<TallyKey>, PamResultCode> {
+        let user = settings.get_user()?.name().to_string_lossy().to_string();
+        let rhost = settings.items.rhost.as_deref().map(|rhost| {
+            aggregate_rhost(
+                rhost,
+                settings.config.rhost_ipv4_prefix,
+                settings.config.rhost_ipv6_prefix,
+            )
+        });
+
+        let mut keys = Vec::new();
+        for track in &settings.config.track {
+            let key = match (track, &rhost) {
+                (Track::User, _) => TallyKey::User(user.clone()),
+                (Track::RHost, Some(rhost)) => TallyKey::RHost(rhost.clone()),
+                (Track::UserRHost, Some(rhost)) => TallyKey::UserRHost(user.clone(), rhost.clone()),
+                (_, None) => continue,
+            };
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+
+        Ok(keys)
+    }
+
+    /// Returns the name of the tally in the tally store. User tallies are named after the user,
+    /// remote host tallies are prefixed with `@`. Tallies in a namespace are prefixed with the
+    /// namespace and `:`.
+    ///
+    /// The namespace, user and remote host are escaped, see `escape_component`, so the name can
+    /// be parsed back with `from_store_name`.
+    ///
+    /// # Arguments
+    ///
+    /// * `namespace`: The `tally_namespace` setting
+    #[must_use]
+    pub fn store_name(&self, namespace: Option<&str>) -> String {
+        let name = match self {
+            TallyKey::User(user) => escape_component(user),
+            TallyKey::RHost(rhost) => format!("@{}", escape_component(rhost)),
+            TallyKey::UserRHost(user, rhost) => {
+                format!("{}@{}", escape_component(user), escape_component(rhost))
+            }
+        };
+        match namespace {
+            Some(namespace) => format!("{}:{name}", escape_component(namespace)),
+            None => name,
+        }
+    }
+
+    /// Parses the name of a tally in the tally store, see `store_name`.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The name of the tally
+    ///
+    /// # Returns
+    ///
+    /// The namespace of the tally, if any, and its `TallyKey`.
+    #[must_use]
+    pub fn from_store_name(name: &str) -> (Option<String>, TallyKey) {
+        // the separators are escaped within the components
+        let (namespace, name) = match name.split_once(':') {
+            Some((namespace, name)) => (Some(unescape_component(namespace)), name),
+            None => (None, name),
+        };
+        let (user, rhost) = match name.split_once('@') {
+            Some((user, rhost)) => (user, Some(unescape_component(rhost))),
+            None => (name, None),
+        };
+
+        let key = match rhost {
+            Some(rhost) if user.is_empty() => TallyKey::RHost(rhost),
+            Some(rhost) => TallyKey::UserRHost(unescape_component(user), rhost),
+            None => TallyKey::User(unescape_component(user)),
+        };
+        (namespace, key)
+    }
+
+    /// Returns the user of the tally, `None` for remote host tallies.
+    #[must_use]
+    pub fn user(&self) -> Option<&str> {
+        match self {
+            TallyKey::User(user) | TallyKey::UserRHost(user, _) => Some(user),
+            TallyKey::RHost(_) => None,
+        }
+    }
+
+    /// Whether a successful login clears the tally. Remote host tallies are kept, so an attacker
+    /// with one valid account cannot reset the tally of the address they spray from.
+    #[must_use]
+    pub fn is_cleared_on_success(&self) -> bool {
+        !matches!(self, TallyKey::RHost(_))
+    }
+}
+
+impl fmt::Display for Track {
+    /// Formats the key as in the `track` setting.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Track::User => write!(f, "user"),
+            Track::RHost => write!(f, "rhost"),
+            Track::UserRHost => write!(f, "user+rhost"),
+        }
+    }
+}
+
+impl fmt::Display for TallyKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TallyKey::User(user) => write!(f, "\"{user}\" account"),
+            TallyKey::RHost(rhost) => write!(f, "\"{rhost}\" remote host"),
+            TallyKey::UserRHost(user, rhost) => {
+                write!(f, "\"{user}\" account from the \"{rhost}\" remote host")
+            }
+        }
+    }
+}
+
+/// The `IpNetwork` struct represents an IPv4 or IPv6 network, e.g. `10.0.0.0/8`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IpNetwork {
+    // Network address with the host bits cleared.
+    addr: IpAddr,
+    // Prefix length.
+    prefix: u8,
+}
+
+impl IpNetwork {
+    /// Creates the network of the given prefix length containing an address.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr`: An address in the network
+    /// * `prefix`: Prefix length, capped at the length of the address
+    #[must_use]
+    pub fn new(addr: IpAddr, prefix: u8) -> Self {
+        match addr {
+            IpAddr::V4(v4) => {
+                let prefix = prefix.min(32);
+                let mask = u32::MAX.checked_shl(u32::from(32 - prefix)).unwrap_or(0);
+                IpNetwork {
+                    addr: IpAddr::V4(Ipv4Addr::from(u32::from(v4) & mask)),
+                    prefix,
+                }
+            }
+            IpAddr::V6(v6) => {
+                let prefix = prefix.min(128);
+                let mask = u128::MAX.checked_shl(u32::from(128 - prefix)).unwrap_or(0);
+                IpNetwork {
+                    addr: IpAddr::V6(Ipv6Addr::from(u128::from(v6) & mask)),
+                    prefix,
+                }
+            }
+        }
+    }
+
+    /// Checks whether the network contains an address.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr`: The address
+    #[must_use]
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        addr.is_ipv4() == self.addr.is_ipv4() && IpNetwork::new(addr, self.prefix) == *self
+    }
+
+    /// Reads a list of networks from the `[Configuration]` table.
+    ///
+    /// # Arguments
+    ///
+    /// * `toml_config`: A reference to a `toml::Value` representing the configuration table.
+    /// * `key`: The key of the list
+    ///
+    /// # Returns
+    ///
+    /// The networks, an empty list if the key is not set.
+    ///
+    /// # Errors
+    ///
+    /// Returns a message describing the problem if the value is not a list of networks.
+    pub fn list_from_toml(toml_config: &toml::Value, key: &str) -> Result<Vec<IpNetwork>, String> {
+        let Some(networks) = toml_config.get(key) else {
+            return Ok(Vec::new());
+        };
+
+        networks
+            .as_array()
+            .ok_or_else(|| format!("{key} must be a list"))?
+            .iter()
+            .map(|network| {
+                network
+                    .as_str()
+                    .and_then(|network| network.parse().ok())
+                    .ok_or_else(|| format!("{key} contains an invalid network {network}"))
+            })
+            .collect()
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = String;
+
+    /// Parses `address/prefix`. An address without prefix is a network of one address.
+    fn from_str(network: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match network.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (network, None),
+        };
+
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("invalid address in {network}"))?;
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            None => max_prefix,
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .ok_or_else(|| format!("invalid prefix length in {network}"))?,
+        };
+
+        Ok(IpNetwork::new(addr, prefix))
+    }
+}
+
+impl fmt::Display for IpNetwork {
+    /// Formats the network as `address/prefix`, or as the address if the network has only one.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let max_prefix = if self.addr.is_ipv4() { 32 } else { 128 };
+        if self.prefix == max_prefix {
+            write!(f, "{}", self.addr)
+        } else {
+            write!(f, "{}/{}", self.addr, self.prefix)
+        }
+    }
+}
+
+/// Parses the `PAM_RHOST` item as IP address. The zone of link-local addresses is stripped and
+/// IPv4-mapped IPv6 addresses are converted to IPv4.
+///
+/// # Arguments
+///
+/// * `rhost`: The `PAM_RHOST` item
+///
+/// # Returns
+///
+/// The IP address, or `None` if the remote host is no IP address.
+#[must_use]
+pub fn parse_rhost(rhost: &str) -> Option<IpAddr> {
+    let addr = rhost.split('%').next().unwrap_or(rhost);
+
+    match addr.parse::<IpAddr>().ok()? {
+        IpAddr::V6(v6) => Some(v6.to_ipv4_mapped().map_or(IpAddr::V6(v6), IpAddr::V4)),
+        ip @ IpAddr::V4(_) => Some(ip),
+    }
+}
+
+/// Aggregates a remote host address into its network.
+///
+/// # Arguments
+///
+/// * `rhost`: The `PAM_RHOST` item
+/// * `ipv4_prefix`: Prefix length IPv4 addresses are aggregated to
+/// * `ipv6_prefix`: Prefix length IPv6 addresses are aggregated to
+///
+/// # Returns
+///
+/// The network as `address/prefix`, or the address if the prefix covers the whole address.
+/// Remote hosts that are no IP address are returned in lowercase.
+fn aggregate_rhost(rhost: &str, ipv4_prefix: u8, ipv6_prefix: u8) -> String {
+    match parse_rhost(rhost) {
+        Some(ip @ IpAddr::V4(_)) => IpNetwork::new(ip, ipv4_prefix).to_string(),
+        Some(ip @ IpAddr::V6(_)) => IpNetwork::new(ip, ipv6_prefix).to_string(),
+        None => rhost.to_lowercase(),
+    }
+}
+
+/// Escapes a component of a tally name. The separators `@` and `:`, `/`, which cannot be part
+/// of a file name, `%` and a leading `.`, which would hide the tally file, are replaced by `%`
+/// and their hexadecimal code.
+///
+/// # Arguments
+///
+/// * `component`: The namespace, user or remote host
+fn escape_component(component: &str) -> String {
+    let mut escaped = String::with_capacity(component.len());
+    for (i, c) in component.chars().enumerate() {
+        if matches!(c, '%' | '@' | ':' | '/') || (i == 0 && c == '.') {
+            let _ = write!(escaped, "%{:02X}", u32::from(c));
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+/// Reverses `escape_component`. Invalid escapes are kept as they are.
+///
+/// # Arguments
+///
+/// * `component`: The escaped namespace, user or remote host
+fn unescape_component(component: &str) -> String {
+    let bytes = component.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let code = component
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        if let Some(code) = code {
+            unescaped.push(code);
+            i += 3;
+        } else {
+            unescaped.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&unescaped).into_owned()
+}
+
+// Unit Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::settings::PamItems;
+    use uzers::User;
+
+    #[test]
+    fn test_aggregate_rhost() {
+        assert_eq!(aggregate_rhost("192.0.2.7", 32, 64), "192.0.2.7");
+        assert_eq!(aggregate_rhost("192.0.2.7", 24, 64), "192.0.2.0/24");
+        assert_eq!(aggregate_rhost("192.0.2.7", 0, 64), "0.0.0.0/0");
+        assert_eq!(aggregate_rhost("::ffff:192.0.2.7", 24, 64), "192.0.2.0/24");
+        assert_eq!(
+            aggregate_rhost("2001:db8:1:2:3:4:5:6", 32, 64),
+            "2001:db8:1:2::/64"
+        );
+        assert_eq!(aggregate_rhost("fe80::1%eth0", 32, 128), "fe80::1");
+        assert_eq!(
+            aggregate_rhost("Host.Example.org", 24, 64),
+            "host.example.org"
+        );
+    }
+
+    #[test]
+    fn test_ip_network() {
+        let network: IpNetwork = "10.1.2.3/8".parse().unwrap();
+        assert_eq!(network.to_string(), "10.0.0.0/8");
+        assert!(network.contains("10.255.0.1".parse().unwrap()));
+        assert!(!network.contains("11.0.0.1".parse().unwrap()));
+        assert!(!network.contains("::a01:203".parse().unwrap()));
+
+        let network: IpNetwork = "::1".parse().unwrap();
+        assert_eq!(network.to_string(), "::1");
+        assert!(network.contains("::1".parse().unwrap()));
+        assert!(!network.contains("::2".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<IpNetwork>().is_err());
+        assert!("bastion/24".parse::<IpNetwork>().is_err());
+
+        let toml_config: toml::Value =
+            toml::from_str(r#"trusted_networks = ["10.0.0.0/8", "::1/128"]"#).unwrap();
+        assert_eq!(
+            IpNetwork::list_from_toml(&toml_config, "trusted_networks")
+                .unwrap()
+                .len(),
+            2
+        );
+        assert!(IpNetwork::list_from_toml(&toml_config, "other")
+            .unwrap()
+            .is_empty());
+        let toml_config: toml::Value =
+            toml::from_str(r#"trusted_networks = ["10.0.0.0/8", "bastion"]"#).unwrap();
+        assert!(IpNetwork::list_from_toml(&toml_config, "trusted_networks").is_err());
+    }
+
+    #[test]
+    fn test_tally_keys() {
+        let mut settings = Settings {
+            user: Some(User::new(1000, "alice", 1000)),
+            config: Config {
+                track: vec![Track::User, Track::RHost, Track::UserRHost],
+                rhost_ipv4_prefix: 24,
+                ..Config::default()
+            },
+            ..Default::default()
+        };
+
+        // without PAM_RHOST only the user is tracked
+        assert_eq!(
+            TallyKey::for_settings(&settings).unwrap(),
+            vec![TallyKey::User("alice".to_string())]
+        );
+
+        settings.items = PamItems {
+            rhost: Some("192.0.2.7".to_string()),
+            ..PamItems::default()
+        };
+        let keys = TallyKey::for_settings(&settings).unwrap();
+        let names: Vec<String> = keys.iter().map(|key| key.store_name(None)).collect();
+        assert_eq!(
+            names,
+            vec!["alice", "@192.0.2.0%2F24", "alice@192.0.2.0%2F24"]
+        );
+        assert_eq!(keys[0].store_name(Some("sshd")), "sshd:alice");
+        assert_eq!(
+            keys[2].to_string(),
+            "\"alice\" account from the \"192.0.2.0/24\" remote host"
+        );
+        for key in &keys {
+            assert_eq!(
+                TallyKey::from_store_name(&key.store_name(None)),
+                (None, key.clone())
+            );
+            assert_eq!(
+                TallyKey::from_store_name(&key.store_name(Some("sshd"))),
+                (Some("sshd".to_string()), key.clone())
+            );
+        }
+        assert_eq!(keys[1].user(), None);
+        assert_eq!(keys[2].user(), Some("alice"));
+        assert!(keys[0].is_cleared_on_success());
+        assert!(!keys[1].is_cleared_on_success());
+        assert!(keys[2].is_cleared_on_success());
+    }
+
+    #[test]
+    fn test_store_name_escaping() {
+        let keys = [
+            TallyKey::User("alice@example.com".to_string()),
+            TallyKey::User(".hidden".to_string()),
+            TallyKey::RHost("fe80::1".to_string()),
+            TallyKey::UserRHost("a_b%41".to_string(), "host_name".to_string()),
+        ];
+        assert_eq!(keys[0].store_name(None), "alice%40example.com");
+        assert_eq!(keys[1].store_name(None), "%2Ehidden");
+        assert_eq!(keys[2].store_name(Some("a:b")), "a%3Ab:@fe80%3A%3A1");
+        for key in &keys {
+            for namespace in [None, Some("ssh:d/1")] {
+                assert_eq!(
+                    TallyKey::from_store_name(&key.store_name(namespace)),
+                    (namespace.map(str::to_string), key.clone())
+                );
+            }
+        }
+        assert_eq!(unescape_component("100%"), "100%");
+        assert_eq!(unescape_component("%zz%4"), "%zz%4");
+    }
+
+    #[test]
+    fn test_from_toml() {
+        let parse =
+            |toml_str: &str| Track::from_toml(&toml::from_str::<toml::Value>(toml_str).unwrap());
+
+        assert_eq!(parse("free_tries = 6"), Ok(vec![Track::User]));
+        assert_eq!(
+            parse(r#"track = ["rhost", "user+rhost"]"#),
+            Ok(vec![Track::RHost, Track::UserRHost])
+        );
+        assert!(parse("track = []").is_err());
+        assert!(parse(r#"track = "user""#).is_err());
+        assert!(parse(r#"track = ["user", "tty"]"#).is_err());
+    }
+}
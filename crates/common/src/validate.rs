@@ -0,0 +1,626 @@
+//! # Validate Module
+//!
+//! The `validate` module checks configuration files strictly. While loading a configuration falls
+//! back to the default for every value it cannot use, validation reports each problem with the
+//! file and line it was found in:
+//!
+//! - syntax errors,
+//! - unknown sections and keys, e.g. a typo like `free_trys`,
+//! - values of the wrong type, e.g. `free_tries = "3"`,
+//! - values out of range, e.g. negative delays or zero free tries.
+//!
+//! The PAM module logs the problems, the CLI binary reports them with `authramp config check`.
+//!
+//! ## License
+//!
+//! pam-authramp
+//! Copyright (C) 2023 github.com/34N0
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use toml_edit::ImDocument;
+
+use crate::config::{
+    drop_in_files, ramp_multiplier_from_toml, CONFIG_KEYS, DEFAULT_CONFIG_FILE_PATH, MAX_SECONDS,
+};
+use crate::curve::DelayCurve;
+use crate::locale::{placeholders, MESSAGE_PLACEHOLDERS};
+use crate::overrides::OVERRIDE_KEYS;
+use crate::track::{IpNetwork, Track};
+
+/// Tables of the configuration file holding one override section per name.
+const SECTION_TABLES: &[&str] = &["Service", "User", "Group"];
+
+/// The `ConfigIssue` struct describes a problem found in a configuration file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigIssue {
+    // File the problem was found in.
+    pub path: PathBuf,
+    // Line of the problem, if known.
+    pub line: Option<usize>,
+    // Description of the problem.
+    pub message: String,
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{line}: {}", self.path.display(), self.message),
+            None => write!(f, "{}: {}", self.path.display(), self.message),
+        }
+    }
+}
+
+/// Validates the configuration file and its drop-in fragments.
+///
+/// # Arguments
+///
+/// * `path`: An optional string slice specifying the path to the configuration file. If not
+///   provided, the default configuration file path is used.
+///
+/// # Returns
+///
+/// The problems found in all files. A missing configuration file is no problem, the defaults
+/// are used then.
+#[must_use]
+pub fn check_config(path: Option<&str>) -> Vec<ConfigIssue> {
+    let path = PathBuf::from(path.unwrap_or(DEFAULT_CONFIG_FILE_PATH));
+
+    let mut issues = match check_file(&path) {
+        Ok(issues) => issues,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => vec![issue(&path, None, format!("cannot be read: {e}"))],
+    };
+
+    for fragment in drop_in_files(&path) {
+        match check_file(&fragment) {
+            Ok(fragment_issues) => issues.extend(fragment_issues),
+            Err(e) => issues.push(issue(&fragment, None, format!("cannot be read: {e}"))),
+        }
+    }
+
+    issues
+}
+
+/// Validates a single configuration file.
+///
+/// # Arguments
+///
+/// * `path`: Path of the file
+///
+/// # Returns
+///
+/// The problems found in the file.
+///
+/// # Errors
+///
+/// Returns an `io::Error` if the file cannot be read.
+pub fn check_file(path: &Path) -> io::Result<Vec<ConfigIssue>> {
+    Ok(check_content(path, &fs::read_to_string(path)?))
+}
+
+/// Validates the content of a configuration file.
+///
+/// # Arguments
+///
+/// * `path`: Path of the file, used in the issues
+/// * `content`: The TOML content of the file
+///
+/// # Returns
+///
+/// The problems found in the content, ordered by line.
+#[must_use]
+pub fn check_content(path: &Path, content: &str) -> Vec<ConfigIssue> {
+    let document = match ImDocument::parse(content) {
+        Ok(document) => document,
+        Err(e) => {
+            let line = e.span().map(|span| line_at(content, span.start));
+            return vec![issue(path, line, e.message().to_string())];
+        }
+    };
+    let toml_table: toml::value::Table = match toml::from_str(content) {
+        Ok(toml_table) => toml_table,
+        Err(e) => return vec![syntax_error(path, content, &e)],
+    };
+
+    let line = |keys: &[&str]| line_of(&document, content, keys);
+    let mut issues = check_sections(&toml_table, &line, path);
+    issues.sort_by_key(|issue| issue.line);
+    issues
+}
+
+/// Validates a configuration file that is already parsed. The content is only parsed again to
+/// find the lines of the problems.
+///
+/// # Arguments
+///
+/// * `path`: Path of the file, used in the issues
+/// * `content`: The TOML content of the file
+/// * `toml_table`: The parsed content
+///
+/// # Returns
+///
+/// The problems found in the content, ordered by line.
+#[must_use]
+pub fn check_parsed(
+    path: &Path,
+    content: &str,
+    toml_table: &toml::value::Table,
+) -> Vec<ConfigIssue> {
+    if check_sections(toml_table, &|_| None, path).is_empty() {
+        return Vec::new();
+    }
+    check_content(path, content)
+}
+
+/// Describes a TOML syntax error.
+///
+/// # Arguments
+///
+/// * `path`: Path of the file, used in the issue
+/// * `content`: The TOML content of the file
+/// * `e`: The error parsing the content
+#[must_use]
+pub fn syntax_error(path: &Path, content: &str, e: &toml::de::Error) -> ConfigIssue {
+    let line = e.span().map(|span| line_at(content, span.start));
+    issue(path, line, e.message().to_string())
+}
+
+/// Checks the sections of a configuration.
+///
+/// # Arguments
+///
+/// * `toml_table`: The configuration
+/// * `line`: Looks up the line of a key path
+/// * `path`: Path of the file, used in the issues
+///
+/// # Returns
+///
+/// The problems found in the sections.
+fn check_sections(
+    toml_table: &toml::value::Table,
+    line: &dyn Fn(&[&str]) -> Option<usize>,
+    path: &Path,
+) -> Vec<ConfigIssue> {
+    let mut issues = Vec::new();
+
+    for (name, value) in toml_table {
+        if name == "Configuration" {
+            issues.extend(check_table(value, CONFIG_KEYS, &[name], line, path));
+        } else if SECTION_TABLES.contains(&name.as_str()) {
+            let Some(sections) = value.as_table() else {
+                issues.push(issue(
+                    path,
+                    line(&[name]),
+                    format!("{name} must hold [{name}.<name>] sections"),
+                ));
+                continue;
+            };
+            for (section, value) in sections {
+                issues.extend(check_table(
+                    value,
+                    OVERRIDE_KEYS,
+                    &[name, section],
+                    line,
+                    path,
+                ));
+            }
+        } else {
+            issues.push(issue(
+                path,
+                line(&[name]),
+                format!("unknown section {name}, expected Configuration, Service, User or Group"),
+            ));
+        }
+    }
+    issues
+}
+
+/// Checks a single value of the `[Configuration]` table or an override section.
+///
+/// # Arguments
+///
+/// * `key`: The key of the value
+/// * `value`: The value
+/// * `table`: The table holding the value, used by settings spread over several keys
+///
+/// # Errors
+///
+/// Returns a message describing the problem if the value has the wrong type or is out of range.
+pub fn check_value(key: &str, value: &toml::Value, table: &toml::Value) -> Result<(), String> {
+    match key {
+        "tally_dir" | "audit_log" | "locale_dir" => match value.as_str() {
+            Some(dir) if Path::new(dir).is_absolute() => Ok(()),
+            Some(_) => Err(format!("{key} must be an absolute path")),
+            None => Err(format!("{key} must be a string")),
+        },
+        "persistent" | "even_deny_root" | "countdown" | "journald" | "debug" | "quiet"
+        | "silent" => value
+            .as_bool()
+            .map(|_| ())
+            .ok_or_else(|| format!("{key} must be true or false")),
+        "free_tries" | "permanent_lock_after" => check_integer(key, value, 1, i64::from(i32::MAX)),
+        "base_delay_seconds" => check_integer(key, value, 0, i64::from(i32::MAX)),
+        "ramp_multiplier" => ramp_multiplier_from_toml(value).map(|_| ()).ok_or_else(|| {
+            format!(
+                "{key} must be a whole number between 0 and {}, got {value}",
+                i32::MAX
+            )
+        }),
+        "exponential_base" => check_number(key, value, 1.0),
+        "exponential_cap" | "max_delay_seconds" | "fail_interval" => {
+            check_integer(key, value, 1, MAX_SECONDS)
+        }
+        "max_history" => check_integer(key, value, 0, i64::MAX),
+        "rhost_ipv4_prefix" => check_integer(key, value, 0, 32),
+        "rhost_ipv6_prefix" => check_integer(key, value, 0, 128),
+        "steps" => match value.as_array() {
+            Some(steps) if steps.is_empty() => Err(format!("{key} must not be empty")),
+            Some(steps) => steps
+                .iter()
+                .try_for_each(|step| check_integer(key, step, 0, MAX_SECONDS)),
+            None => Err(format!("{key} must be a list of integers")),
+        },
+        "delay_curve" => DelayCurve::from_toml(table).map(|_| ()),
+        "track" => Track::from_toml(table).map(|_| ()),
+        "trusted_networks" => IpNetwork::list_from_toml(table, key).map(|_| ()),
+        "trusted_ttys" => match value.as_array() {
+            Some(ttys) if ttys.iter().all(toml::Value::is_str) => Ok(()),
+            _ => Err(format!("{key} must be a list of strings")),
+        },
+        "on_corrupt_tally" => check_choice(key, value, &["fail_closed", "fail_open", "lock"]),
+        "storage" => check_choice(key, value, &["file", "sqlite"]),
+        "log_level" => check_choice(key, value, &["error", "warning", "notice", "info", "debug"]),
+        "lock_message" | "countdown_message" | "permanent_lock_message" => match value.as_str() {
+            Some(template) => placeholders(template).try_for_each(|name| {
+                if MESSAGE_PLACEHOLDERS.contains(&name) {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "unknown placeholder {{{name}}} in {key}, expected one of {}",
+                        MESSAGE_PLACEHOLDERS
+                            .iter()
+                            .map(|name| format!("{{{name}}}"))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ))
+                }
+            }),
+            None => Err(format!("{key} must be a string")),
+        },
+        "tally_namespace" => match value.as_str() {
+            Some(namespace) if !namespace.is_empty() => Ok(()),
+            _ => Err(format!("{key} must be a non-empty string")),
+        },
+        _ => Err(format!("unknown key {key}")),
+    }
+}
+
+/// Checks the keys and values of a table.
+///
+/// # Arguments
+///
+/// * `table`: The table
+/// * `keys`: The keys allowed in the table
+/// * `table_path`: The keys leading to the table
+/// * `line`: Looks up the line of a key path
+/// * `path`: Path of the file, used in the issues
+///
+/// # Returns
+///
+/// The problems found in the table.
+fn check_table(
+    table: &toml::Value,
+    keys: &[&str],
+    table_path: &[&str],
+    line: &dyn Fn(&[&str]) -> Option<usize>,
+    path: &Path,
+) -> Vec<ConfigIssue> {
+    let name = table_path.join(".");
+    let Some(entries) = table.as_table() else {
+        return vec![issue(
+            path,
+            line(table_path),
+            format!("{name} must be a table"),
+        )];
+    };
+
+    let mut issues = Vec::new();
+    for (key, value) in entries {
+        let key_path: Vec<&str> = table_path.iter().copied().chain([key.as_str()]).collect();
+
+        let result = if keys.contains(&key.as_str()) {
+            check_value(key, value, table)
+        } else {
+            Err(match closest_key(key, keys) {
+                Some(closest) => format!("unknown key {key} in [{name}], did you mean {closest}?"),
+                None => format!("unknown key {key} in [{name}]"),
+            })
+        };
+
+        if let Err(message) = result {
+            issues.push(issue(path, line(&key_path), message));
+        }
+    }
+    issues
+}
+
+/// Checks that a value is an integer in the given range.
+fn check_integer(key: &str, value: &toml::Value, min: i64, max: i64) -> Result<(), String> {
+    match value.as_integer() {
+        Some(val) if val < min => Err(format!("{key} must be >= {min}, got {val}")),
+        Some(val) if val > max => Err(format!("{key} must be <= {max}, got {val}")),
+        Some(_) => Ok(()),
+        None => Err(format!("{key} must be an integer, got {value}")),
+    }
+}
+
+/// Checks that a value is an integer or float of at least `min`.
+fn check_number(key: &str, value: &toml::Value, min: f64) -> Result<(), String> {
+    let number = value.as_float().or_else(|| {
+        value
+            .as_integer()
+            .and_then(|val| i32::try_from(val).ok())
+            .map(f64::from)
+    });
+    match number {
+        Some(val) if val.is_nan() || val < min => Err(format!("{key} must be >= {min}, got {val}")),
+        Some(_) => Ok(()),
+        None => Err(format!("{key} must be a number, got {value}")),
+    }
+}
+
+/// Checks that a value is one of the given strings.
+fn check_choice(key: &str, value: &toml::Value, choices: &[&str]) -> Result<(), String> {
+    match value.as_str() {
+        Some(val) if choices.contains(&val) => Ok(()),
+        _ => Err(format!(
+            "{key} must be one of {}, got {value}",
+            choices.join(", ")
+        )),
+    }
+}
+
+/// Finds the allowed key closest to a misspelled key.
+///
+/// # Returns
+///
+/// The closest key within an edit distance of two, if any.
+fn closest_key<'a>(key: &str, keys: &[&'a str]) -> Option<&'a str> {
+    keys.iter()
+        .map(|candidate| (edit_distance(key, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Calculates the Levenshtein distance of two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                diagonal
+            } else {
+                1 + diagonal.min(above).min(row[j])
+            };
+            diagonal = above;
+        }
+    }
+
+    row[b.len()]
+}
+
+/// Looks up the line of a key in the parsed document.
+///
+/// # Arguments
+///
+/// * `document`: The parsed document
+/// * `content`: The content of the document
+/// * `keys`: The keys leading to the value, e.g. `["Configuration", "free_tries"]`
+fn line_of<S>(document: &ImDocument<S>, content: &str, keys: &[&str]) -> Option<usize> {
+    let (last, parents) = keys.split_last()?;
+
+    let mut item = document.as_item();
+    for key in parents {
+        item = item.as_table_like()?.get(key)?;
+    }
+
+    let (key, item) = item.as_table_like()?.get_key_value(last)?;
+    let span = key.span().or_else(|| item.span())?;
+    Some(line_at(content, span.start))
+}
+
+/// Converts a byte offset into a line number, starting at 1.
+fn line_at(content: &str, offset: usize) -> usize {
+    content
+        .get(..offset)
+        .map_or(1, |head| head.matches('\n').count() + 1)
+}
+
+/// Creates a `ConfigIssue`.
+fn issue(path: &Path, line: Option<usize>, message: String) -> ConfigIssue {
+    ConfigIssue {
+        path: path.to_path_buf(),
+        line,
+        message,
+    }
+}
+
+// Unit Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn check(content: &str) -> Vec<String> {
+        check_content(Path::new("authramp.conf"), content)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_config() {
+        let content = r#"
+[Configuration]
+tally_dir = "/var/lib/authramp"
+free_tries = 3
+ramp_multiplier = 50
+delay_curve = "steps"
+steps = [30, 60]
+track = ["user", "rhost"]
+trusted_networks = ["10.0.0.0/8"]
+log_level = "info"
+lock_message = "Locked for {remaining}, {failures} failures"
+
+[Service.sshd]
+free_tries = 2
+tally_namespace = "sshd"
+
+[User.backup]
+max_delay_seconds = 600
+"#;
+        assert!(check(content).is_empty());
+        assert!(check("").is_empty());
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let content = r#"
+[Configuration]
+free_trys = 3
+free_tries = 0
+base_delay_seconds = -30
+ramp_multiplier = "fast"
+storage = "redis"
+delay_curve = "cubic"
+lock_message = "Locked until {unlock}"
+
+[User.alice]
+free_tries = 1
+tally_dir = "/tmp"
+
+[Host.bastion]
+free_tries = 1
+"#;
+        assert_eq!(
+            check(content),
+            vec![
+                "authramp.conf:3: unknown key free_trys in [Configuration], did you mean free_tries?",
+                "authramp.conf:4: free_tries must be >= 1, got 0",
+                "authramp.conf:5: base_delay_seconds must be >= 0, got -30",
+                "authramp.conf:6: ramp_multiplier must be a whole number between 0 and 2147483647, got \"fast\"",
+                "authramp.conf:7: storage must be one of file, sqlite, got \"redis\"",
+                "authramp.conf:8: unknown delay_curve 'cubic', expected one of: linear, exponential, nlogn, fibonacci, steps",
+                "authramp.conf:9: unknown placeholder {unlock} in lock_message, expected one of {remaining}, {until}, {failures}, {user}",
+                "authramp.conf:13: unknown key tally_dir in [User.alice]",
+                "authramp.conf:15: unknown section Host, expected Configuration, Service, User or Group",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        let content = r#"
+[Configuration]
+ramp_multiplier = 20.5
+fail_interval = 9223372036854775807
+max_delay_seconds = 2147483648
+delay_curve = "exponential"
+exponential_cap = 2147483648
+"#;
+        assert_eq!(
+            check(content),
+            vec![
+                "authramp.conf:3: ramp_multiplier must be a whole number between 0 and 2147483647, got 20.5",
+                "authramp.conf:4: fail_interval must be <= 2147483647, got 9223372036854775807",
+                "authramp.conf:5: max_delay_seconds must be <= 2147483647, got 2147483648",
+                "authramp.conf:6: exponential_cap must be between 1 and 2147483647, got 2147483648",
+                "authramp.conf:7: exponential_cap must be <= 2147483647, got 2147483648",
+            ]
+        );
+        assert!(check("[Configuration]\nramp_multiplier = 20.0").is_empty());
+    }
+
+    #[test]
+    fn test_check_parsed() {
+        let content = "[Configuration]\nfree_tries = 3\n\n[User.alice]\ncountdown = true";
+        let toml_table = toml::from_str(content).unwrap();
+        assert_eq!(
+            check_parsed(Path::new("authramp.conf"), content, &toml_table)
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec!["authramp.conf:5: unknown key countdown in [User.alice]"]
+        );
+
+        let content = "[Configuration]\nfree_tries = 3";
+        let toml_table = toml::from_str(content).unwrap();
+        assert!(check_parsed(Path::new("authramp.conf"), content, &toml_table).is_empty());
+    }
+
+    #[test]
+    fn test_syntax_error() {
+        let issues = check("[Configuration]\nfree_tries = 3\ncountdown = \n");
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].starts_with("authramp.conf:3: "), "{}", issues[0]);
+    }
+
+    #[test]
+    fn test_check_config_drop_ins() {
+        let temp_dir = TempDir::new("test_check_config_drop_ins").unwrap();
+        let conf_file_path = temp_dir.path().join("authramp.conf");
+        let drop_in_dir = temp_dir.path().join("authramp.conf.d");
+        fs::create_dir(&drop_in_dir).unwrap();
+
+        // a missing configuration file is valid
+        assert!(check_config(Some(conf_file_path.to_str().unwrap())).is_empty());
+
+        fs::write(&conf_file_path, "[Configuration]\nfree_tries = 3").unwrap();
+        fs::write(
+            drop_in_dir.join("10-sshd.conf"),
+            "[Service.sshd]\nfree_tries = -1",
+        )
+        .unwrap();
+
+        let issues = check_config(Some(conf_file_path.to_str().unwrap()));
+        assert_eq!(
+            issues,
+            vec![ConfigIssue {
+                path: drop_in_dir.join("10-sshd.conf"),
+                line: Some(2),
+                message: "free_tries must be >= 1, got -1".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("free_trys", "free_tries"), 2);
+        assert_eq!(edit_distance("countdown", "countdown"), 0);
+        assert_eq!(closest_key("storag", CONFIG_KEYS), Some("storage"));
+        assert_eq!(closest_key("verbose", CONFIG_KEYS), None);
+    }
+}
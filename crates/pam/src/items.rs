@@ -10,8 +10,8 @@
 //! Each item type corresponds to a specific piece of data, such as the user's username, password,
 //! or the PAM conversation function.
 //!
-//! This module also provides implementations of the `Item` trait for several types, including
-//! `CString`, `Conv`, and `Option<CString>`.
+//! This module also provides implementations of the `Item` trait for `Conv` and the string items
+//! `Service`, `Tty`, `RHost` and `RUser`.
 //!
 //! ## License
 //!
@@ -21,10 +21,22 @@
 //! license that can be found in the LICENSE file or at
 //! https://opensource.org/licenses/MIT.
 
+use std::ffi::CStr;
+
+use libc::c_char;
+
 #[repr(u32)]
 pub enum ItemType {
+    /// The service name
+    Service = 1,
+    /// The tty name
+    Tty = 3,
+    /// The remote host name
+    RHost = 4,
     /// The pam_conv structure
     Conv = 5,
+    /// The remote user name
+    RUser = 8,
 }
 
 // A type that can be requested by `pam::Handle::get_item`.
@@ -45,3 +57,31 @@ pub trait Item {
     /// The function to convert from this wrapper type to a C-compatible pointer.
     fn into_raw(self) -> *const Self::Raw;
 }
+
+/// Implements `Item` for a string item wrapping a `CStr` borrowed from the PAM context.
+macro_rules! cstr_item {
+    ($name:ident, $item_type:expr) => {
+        pub struct $name<'a>(pub &'a CStr);
+
+        impl<'a> Item for $name<'a> {
+            type Raw = c_char;
+
+            fn type_id() -> ItemType {
+                $item_type
+            }
+
+            unsafe fn from_raw(raw: *const Self::Raw) -> Self {
+                Self(CStr::from_ptr(raw))
+            }
+
+            fn into_raw(self) -> *const Self::Raw {
+                self.0.as_ptr()
+            }
+        }
+    };
+}
+
+cstr_item!(Service, ItemType::Service);
+cstr_item!(Tty, ItemType::Tty);
+cstr_item!(RHost, ItemType::RHost);
+cstr_item!(RUser, ItemType::RUser);
@@ -0,0 +1,186 @@
+//! # Journal module
+//!
+//! This module sends structured log entries to systemd-journald with its native protocol, see
+//! <https://systemd.io/JOURNAL_NATIVE_PROTOCOL/>. Unlike `pam_syslog`, the fields of an entry
+//! are kept, so they can be matched with `journalctl FIELD=value`. The entries are written to the
+//! datagram socket of journald directly, the module does not link against libsystemd.
+//!
+//! ```no_run
+//! use pam::journal::Journal;
+//! use pam::LogLevel;
+//!
+//! let journal = Journal::connect().unwrap();
+//! journal
+//!     .send(LogLevel::Notice, "account locked", &[("AUTHRAMP_USER", "alice")])
+//!     .unwrap();
+//! ```
+//!
+//!  ## License
+//!
+//! Copyright 2023 34n0
+//!
+//! Use of this source code is governed by an MIT-style
+//! license that can be found in the LICENSE file or at
+//! https://opensource.org/licenses/MIT.
+
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+
+use crate::LogLevel;
+
+/// Socket journald receives native protocol entries on.
+pub const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Identifier the entries are logged with.
+const SYSLOG_IDENTIFIER: &str = "authramp";
+
+/// A connection to the journal socket.
+#[derive(Debug)]
+pub struct Journal {
+    socket: UnixDatagram,
+}
+
+impl Journal {
+    /// Connects to the socket of journald.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `io::Error` if the socket does not exist, e.g. on systems without systemd.
+    pub fn connect() -> io::Result<Self> {
+        Self::connect_to(JOURNAL_SOCKET)
+    }
+
+    /// Connects to a datagram socket speaking the native journal protocol.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Path of the socket.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `io::Error` if the socket cannot be connected.
+    pub fn connect_to(path: impl AsRef<Path>) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(Journal { socket })
+    }
+
+    /// Sends an entry to the journal.
+    ///
+    /// # Arguments
+    ///
+    /// * `level`: Priority of the entry.
+    /// * `message`: The `MESSAGE` field.
+    /// * `fields`: Further fields. Names must consist of uppercase letters, digits and
+    ///   underscores and must not start with an underscore.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `io::Error` if a field name is invalid or the entry cannot be sent.
+    pub fn send(&self, level: LogLevel, message: &str, fields: &[(&str, &str)]) -> io::Result<()> {
+        let priority = (level as i32).to_string();
+        let mut entry = Vec::new();
+        for (name, value) in [
+            ("PRIORITY", priority.as_str()),
+            ("SYSLOG_IDENTIFIER", SYSLOG_IDENTIFIER),
+            ("MESSAGE", message),
+        ]
+        .iter()
+        .chain(fields)
+        {
+            encode_field(&mut entry, name, value)?;
+        }
+
+        self.socket.send(&entry).map(|_| ())
+    }
+}
+
+/// Appends a field in the native journal format. Values with a newline are sent with their
+/// length instead of the `=` separator.
+///
+/// # Arguments
+///
+/// * `entry`: The entry to append to.
+/// * `name`: The field name.
+/// * `value`: The field value.
+///
+/// # Errors
+///
+/// Returns an `io::Error` of kind `InvalidInput` if the field name is invalid.
+fn encode_field(entry: &mut Vec<u8>, name: &str, value: &str) -> io::Result<()> {
+    let valid = name
+        .bytes()
+        .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit() || b == b'_')
+        && !name.is_empty()
+        && !name.starts_with('_');
+    if !valid {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid journal field name {name:?}"),
+        ));
+    }
+
+    entry.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+    Ok(())
+}
+
+// Unit Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_encode_field() {
+        let mut entry = Vec::new();
+        encode_field(&mut entry, "AUTHRAMP_USER", "alice").unwrap();
+        encode_field(&mut entry, "MESSAGE", "a\nb").unwrap();
+        assert_eq!(
+            entry,
+            b"AUTHRAMP_USER=alice\nMESSAGE\n\x03\0\0\0\0\0\0\0a\nb\n".to_vec()
+        );
+
+        assert!(encode_field(&mut entry, "_PID", "1").is_err());
+        assert!(encode_field(&mut entry, "user", "alice").is_err());
+        assert!(encode_field(&mut entry, "", "alice").is_err());
+    }
+
+    #[test]
+    fn test_send() {
+        let temp_dir = TempDir::new("test_journal_send").unwrap();
+        let path = temp_dir.path().join("socket");
+        let server = UnixDatagram::bind(&path).unwrap();
+
+        let journal = Journal::connect_to(&path).unwrap();
+        journal
+            .send(
+                LogLevel::Notice,
+                "account locked",
+                &[("AUTHRAMP_USER", "alice"), ("AUTHRAMP_FAILURES", "7")],
+            )
+            .unwrap();
+
+        let mut buf = [0; 1024];
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&buf[..len]),
+            "PRIORITY=5\nSYSLOG_IDENTIFIER=authramp\nMESSAGE=account locked\n\
+             AUTHRAMP_USER=alice\nAUTHRAMP_FAILURES=7\n"
+        );
+
+        // entries with invalid fields are not sent
+        assert!(journal
+            .send(LogLevel::Info, "invalid", &[("user", "alice")])
+            .is_err());
+        assert!(Journal::connect_to(temp_dir.path().join("missing")).is_err());
+    }
+}
@@ -12,6 +12,8 @@
 //! - `LogLevel`: An enum representing the possible log levels that can be used when logging
 //!   messages with the `pam_syslog` function.
 //!
+//! The `journal` module sends structured entries to systemd-journald.
+//!
 //! This module also provides the `PamHooks` trait, which can be implemented by types that
 //! provide hooks for various PAM operations, such as account management and authentication.
 //!
@@ -25,6 +27,7 @@
 
 pub mod conv;
 pub mod items;
+pub mod journal;
 pub mod macros;
 
 use libc::c_char;
@@ -38,6 +41,8 @@ pub type PamMessageStyle = c_int;
 pub const PAM_ERROR_MSG: PamMessageStyle = 3;
 pub const PAM_TEXT_INFO: PamMessageStyle = 4;
 
+pub const PAM_SILENT: PamFlag = 0x8000;
+
 #[allow(non_camel_case_types, dead_code)]
 #[derive(Debug, PartialEq)]
 #[repr(C)]
@@ -52,6 +57,8 @@ pub enum PamResultCode {
     PAM_ABORT = 26,
 }
 
+/// Ordered by verbosity, `Emergency` is the least verbose level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum LogLevel {
     /// system is unusable, corresponds to LOG_EMERG
     Emergency = 0,
@@ -95,6 +102,8 @@ extern "C" {
         item: &mut *const libc::c_void,
     ) -> PamResultCode;
 
+    fn pam_getenv(pamh: *const PamHandle, name: *const c_char) -> *const c_char;
+
     fn pam_syslog(
         pamh: *const PamHandle,
         priority: libc::c_int,
@@ -168,6 +177,19 @@ impl PamHandle {
         }
     }
 
+    /// Retrieves a variable of the PAM environment, see `pam_getenv(3)`.
+    ///
+    /// Returns `None` if the variable is not set or not valid UTF-8.
+    pub fn getenv(&self, name: &str) -> Option<String> {
+        let name = CString::new(name).ok()?;
+        let value = unsafe { pam_getenv(self, name.as_ptr()) };
+        if value.is_null() {
+            return None;
+        }
+        let value = unsafe { CStr::from_ptr(value) };
+        value.to_str().ok().map(str::to_string)
+    }
+
     /// Log a message with the specified level to the syslog.
     ///
     /// This method wraps pam_syslog, which prefixes the message with a string indicating
@@ -22,12 +22,15 @@
 //! free_tries = 6
 //! base_delay_seconds = 30
 //! ramp_multiplier = 1.5
+//! delay_curve = "nlogn"
 //! ```
 //!
 //! - `tally_dir`: Directory where tally information is stored.
 //! - `free_tries`: Number of allowed free authentication attempts before applying delays.
 //! - `base_delay_seconds`: Base delay applied to each authentication failure.
 //! - `ramp_multiplier`: Multiplier for the delay calculation based on the number of failures.
+//! - `delay_curve`: Function mapping the failures to a delay (`linear`, `exponential`, `nlogn`,
+//!   `fibonacci` or `steps`).
 //!
 //! ## License
 //!
@@ -49,8 +52,11 @@
 
 mod tally;
 
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, Utc};
 use common::actions::Actions;
+use common::audit::{AuditEvent, AuditEventKind};
+use common::locale::{self, Catalog};
+use common::schema::AdminLock;
 use common::settings::Settings;
 use pam::conv::Conv;
 use pam::pam_try;
@@ -58,11 +64,15 @@ use pam::{PamFlag, PamResultCode, PAM_TEXT_INFO};
 use pam::{PamHandle, PamHooks};
 use std::cmp::min;
 use std::ffi::CStr;
+use std::fmt::Write;
 use std::thread::sleep;
-use uzers::get_user_by_name;
+use uzers::{get_user_by_name, User};
 
 use tally::Tally;
 
+/// Format of the unlock time in lockout messages, catalogs can translate it.
+const UNTIL_FORMAT: &str = "%Y-%m-%d %I:%M:%S %p";
+
 pub struct Pamauthramp;
 
 pam::pam_hooks!(Pamauthramp);
@@ -159,67 +169,170 @@ where
 
     // common::util::syslog::init_pam_log(pam_h, &settings)?;
 
-    // Get and Set tally
-    let tally = Tally::new_from_tally_file(&Some(pam_h), &settings)?;
+    // Attempts from trusted sources are neither ramped nor counted, but still bounced by an
+    // administrative lock. A success still resets
+    let tally = if settings.is_trusted_source() && settings.get_action()? != Actions::AUTHSUCC {
+        let _ = settings.config.log(
+            pam_h,
+            pam::LogLevel::Info,
+            format!(
+                "Trusted source rhost={:?} tty={:?}, skipping the ramp",
+                settings.items.rhost, settings.items.tty
+            ),
+        );
+        Tally::new_for_trusted_source(&Some(pam_h), &settings)?
+    } else {
+        // Get and Set tally
+        Tally::new_from_tally_file(&Some(pam_h), &settings)?
+    };
 
     pam_hook(pam_h, &settings, &tally)
 }
 
-/// Formats a Duration into a human-readable string representation.
-/// The format includes hours, minutes, and seconds, excluding zero values.
+/// Formats a Duration into a human-readable string representation in the language of the
+/// catalog. The format includes hours, minutes, and seconds, excluding zero values.
 ///
 /// # Arguments
 /// - `remaining_time`: Duration representing the remaining time
+/// - `max_delay`: Duration the remaining time is capped at
+/// - `catalog`: `Catalog` translating the units
 ///
 /// # Returns
 /// Formatted string indicating the remaining time in the countdown
-fn format_remaining_countdown_time(remaining_time: Duration) -> String {
+fn format_remaining_countdown_time(
+    remaining_time: Duration,
+    max_delay: Duration,
+    catalog: &Catalog,
+) -> String {
+    let remaining_time = min(remaining_time, max_delay);
+
     if remaining_time.num_seconds() == 0 {
         return "..".to_string();
     }
 
-    let mut formatted_time = String::new();
-
-    let mut t_val = remaining_time.num_hours();
-    let mut t_desc = "hours";
-
-    if t_val > 0 {
-        if t_val == 1 {
-            t_desc = t_desc.trim_end_matches('s');
-        }
-        formatted_time += &format!("{t_val} {t_desc}, ");
-    }
-
-    t_val = remaining_time.num_minutes() % 60;
-    t_desc = "minutes";
-
-    if t_val > 0 {
-        if t_val == 1 {
-            t_desc = t_desc.trim_end_matches('s');
-        }
-        formatted_time += &format!("{t_val} {t_desc} and ");
-    }
+    let unit = |value: i64, singular, plural| {
+        let value = u64::try_from(value).unwrap_or_default();
+        locale::render(
+            catalog.ngettext(singular, plural, value),
+            &[("n", &value.to_string())],
+        )
+    };
+    let hours = remaining_time.num_hours();
+    let minutes = remaining_time.num_minutes() % 60;
+    let seconds = remaining_time.num_seconds() % 60;
+
+    let template = match (hours > 0, minutes > 0) {
+        (true, true) => "{hours}, {minutes} and {seconds}",
+        (true, false) => "{hours}, {seconds}",
+        (false, true) => "{minutes} and {seconds}",
+        (false, false) => "{seconds}",
+    };
 
-    t_val = remaining_time.num_seconds() % 60;
-    t_desc = "seconds";
+    locale::render(
+        catalog.gettext(template),
+        &[
+            ("hours", &unit(hours, "{n} hour", "{n} hours")),
+            ("minutes", &unit(minutes, "{n} minute", "{n} minutes")),
+            ("seconds", &unit(seconds, "{n} second", "{n} seconds")),
+        ],
+    )
+}
 
-    if t_val == 1 {
-        t_desc = t_desc.trim_end_matches('s');
+/// Loads the catalog translating the messages to the locale of the PAM environment. Nothing is
+/// loaded if the `silent` setting is enabled.
+///
+/// # Arguments
+/// - `pam_h`: `PamHandle` instance used to log errors
+/// - `settings`: Settings for the authramp module
+///
+/// # Returns
+/// The `Catalog`, or the English default catalog if it cannot be loaded
+fn load_catalog(pam_h: &PamHandle, settings: &Settings) -> Catalog {
+    if settings.config.silent {
+        return Catalog::default();
     }
 
-    formatted_time += &format!("{t_val} {t_desc}");
+    Catalog::load(
+        &settings.config.locale_dir,
+        settings.items.locale.as_deref(),
+    )
+    .unwrap_or_else(|e| {
+        let _ = settings.config.log(
+            pam_h,
+            pam::LogLevel::Warning,
+            format!("Invalid message catalog, using English: {e}"),
+        );
+        Catalog::default()
+    })
+}
 
-    formatted_time
+/// Renders a lockout message template of the configuration in the language of the catalog.
+///
+/// # Arguments
+/// - `settings`: Settings for the authramp module
+/// - `catalog`: `Catalog` translating the template
+/// - `template`: The template, see `common::locale::MESSAGE_PLACEHOLDERS`
+/// - `unlock_instant`: When the account is unlocked, `None` if it is locked until it is reset
+/// - `failures`: Failures of the tally
+///
+/// # Returns
+/// The message to the user
+fn lock_message(
+    settings: &Settings,
+    catalog: &Catalog,
+    template: &str,
+    unlock_instant: Option<DateTime<Utc>>,
+    failures: i32,
+) -> String {
+    let (remaining, until) = unlock_instant
+        .map(|unlock_instant| {
+            let remaining = format_remaining_countdown_time(
+                unlock_instant - Utc::now(),
+                Duration::try_seconds(settings.config.max_delay_seconds)
+                    .unwrap_or_else(Duration::max_value),
+                catalog,
+            );
+
+            // a translated time format can be invalid
+            let mut until = String::new();
+            if write!(
+                until,
+                "{}",
+                unlock_instant.format(catalog.gettext(UNTIL_FORMAT))
+            )
+            .is_err()
+            {
+                until = unlock_instant.format(UNTIL_FORMAT).to_string();
+            }
+            (remaining, until)
+        })
+        .unwrap_or_default();
+    let user = settings
+        .user
+        .as_ref()
+        .map(|user| user.name().to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    locale::render(
+        catalog.gettext(template),
+        &[
+            ("remaining", &remaining),
+            ("until", &until),
+            ("failures", &failures.to_string()),
+            ("user", &user),
+        ],
+    )
 }
 
 /// Sends a message to the PAM conversation function and logs errors if they occur.
 ///
 /// This function retrieves the conversation function from the PAM handle and sends
 /// a specified message to it. If any errors occur during this process, they are logged
-/// appropriately.
+/// appropriately. Nothing is sent if the `silent` setting is enabled.
 ///
 /// # Arguments
 /// - `pam_h`: Mutable reference to the `PamHandle`
+/// - `settings`: Settings for the authramp module
 /// - `msg`: String slice containing the message to be sent
 ///
 /// # Returns
@@ -230,7 +343,11 @@ fn format_remaining_countdown_time(remaining_time: Duration) -> String {
 /// - If the conversation function cannot be accessed from the PAM handle.
 /// - If sending the message to the conversation function fails.
 /// - If logging the error fails.
-fn pam_message(pam_h: &mut PamHandle, msg: &str) -> Result<(), PamResultCode> {
+fn pam_message(pam_h: &mut PamHandle, settings: &Settings, msg: &str) -> Result<(), PamResultCode> {
+    if settings.config.silent {
+        return Ok(());
+    }
+
     if let Ok(Some(conv)) = pam_h.get_item::<Conv>() {
         // Send a message to the conversation function
         let conv_res = conv.send(PAM_TEXT_INFO, msg);
@@ -239,7 +356,8 @@ fn pam_message(pam_h: &mut PamHandle, msg: &str) -> Result<(), PamResultCode> {
         match conv_res {
             Ok(_) => Ok(()),
             Err(pam_code) => {
-                match pam_h.log(
+                match settings.config.log(
+                    pam_h,
                     pam::LogLevel::Error,
                     format!("{pam_code:?}: Error starting PAM conversation."),
                 ) {
@@ -249,7 +367,8 @@ fn pam_message(pam_h: &mut PamHandle, msg: &str) -> Result<(), PamResultCode> {
             }
         }
     } else {
-        match pam_h.log(
+        match settings.config.log(
+            pam_h,
             pam::LogLevel::Error,
             "Error accessing conversation in PAM library.".to_string(),
         ) {
@@ -259,6 +378,25 @@ fn pam_message(pam_h: &mut PamHandle, msg: &str) -> Result<(), PamResultCode> {
     }
 }
 
+/// Logs an event to the audit log and the journal, see `common::audit`. Errors are logged, they
+/// do not fail the authentication.
+///
+/// # Arguments
+/// - `pam_h`: `PamHandle` instance used to log errors
+/// - `settings`: Settings for the authramp module
+/// - `event`: The `AuditEvent`
+fn audit(pam_h: Option<&PamHandle>, settings: &Settings, event: &AuditEvent) {
+    if let Err(e) = event.log(&settings.config) {
+        if let Some(pam_h) = pam_h {
+            let _ = settings.config.log(
+                pam_h,
+                pam::LogLevel::Error,
+                format!("Error logging audit event: {e}"),
+            );
+        }
+    }
+}
+
 /// Handles the account lockout mechanism based on the number of failures and settings.
 /// If the account is locked, it sends periodic messages to the user until the account is unlocked.
 ///
@@ -269,6 +407,7 @@ fn pam_message(pam_h: &mut PamHandle, msg: &str) -> Result<(), PamResultCode> {
 ///
 /// # Returns
 /// `PAM_SUCCESS` if the account is successfully unlocked, `PAM_AUTH_ERR` otherwise
+#[allow(clippy::too_many_lines)]
 fn bounce_auth(pam_h: &mut PamHandle, settings: &Settings, tally: &Tally) -> PamResultCode {
     // get user
     let user = match settings.get_user() {
@@ -281,6 +420,46 @@ fn bounce_auth(pam_h: &mut PamHandle, settings: &Settings, tally: &Tally) -> Pam
         return PamResultCode::PAM_SUCCESS;
     }
 
+    // administrative locks are never counted down
+    if let Some(admin_lock) = tally.active_admin_lock() {
+        return bounce_admin_lock(pam_h, settings, user, admin_lock, tally.failures_count);
+    }
+
+    if tally.is_permanently_locked(settings) {
+        if let Err(result_code) = settings.config.log(
+            pam_h,
+            pam::LogLevel::Info,
+            format!("PAM_AUTH_ERR: Account {user:?} is getting bounced. Account is locked until it is reset."),
+        ) {
+            return result_code;
+        }
+        audit(
+            Some(pam_h),
+            settings,
+            &AuditEvent {
+                count: Some(tally.failures_count),
+                ..AuditEvent::from_settings(AuditEventKind::Bounce, settings, None)
+            }
+            .locked_until(tally.locked_until(settings)),
+        );
+
+        let catalog = load_catalog(pam_h, settings);
+        if let Err(result_code) = pam_message(
+            pam_h,
+            settings,
+            &lock_message(
+                settings,
+                &catalog,
+                &settings.config.permanent_lock_message,
+                None,
+                tally.failures_count,
+            ),
+        ) {
+            return result_code;
+        }
+        return PamResultCode::PAM_AUTH_ERR;
+    }
+
     if tally.failures_count > settings.config.free_tries {
         let delay = tally.get_delay(settings);
 
@@ -289,7 +468,8 @@ fn bounce_auth(pam_h: &mut PamHandle, settings: &Settings, tally: &Tally) -> Pam
             .unlock_instant
             .unwrap_or(tally.failure_instant + delay);
 
-        match pam_h.log(
+        match settings.config.log(
+                pam_h,
                 pam::LogLevel::Info,
                 format!(
                     "PAM_AUTH_ERR: Account {user:?} is getting bounced. Account still locked until {unlock_instant}"
@@ -299,15 +479,32 @@ fn bounce_auth(pam_h: &mut PamHandle, settings: &Settings, tally: &Tally) -> Pam
                 Err(result_code) => return result_code,
             }
 
+        if Utc::now() < unlock_instant {
+            audit(
+                Some(pam_h),
+                settings,
+                &AuditEvent {
+                    count: Some(tally.failures_count),
+                    ..AuditEvent::from_settings(AuditEventKind::Bounce, settings, None)
+                }
+                .locked_until(Some(unlock_instant)),
+            );
+        }
+
         // Don't loop and return timestamp if configured
         if !settings.config.countdown {
             // If account is locked, keep user locked out
             if Utc::now() < unlock_instant {
+                let catalog = load_catalog(pam_h, settings);
                 if let Err(result_code) = pam_message(
                     pam_h,
-                    &format!(
-                        "Account locked until {}.",
-                        unlock_instant.format("%Y-%m-%d %I:%M:%S %p")
+                    settings,
+                    &lock_message(
+                        settings,
+                        &catalog,
+                        &settings.config.lock_message,
+                        Some(unlock_instant),
+                        tally.failures_count,
                     ),
                 ) {
                     return result_code;
@@ -317,20 +514,22 @@ fn bounce_auth(pam_h: &mut PamHandle, settings: &Settings, tally: &Tally) -> Pam
             return PamResultCode::PAM_SUCCESS;
         }
 
+        let catalog = load_catalog(pam_h, settings);
         while Utc::now() < unlock_instant {
             // Calculate remaining time until unlock
             let remaining_time = unlock_instant - Utc::now();
 
-            // Cap remaining time at 24 hours
-            let capped_remaining_time = min(remaining_time, Duration::hours(24));
-
             // Only send a message every two seconds to help with latency
-            if capped_remaining_time.num_seconds() % 2 == 0 {
+            if remaining_time.num_seconds() % 2 == 0 {
                 if let Err(result_code) = pam_message(
                     pam_h,
-                    &format!(
-                        "Account locked! Unlocking in {}.",
-                        format_remaining_countdown_time(capped_remaining_time)
+                    settings,
+                    &lock_message(
+                        settings,
+                        &catalog,
+                        &settings.config.countdown_message,
+                        Some(unlock_instant),
+                        tally.failures_count,
                     ),
                 ) {
                     return result_code;
@@ -344,6 +543,75 @@ fn bounce_auth(pam_h: &mut PamHandle, settings: &Settings, tally: &Tally) -> Pam
     PamResultCode::PAM_SUCCESS
 }
 
+/// Bounces an authentication attempt of an account locked by an administrator with
+/// `authramp lock`.
+///
+/// # Arguments
+/// - `pam_h`: `PamHandle` instance for interacting with PAM
+/// - `settings`: Settings for the authramp module
+/// - `user`: The locked user
+/// - `admin_lock`: The active `AdminLock` of the account
+/// - `failures`: Failures of the tally
+///
+/// # Returns
+/// `PAM_AUTH_ERR`, or the error of logging or messaging the user
+fn bounce_admin_lock(
+    pam_h: &mut PamHandle,
+    settings: &Settings,
+    user: &User,
+    admin_lock: &AdminLock,
+    failures: i32,
+) -> PamResultCode {
+    let reason = admin_lock
+        .reason
+        .as_ref()
+        .map(|reason| format!(" Reason: {reason}."))
+        .unwrap_or_default();
+    let log_until = match admin_lock.until {
+        Some(until) => format!("until {until}"),
+        None => "until it is reset".to_string(),
+    };
+
+    if let Err(result_code) = settings.config.log(
+        pam_h,
+        pam::LogLevel::Notice,
+        format!("PAM_AUTH_ERR: Account {user:?} is getting bounced. Account is locked by an administrator {log_until}.{reason}"),
+    ) {
+        return result_code;
+    }
+    audit(
+        Some(pam_h),
+        settings,
+        &AuditEvent {
+            reason: admin_lock.reason.clone(),
+            ..AuditEvent::from_settings(AuditEventKind::Bounce, settings, None)
+        }
+        .locked_until(Some(admin_lock.locked_until())),
+    );
+
+    let catalog = load_catalog(pam_h, settings);
+    let message = match admin_lock.until {
+        Some(until) => lock_message(
+            settings,
+            &catalog,
+            &settings.config.lock_message,
+            Some(until),
+            failures,
+        ),
+        None => lock_message(
+            settings,
+            &catalog,
+            &settings.config.permanent_lock_message,
+            None,
+            failures,
+        ),
+    };
+    if let Err(result_code) = pam_message(pam_h, settings, &message) {
+        return result_code;
+    }
+    PamResultCode::PAM_AUTH_ERR
+}
+
 // Unit tests
 #[cfg(test)]
 mod tests {
@@ -355,32 +623,174 @@ mod tests {
     #[test]
     fn test_format_remaining_time() {
         let cast_error = &"bad time delta!";
+        let max_delay = TimeDelta::hours(24);
+        let catalog = Catalog::default();
 
         // Test with duration of 2 hours, 24 minutes, and 5 seconds
         let duration =
             TimeDelta::from_std(Duration::new(2 * 3600 + 24 * 60 + 5, 0)).expect(cast_error);
         assert_eq!(
-            format_remaining_countdown_time(duration),
+            format_remaining_countdown_time(duration, max_delay, &catalog),
             "2 hours, 24 minutes and 5 seconds"
         );
 
         // Test with duration of 1 hour, 1 minute, and 0 seconds
         let duration = TimeDelta::from_std(Duration::new(3600 + 60, 0)).expect(cast_error);
         assert_eq!(
-            format_remaining_countdown_time(duration),
+            format_remaining_countdown_time(duration, max_delay, &catalog),
             "1 hour, 1 minute and 0 seconds"
         );
 
         // Test with duration of 35 seconds
         let duration = TimeDelta::from_std(Duration::new(35, 0)).expect(cast_error);
-        assert_eq!(format_remaining_countdown_time(duration), "35 seconds");
+        assert_eq!(
+            format_remaining_countdown_time(duration, max_delay, &catalog),
+            "35 seconds"
+        );
 
         // Test with duration of 35 seconds
         let duration = TimeDelta::from_std(Duration::new(1, 0)).expect(cast_error);
-        assert_eq!(format_remaining_countdown_time(duration), "1 second");
+        assert_eq!(
+            format_remaining_countdown_time(duration, max_delay, &catalog),
+            "1 second"
+        );
 
         // Test with duration of 0 seconds
         let duration = TimeDelta::from_std(Duration::new(0, 0)).expect(cast_error);
-        assert_eq!(format_remaining_countdown_time(duration), "..");
+        assert_eq!(
+            format_remaining_countdown_time(duration, max_delay, &catalog),
+            ".."
+        );
+
+        // Test with duration above the maximum delay
+        let duration = TimeDelta::from_std(Duration::new(30 * 3600, 0)).expect(cast_error);
+        assert_eq!(
+            format_remaining_countdown_time(duration, max_delay, &catalog),
+            "24 hours, 0 seconds"
+        );
+        assert_eq!(
+            format_remaining_countdown_time(duration, TimeDelta::minutes(90), &catalog),
+            "1 hour, 30 minutes and 0 seconds"
+        );
+    }
+
+    #[test]
+    fn test_format_remaining_time_translated() {
+        let catalog = Catalog::parse(
+            r#"
+msgid "{minutes} and {seconds}"
+msgstr "{minutes} und {seconds}"
+
+msgid "{n} minute"
+msgid_plural "{n} minutes"
+msgstr[0] "{n} Minute"
+msgstr[1] "{n} Minuten"
+
+msgid "{n} second"
+msgid_plural "{n} seconds"
+msgstr[0] "{n} Sekunde"
+msgstr[1] "{n} Sekunden"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            format_remaining_countdown_time(TimeDelta::seconds(61), TimeDelta::hours(24), &catalog),
+            "1 Minute und 1 Sekunde"
+        );
+        assert_eq!(
+            format_remaining_countdown_time(
+                TimeDelta::seconds(150),
+                TimeDelta::hours(24),
+                &catalog
+            ),
+            "2 Minuten und 30 Sekunden"
+        );
+    }
+
+    #[test]
+    fn test_trusted_source_bounced_by_admin_lock() {
+        let temp_dir = crate::tally::temp_tally_dir("test_trusted_source_bounced_by_admin_lock");
+        std::fs::write(
+            temp_dir.path().join("alice"),
+            "schema_version = 3\n\n[Fails]\ncount = 0\n\n[Lock]\ninstant = \"2023-01-01T00:00:00Z\"\n",
+        )
+        .unwrap();
+
+        let settings = Settings {
+            user: Some(User::new(9999, "alice", 9999)),
+            action: Some(Actions::PREAUTH),
+            items: common::settings::PamItems {
+                rhost: Some("10.1.2.3".to_string()),
+                ..common::settings::PamItems::default()
+            },
+            config: common::config::Config {
+                tally_dir: temp_dir.path().to_path_buf(),
+                trusted_networks: vec!["10.0.0.0/8".parse().unwrap()],
+                // nothing is logged or sent, so the handle is never used
+                quiet: true,
+                silent: true,
+                ..common::config::Config::default()
+            },
+            ..Settings::default()
+        };
+        assert!(settings.is_trusted_source());
+
+        let tally = Tally::new_for_trusted_source(&None, &settings).unwrap();
+        let pam_h = unsafe { &mut *std::ptr::NonNull::<PamHandle>::dangling().as_ptr() };
+        assert_eq!(
+            bounce_auth(pam_h, &settings, &tally),
+            PamResultCode::PAM_AUTH_ERR
+        );
+    }
+
+    #[test]
+    fn test_lock_message() {
+        let settings = Settings {
+            user: Some(User::new(9999, "alice", 9999)),
+            config: common::config::Config {
+                lock_message: "{user} locked until {until}, {failures} failures".to_string(),
+                ..common::config::Config::default()
+            },
+            ..Settings::default()
+        };
+        let until = DateTime::parse_from_rfc3339("2024-02-04T13:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        assert_eq!(
+            lock_message(
+                &settings,
+                &Catalog::default(),
+                &settings.config.lock_message,
+                Some(until),
+                7
+            ),
+            "alice locked until 2024-02-04 01:00:00 PM, 7 failures"
+        );
+
+        // the time format is translated, invalid formats fall back to the default
+        let catalog = Catalog::parse(
+            "msgid \"{user} locked until {until}, {failures} failures\"\n\
+             msgstr \"{user} gesperrt bis {until}\"\n\
+             msgid \"%Y-%m-%d %I:%M:%S %p\"\n\
+             msgstr \"%d.%m.%Y %H:%M\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            lock_message(
+                &settings,
+                &catalog,
+                &settings.config.lock_message,
+                Some(until),
+                7
+            ),
+            "alice gesperrt bis 04.02.2024 13:00"
+        );
+        let catalog = Catalog::parse("msgid \"%Y-%m-%d %I:%M:%S %p\"\nmsgstr \"%Q\"\n").unwrap();
+        assert_eq!(
+            lock_message(&settings, &catalog, "{until}", Some(until), 7),
+            "2024-02-04 01:00:00 PM"
+        );
     }
 }
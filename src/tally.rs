@@ -13,10 +13,22 @@
 //!
 //! The `Tally` struct has the following fields:
 //!
-//! - `tally_file`: An optional `PathBuf` representing the path to the file storing tally information.
+//! - `file`: An optional `PathBuf` representing the path to the file storing tally information.
 //! - `failures_count`: An integer representing the number of authentication failures.
 //! - `failure_instant`: A `DateTime<Utc>` representing the timestamp of the last authentication failure.
+//! - `failure_instants`: A `Vec<DateTime<Utc>>` with the timestamps of the failures within the
+//!   `fail_interval` window, bounded by `lockout::MAX_FAILURE_INSTANTS`. Only tracked if
+//!   `fail_interval` is configured.
+//! - `history`: A bounded list of `FailureRecord`s describing the most recent failures.
 //! - `unlock_instant`: An optional `DateTime<Utc>` representing the time when the account will be unlocked.
+//! - `admin_lock`: An optional `AdminLock` set with `authramp lock`. Unlike the failures, it is not
+//!   cleared by a successful login.
+//!
+//! ## Tally Store
+//!
+//! The tally is persisted in the tally store selected with the `storage` setting, see the
+//! `store` module of the `common` crate. Tallies that cannot be loaded are handled according to
+//! the `on_corrupt_tally` setting.
 //!
 //! ## License
 //!
@@ -36,17 +48,18 @@
 //! You should have received a copy of the GNU General Public License
 //! along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::{
-    fs,
-    os::unix::fs::{chown, MetadataExt, PermissionsExt},
-    path::{Path, PathBuf},
-};
+use std::path::PathBuf;
 
 use chrono::{DateTime, Duration, Utc};
 use common::actions::Actions;
+use common::audit::{AuditEvent, AuditEventKind};
+use common::config::CorruptTallyPolicy;
+use common::lockout;
+use common::schema::{AdminLock, FailsTable, FailureRecord, TallyFile};
 use common::settings::Settings;
+use common::store::{self, StoreError, TallyStore};
+use common::track::TallyKey;
 use pam::{PamHandle, PamResultCode};
-use uzers::User;
 
 /// The `Tally` struct represents the account lockout information, including
 /// the number of authentication failures and the timestamp of the last failure.
@@ -58,8 +71,14 @@ pub struct Tally {
     pub failures_count: i32,
     /// A `DateTime<Utc>` representing the timestamp of the last authentication failure.
     pub failure_instant: DateTime<Utc>,
+    /// Timestamps of the most recent failures within the `fail_interval` window.
+    pub failure_instants: Vec<DateTime<Utc>>,
+    /// The most recent failures, oldest first, bounded by `max_history`.
+    pub history: Vec<FailureRecord>,
     /// An optional `DateTime<Utc>` representing the time when the account will be unlocked.
     pub unlock_instant: Option<DateTime<Utc>>,
+    /// The lockout set by an administrator.
+    pub admin_lock: Option<AdminLock>,
 }
 
 impl Default for Tally {
@@ -69,146 +88,406 @@ impl Default for Tally {
             file: None,
             failures_count: 0,
             failure_instant: Utc::now(),
+            failure_instants: Vec::new(),
+            history: Vec::new(),
             unlock_instant: None,
+            admin_lock: None,
         }
     }
 }
 
 impl Tally {
-    /// Calculates the delay based on the number of authentication failures and settings.
-    /// Uses the authramp formula: `delay=ramp_multiplier×(fails` − `free_tries)×ln(fails` − `free_tries)+base_delay_seconds`
+    /// Calculates the delay based on the number of authentication failures and settings,
+    /// see `common::lockout::delay`.
     ///
     /// # Arguments
-    /// - `fails`: Number of authentication failures
     /// - `settings`: Settings for the authramp module
     ///
     /// # Returns
-    /// Calculated delay as a floating-point number
+    /// Calculated delay as a `Duration`
     pub fn get_delay(&self, settings: &Settings) -> Duration {
-        Duration::seconds(
-            (f64::from(settings.config.ramp_multiplier)
-                * (f64::from(self.failures_count) - f64::from(settings.config.free_tries))
-                * ((f64::from(self.failures_count) - f64::from(settings.config.free_tries)).ln())
-                + f64::from(settings.config.base_delay_seconds)) as i64,
+        lockout::delay(&settings.config, self.failures_count)
+    }
+
+    /// Checks whether the account reached `permanent_lock_after` failures and stays locked
+    /// until it is reset with `authramp reset`.
+    ///
+    /// # Arguments
+    /// - `settings`: Settings for the authramp module
+    ///
+    /// # Returns
+    /// `true` if the account is permanently locked
+    pub fn is_permanently_locked(&self, settings: &Settings) -> bool {
+        lockout::is_permanently_locked(&settings.config, self.failures_count)
+    }
+
+    /// Returns the time until which the tally locks the account, `DateTime::<Utc>::MAX_UTC` if
+    /// it is permanently locked, or `None` if it is within the free tries and not locked by an
+    /// administrator.
+    ///
+    /// # Arguments
+    /// - `settings`: Settings for the authramp module
+    pub fn locked_until(&self, settings: &Settings) -> Option<DateTime<Utc>> {
+        lockout::locked_until(
+            &settings.config,
+            self.failures_count,
+            self.failure_instant,
+            self.unlock_instant,
         )
+        .max(self.admin_lock.as_ref().map(AdminLock::locked_until))
+    }
+
+    /// Returns the lockout set by an administrator if it currently applies.
+    pub fn active_admin_lock(&self) -> Option<&AdminLock> {
+        self.admin_lock
+            .as_ref()
+            .filter(|admin_lock| admin_lock.is_active(Utc::now()))
+    }
+
+    /// Forgets failures older than the configured `fail_interval`.
+    ///
+    /// # Arguments
+    /// - `settings`: Settings for the authramp module
+    fn expire_failures(&mut self, settings: &Settings) {
+        if let Some((failures_count, instants)) = lockout::unexpired_failures(
+            &settings.config,
+            self.failures_count,
+            self.failure_instant,
+            &self.failure_instants,
+            Utc::now(),
+        ) {
+            self.failures_count = failures_count;
+            self.failure_instants = instants;
+        }
+    }
+
+    /// Converts the tally into the on-disk `TallyFile` representation.
+    ///
+    /// Failure timestamps are only stored if `fail_interval` is configured.
+    ///
+    /// # Arguments
+    /// - `settings`: Settings for the authramp module
+    fn to_tally_file(&self, settings: &Settings) -> TallyFile {
+        let tally_file = TallyFile::new(
+            FailsTable {
+                count: self.failures_count,
+                instant: (self.failures_count > 0).then_some(self.failure_instant),
+                unlock_instant: self.unlock_instant,
+                instants: if settings.config.fail_interval.is_some() {
+                    self.failure_instants.clone()
+                } else {
+                    Vec::new()
+                },
+            },
+            self.history.clone(),
+        );
+        TallyFile {
+            lock: self.admin_lock.clone(),
+            ..tally_file
+        }
+    }
+
+    /// Writes the tally to the tally store.
+    ///
+    /// # Arguments
+    /// - `store`: The `TallyStore` holding the tallies.
+    /// - `name`: Name of the tally in the store
+    /// - `settings`: Settings for the authramp module
+    ///
+    /// # Returns
+    /// A `Result` indicating success or the `StoreError` that occurred.
+    fn save(
+        &self,
+        store: &dyn TallyStore,
+        name: &str,
+        settings: &Settings,
+    ) -> Result<(), StoreError> {
+        store.save(name, &self.to_tally_file(settings))
+    }
+
+    /// Records an authentication failure, see `common::lockout::record_failure`.
+    ///
+    /// # Arguments
+    /// - `settings`: Settings for the authramp module
+    fn record_failure(&mut self, settings: &Settings) {
+        let mut tally_file = self.to_tally_file(settings);
+        lockout::record_failure(
+            &settings.config,
+            &mut tally_file,
+            FailureRecord::new(Utc::now(), &settings.items),
+        );
+        self.set_tally_file(tally_file);
+    }
+
+    /// Takes the values of a tally read from the tally store.
+    ///
+    /// # Arguments
+    /// - `tally_file`: The stored tally
+    fn set_tally_file(&mut self, tally_file: TallyFile) {
+        let fails = tally_file.fails;
+        self.failures_count = fails.count;
+        self.failure_instant = fails.instant.unwrap_or_default();
+        self.unlock_instant = fails.unlock_instant;
+        self.failure_instants = fails.instants;
+        self.history = tally_file.history;
+        self.admin_lock = tally_file.lock;
+    }
+
+    /// Appends a failure to the audit log, followed by a lockout if the failure locks the account.
+    ///
+    /// # Arguments
+    /// - `name`: Name of the tally in the tally store
+    /// - `settings`: Settings for the authramp module
+    fn audit_failure(&self, pam_h: &Option<&mut PamHandle>, name: &str, settings: &Settings) {
+        let failure = AuditEvent {
+            count: Some(self.failures_count),
+            ..AuditEvent::from_settings(AuditEventKind::Failure, settings, Some(name))
+        }
+        .locked_until(lockout::locked_until(
+            &settings.config,
+            self.failures_count,
+            self.failure_instant,
+            self.unlock_instant,
+        ));
+        crate::audit(pam_h.as_deref(), settings, &failure);
+
+        if failure.permanent || failure.unlock_time.is_some() {
+            crate::audit(
+                pam_h.as_deref(),
+                settings,
+                &AuditEvent {
+                    event: AuditEventKind::Lockout,
+                    ..failure
+                },
+            );
+        }
     }
 
-    /// Opens or creates the tally file based on the provided `Settings`.
+    /// Opens the tallies tracked by the `track` setting in the configured tally store based on
+    /// the provided `Settings`.
     ///
-    /// If the file exists, loads the values; if not, creates the file with default values.
-    /// Updates the tally based on authentication actions, such as successful or failed attempts.
+    /// If a tally exists, loads the values; if not, a failed attempt creates it.
+    /// Updates the tallies based on authentication actions, such as successful or failed attempts.
+    /// Remote host tallies are not cleared by a successful attempt.
+    ///
+    /// Updates hold an exclusive lock on the tally for the whole read-modify-write cycle, so
+    /// concurrent authentication attempts cannot lose failures. Reading on PREAUTH does not need
+    /// the lock.
     ///
     /// # Arguments
     /// - `settings`: A reference to the `Settings` struct.
     ///
     /// # Returns
-    /// A `Result` containing either the `Tally` struct or a `PAM_AUTH_ERR`.
+    /// A `Result` containing either the most restrictive `Tally` or a `PAM_AUTH_ERR`.
     pub fn new_from_tally_file(
         pam_h: &Option<&mut PamHandle>,
         settings: &Settings,
     ) -> Result<Self, PamResultCode> {
+        let store = store::open(&settings.config).map_err(|e| {
+            Self::log_store_error(pam_h, settings, "Error opening tally store", &e);
+            PamResultCode::PAM_SYSTEM_ERR
+        })?;
+
+        let mut restrictive: Option<Tally> = None;
+        for key in TallyKey::for_settings(settings)? {
+            if settings.action == Some(Actions::AUTHSUCC) && !key.is_cleared_on_success() {
+                continue;
+            }
+
+            let tally = Self::new_from_store(pam_h, store.as_ref(), &key, settings)?;
+            restrictive = match restrictive {
+                Some(other) if other.locked_until(settings) >= tally.locked_until(settings) => {
+                    Some(other)
+                }
+                _ => Some(tally),
+            };
+        }
+
+        Ok(restrictive.unwrap_or_default())
+    }
+
+    /// Loads the administrative locks of the tallies tracked by the `track` setting without
+    /// counting the attempt. Attempts from trusted sources skip the ramp, but not a lock set
+    /// with `authramp lock`.
+    ///
+    /// Tallies that cannot be read are skipped, so a broken tally does not lock out trusted
+    /// sources.
+    ///
+    /// # Arguments
+    /// - `settings`: A reference to the `Settings` struct.
+    ///
+    /// # Returns
+    /// A `Result` containing a `Tally` without failures holding the active administrative lock,
+    /// if any, or a `PAM_SYSTEM_ERR` if the tally store cannot be opened.
+    pub fn new_for_trusted_source(
+        pam_h: &Option<&mut PamHandle>,
+        settings: &Settings,
+    ) -> Result<Self, PamResultCode> {
+        let store = store::open(&settings.config).map_err(|e| {
+            Self::log_store_error(pam_h, settings, "Error opening tally store", &e);
+            PamResultCode::PAM_SYSTEM_ERR
+        })?;
+
         let mut tally = Tally::default();
-        let user = settings.get_user()?;
+        for key in TallyKey::for_settings(settings)? {
+            let name = key.store_name(settings.config.tally_namespace.as_deref());
+            match store.load(&name) {
+                Ok(Some(tally_file)) => {
+                    tally.admin_lock = tally
+                        .admin_lock
+                        .take()
+                        .into_iter()
+                        .chain(tally_file.lock)
+                        .filter(|admin_lock| admin_lock.is_active(Utc::now()))
+                        .max_by_key(AdminLock::locked_until);
+                }
+                Ok(None) => (),
+                Err(e) => Self::log_store_error(pam_h, settings, "Error reading tally", &e),
+            }
+        }
 
-        let tally_file = settings.config.tally_dir.join(user.name());
+        Ok(tally)
+    }
 
-        if tally_file.exists() {
-            Self::load_tally_from_file(pam_h, &mut tally, user, &tally_file, settings)?;
-        } else if settings.action == Some(Actions::AUTHFAIL) {
-            Self::create_tally_file(pam_h, &mut tally, &tally_file, settings)?;
+    /// Opens a single tally in the given tally store, see `new_from_tally_file`.
+    ///
+    /// # Arguments
+    /// - `store`: The `TallyStore` holding the tallies.
+    /// - `key`: The `TallyKey` of the tally.
+    /// - `settings`: A reference to the `Settings` struct.
+    ///
+    /// # Returns
+    /// A `Result` containing either the `Tally` struct or a `PAM_AUTH_ERR`.
+    pub fn new_from_store(
+        pam_h: &Option<&mut PamHandle>,
+        store: &dyn TallyStore,
+        key: &TallyKey,
+        settings: &Settings,
+    ) -> Result<Self, PamResultCode> {
+        let mut tally = Tally::default();
+        let name = key.store_name(settings.config.tally_namespace.as_deref());
+
+        let lock_error = |e: StoreError| {
+            Self::log_store_error(pam_h, settings, "Error locking tally", &e);
+            PamResultCode::PAM_SYSTEM_ERR
         };
 
+        match settings.get_action()? {
+            Actions::PREAUTH => {
+                Self::load_tally(pam_h, &mut tally, key, store, settings)?;
+            }
+            Actions::AUTHSUCC => {
+                if let Some(_lock) = store.lock(&name, false).map_err(lock_error)? {
+                    Self::load_tally(pam_h, &mut tally, key, store, settings)?;
+                }
+            }
+            Actions::AUTHFAIL => {
+                let _lock = store.lock(&name, true).map_err(lock_error)?;
+                Self::load_tally(pam_h, &mut tally, key, store, settings)?;
+            }
+        }
+
         Ok(tally)
     }
 
-    /// Loads tally information from an existing file.
+    /// Logs an error of the tally store.
+    ///
+    /// # Arguments
+    /// - `settings`: A reference to the `Settings` struct.
+    /// - `message`: Description of the failed operation
+    /// - `e`: The `StoreError` that occurred
+    fn log_store_error(
+        pam_h: &Option<&mut PamHandle>,
+        settings: &Settings,
+        message: &str,
+        e: &StoreError,
+    ) {
+        if let Some(pam_h) = pam_h {
+            let _ = settings
+                .config
+                .log(pam_h, pam::LogLevel::Error, format!("{message}: {e}"));
+        }
+    }
+
+    /// Loads a tally from the tally store.
     ///
     /// # Arguments
-    /// - `tally_file`: A reference to the tally file `Path`.
     /// - `tally`: A mutable reference to the `Tally` struct.
+    /// - `key`: The `TallyKey` of the tally.
+    /// - `store`: The `TallyStore` holding the tallies.
     /// - `settings`: A reference to the `Settings` struct.
     ///
     /// # Returns
     /// A `Result` indicating success or a `PAM_SYSTEM_ERR` in case of errors.
-    fn load_tally_from_file(
+    fn load_tally(
         pam_h: &Option<&mut PamHandle>,
         tally: &mut Tally,
-        user: &User,
-        tally_file: &Path,
+        key: &TallyKey,
+        store: &dyn TallyStore,
         settings: &Settings,
     ) -> Result<(), PamResultCode> {
-        // load tally file into table
-        let toml_tally =
-            toml::from_str::<toml::Value>(&std::fs::read_to_string(tally_file).map_err(|e| {
-                if let Some(pam_h) = &pam_h {
-                    match pam_h.log(
-                        pam::LogLevel::Error,
-                        format!("{e:?}: Error reading tally file:"),
-                    ) {
-                        Ok(()) => (),
-                        Err(result_code) => return result_code,
-                    }
+        match store.load(&key.store_name(settings.config.tally_namespace.as_deref())) {
+            Ok(Some(tally_file_content)) => tally.set_tally_file(tally_file_content),
+            Ok(None) => {
+                // only a failed attempt creates a tally
+                if settings.action == Some(Actions::AUTHFAIL) {
+                    Self::create_tally(pam_h, key, store, settings)?;
                 }
-                PamResultCode::PAM_SYSTEM_ERR
-            })?)
-            .map_err(|e| {
+                return Ok(());
+            }
+            Err(StoreError::Corrupt { error, modified }) => {
                 if let Some(pam_h) = &pam_h {
-                    match pam_h.log(
+                    settings.config.log(
+                        pam_h,
                         pam::LogLevel::Error,
-                        format!("{e:?}: Error parsing tally file: {e}"),
-                    ) {
-                        Ok(()) => (),
-                        Err(result_code) => return result_code,
-                    }
+                        format!("Error loading tally of the {key}: {error}"),
+                    )?;
                 }
-                PamResultCode::PAM_SYSTEM_ERR
-            })?;
-
-        // Extract values from the "Fails" table
-        if let Some(fails_table) = toml_tally.get("Fails").and_then(|v| v.as_table()) {
-            tally.failures_count = fails_table
-                .get("count")
-                .and_then(toml::Value::as_integer)
-                .map(|count| count as i32)
-                .unwrap_or_default();
-
-            tally.failure_instant = fails_table
-                .get("instant")
-                .and_then(|instant| instant.as_str())
-                .and_then(|instant| instant.parse().ok())
-                .unwrap_or_default();
-
-            tally.unlock_instant = fails_table
-                .get("unlock_instant")
-                .and_then(|unlock_instant| unlock_instant.as_str())
-                .and_then(|unlock_instant| unlock_instant.parse().ok());
-        } else {
-            // If the "Fails" table doesn't exist, return an error
-            if let Some(pam_h) = &pam_h {
-                match pam_h.log(
-                    pam::LogLevel::Error,
-                    "Error reading tally file: [Fails] table does not exist".to_string(),
-                ) {
-                    Ok(()) => (),
-                    Err(result_code) => return Err(result_code),
+
+                match settings.config.on_corrupt_tally {
+                    CorruptTallyPolicy::FailClosed => return Err(PamResultCode::PAM_SYSTEM_ERR),
+                    // continue with an empty tally, the next update overwrites it
+                    CorruptTallyPolicy::FailOpen => (),
+                    CorruptTallyPolicy::Lock => {
+                        let modified = modified.unwrap_or_else(Utc::now);
+
+                        tally.failures_count = settings.config.free_tries + 1;
+                        tally.failure_instant = modified;
+                        // a delay past the supported time range locks until it is reset
+                        tally.unlock_instant = Some(
+                            Duration::try_seconds(settings.config.max_delay_seconds)
+                                .and_then(|delay| modified.checked_add_signed(delay))
+                                .unwrap_or(DateTime::<Utc>::MAX_UTC),
+                        );
+
+                        // keep the corrupt tally, only a successful login replaces it
+                        if settings.action != Some(Actions::AUTHSUCC) {
+                            return Ok(());
+                        }
+                    }
                 }
             }
-
-            return Err(PamResultCode::PAM_SYSTEM_ERR);
+            Err(e) => {
+                Self::log_store_error(pam_h, settings, "Error reading tally", &e);
+                return Err(PamResultCode::PAM_SYSTEM_ERR);
+            }
         }
 
-        Self::update_tally(pam_h, tally, user, tally_file, settings)
+        tally.expire_failures(settings);
+
+        Self::update_tally(pam_h, tally, key, store, settings)
     }
 
-    /// Updates tally information based on a section from the tally file.
+    /// Updates tally information based on the authentication action and writes it to the store.
     ///
-    /// AUTHSUCC deletes the tally
+    /// AUTHSUCC deletes the tally, except for an active administrative lock
     /// AUTHERR increases the tally
     /// PREAUTH is ignored;
     ///
     /// # Arguments
-    /// - `fails_section`: A reference to the "Fails" section of the TOML file.
     /// - `tally`: A mutable reference to the `Tally` struct.
+    /// - `key`: The `TallyKey` of the tally.
+    /// - `store`: The `TallyStore` holding the tallies.
     /// - `settings`: A reference to the `Settings` struct.
     ///
     /// # Returns
@@ -216,10 +495,12 @@ impl Tally {
     fn update_tally(
         pam_h: &Option<&mut PamHandle>,
         tally: &mut Tally,
-        user: &User,
-        tally_file: &Path,
+        key: &TallyKey,
+        store: &dyn TallyStore,
         settings: &Settings,
     ) -> Result<(), PamResultCode> {
+        let name = key.store_name(settings.config.tally_namespace.as_deref());
+
         // Handle specific actions based on settings.action
         match settings.get_action()? {
             Actions::PREAUTH => Ok(()),
@@ -232,28 +513,39 @@ impl Tally {
 
                 // Reset unlock_instant to None on AUTHSUCC
                 tally.unlock_instant = None;
+                tally.failure_instants.clear();
+                tally.history.clear();
 
-                // Write the updated values back to the file
-                let toml_str = format!("[Fails]\ncount = {}", tally.failures_count);
-                std::fs::write(tally_file, toml_str).map_err(|e| {
-                    if let Some(pam_h) = &pam_h {
-                        match pam_h.log(pam::LogLevel::Error, format!("Error resetting tally: {e}"))
-                        {
-                            Ok(()) => (),
-                            Err(result_code) => return result_code,
-                        }
-                    }
+                // only authramp reset removes an administrative lock, expired locks are dropped
+                if tally.active_admin_lock().is_none() {
+                    tally.admin_lock = None;
+                }
+
+                // Write the updated values back to the store
+                tally.save(store, &name, settings).map_err(|e| {
+                    Self::log_store_error(pam_h, settings, "Error resetting tally", &e);
                     PamResultCode::PAM_PERM_DENIED
                 })?;
 
                 // log account unlock
                 if total_failures > 0 {
+                    crate::audit(
+                        pam_h.as_deref(),
+                        settings,
+                        &AuditEvent {
+                            count: Some(total_failures),
+                            ..AuditEvent::from_settings(
+                                AuditEventKind::Unlock,
+                                settings,
+                                Some(&name),
+                            )
+                        },
+                    );
                     if let Some(pam_h) = &pam_h {
-                        match pam_h.log(
-                        pam::LogLevel::Info,
-                        format!("PAM_SUCCESS: Clear tally ({} failures) for the {:?} account. Account is unlocked.",
-                        total_failures,
-                        user.name()),
+                        match settings.config.log(
+                        pam_h,
+                        pam::LogLevel::Notice,
+                        format!("PAM_SUCCESS: Clear tally ({total_failures} failures) for the {key}. Account is unlocked."),
                     ) {
                         Ok(()) => (),
                         Err(result_code) => return Err(result_code),
@@ -263,48 +555,37 @@ impl Tally {
                 Ok(())
             }
             Actions::AUTHFAIL => {
-                // If action is AUTHFAIL, update count and instant
-                tally.failures_count += 1;
-                tally.failure_instant = Utc::now();
+                tally.record_failure(settings);
 
-                let mut delay = tally.get_delay(settings);
-
-                // Cap unlock_instant at 24 hours from now
-                if delay > Duration::hours(24) {
-                    delay = Duration::hours(24);
-                }
+                // Write the updated values back to the store
+                tally.save(store, &name, settings).map_err(|e| {
+                    Self::log_store_error(pam_h, settings, "Error writing tally", &e);
+                    PamResultCode::PAM_PERM_DENIED
+                })?;
 
-                tally.unlock_instant = Some(tally.failure_instant + delay);
+                tally.audit_failure(pam_h, &name, settings);
 
-                // Write the updated values back to the file
-                let toml_str = format!(
-                    "[Fails]\ncount = {}\ninstant = \"{}\"\nunlock_instant = \"{}\"",
-                    tally.failures_count,
-                    tally.failure_instant,
-                    tally.unlock_instant.unwrap()
-                );
-                std::fs::write(tally_file, toml_str).map_err(|e| {
+                if tally.is_permanently_locked(settings) {
+                    // log permanent account lock
                     if let Some(pam_h) = &pam_h {
-                        match pam_h.log(
-                            pam::LogLevel::Error,
-                            format!("{e:?}: Error writing tally file:"),
+                        match settings.config.log(
+                            pam_h,
+                            pam::LogLevel::Notice,
+                            format!("PAM_AUTH_ERR: Added tally ({} failures) for the {key}. Account is locked until it is reset.",
+                            tally.failures_count),
                         ) {
                             Ok(()) => (),
-                            Err(result_code) => return result_code,
+                            Err(result_code) => return Err(result_code),
                         }
                     }
-
-                    PamResultCode::PAM_PERM_DENIED
-                })?;
-
-                if tally.failures_count > settings.config.free_tries {
+                } else if tally.failures_count > settings.config.free_tries {
                     // log account unlock
                     if let Some(pam_h) = &pam_h {
-                        match pam_h.log(
-                            pam::LogLevel::Info,
-                            format!("PAM_AUTH_ERR: Added tally ({} failures) for the {:?} account. Account is locked until {}.",
+                        match settings.config.log(
+                            pam_h,
+                            pam::LogLevel::Notice,
+                            format!("PAM_AUTH_ERR: Added tally ({} failures) for the {key}. Account is locked until {}.",
                             tally.failures_count,
-                            user.name(),
                             tally.unlock_instant.unwrap()),
                         ) {
                             Ok(()) => (),
@@ -317,125 +598,60 @@ impl Tally {
         }
     }
 
-    /// Creates a new tally file with default values.
+    /// Creates a tally with its first failure.
     ///
     /// # Arguments
-    /// - `tally_file`: A reference to the tally file `Path`.
-    /// - `tally`: A mutable reference to the `Tally` struct.
+    /// - `key`: The `TallyKey` of the tally.
+    /// - `store`: The `TallyStore` holding the tallies.
     /// - `settings`: A reference to the `Settings` struct.
     ///
     /// # Returns
     /// A `Result` indicating success or a `PAM_SYSTEM_ERR` in case of errors.
-    fn create_tally_file(
+    fn create_tally(
         pam_h: &Option<&mut PamHandle>,
-        tally: &mut Tally,
-        tally_file: &Path,
-        _settings: &Settings,
+        key: &TallyKey,
+        store: &dyn TallyStore,
+        settings: &Settings,
     ) -> Result<(), PamResultCode> {
-        // Get the Parent directory
-        let Some(parent_dir) = tally_file.parent() else {
-            if let Some(pam_h) = pam_h {
-                let log_result = pam_h.log(
-                    pam::LogLevel::Error,
-                    "Failed to get tally directory".to_string(),
-                );
-                if log_result.is_err() {
-                    return Err(PamResultCode::PAM_SYSTEM_ERR);
-                }
-            }
-            return Err(PamResultCode::PAM_SYSTEM_ERR);
-        };
-
-        // Create the parent directory with all intermediate directories
-        if let Err(e) = fs::create_dir_all(parent_dir) {
-            if let Some(pam_h) = pam_h {
-                let log_result = pam_h.log(
-                    pam::LogLevel::Error,
-                    format!("{e:?}: Error creating tally directory"),
-                );
-                if log_result.is_err() {
-                    return Err(PamResultCode::PAM_SYSTEM_ERR);
-                }
-            }
-            return Err(PamResultCode::PAM_SYSTEM_ERR);
-        }
-
-        // Set the permissions to 755
-        let permissions = fs::Permissions::from_mode(0o755);
-
-        if let Err(e) = fs::set_permissions(parent_dir, permissions.clone()) {
-            if let Some(pam_h) = pam_h {
-                let log_result = pam_h.log(
-                    pam::LogLevel::Error,
-                    format!("{e:?}: Error setting tally directory permissions"),
-                );
-                if log_result.is_err() {
-                    return Err(PamResultCode::PAM_SYSTEM_ERR);
-                }
-            }
-            return Err(PamResultCode::PAM_SYSTEM_ERR);
-        }
-
-        // Write the TOML string to disk
-        let toml_str = format!(
-            "[Fails]\ncount = {}\ninstant = \"{}\"",
-            tally.failures_count + 1,
-            tally.failure_instant
-        );
-
-        std::fs::write(tally_file, toml_str).map_err(|e| {
-            if let Some(pam_h) = &pam_h {
-                match pam_h.log(
-                    pam::LogLevel::Error,
-                    format!("{e:?}:  Error writing tally file:"),
-                ) {
-                    Ok(()) => (),
-                    Err(result_code) => return result_code,
-                }
-            }
+        let mut first_failure = Tally::default();
+        first_failure.record_failure(settings);
+        // the unlock time of the first failure is calculated from the delay when needed
+        first_failure.unlock_instant = None;
+
+        let name = key.store_name(settings.config.tally_namespace.as_deref());
+        first_failure.save(store, &name, settings).map_err(|e| {
+            Self::log_store_error(pam_h, settings, "Error writing tally", &e);
             PamResultCode::PAM_SYSTEM_ERR
         })?;
 
-        //  set file permissions
-        if let Err(e) = fs::set_permissions(tally_file, permissions) {
-            if let Some(pam_h) = pam_h {
-                let log_result = pam_h.log(
-                    pam::LogLevel::Error,
-                    format!("{e:?}: Error setting tally file permissions"),
-                );
-                if log_result.is_err() {
-                    return Err(PamResultCode::PAM_SYSTEM_ERR);
-                }
-            }
-            return Err(PamResultCode::PAM_SYSTEM_ERR);
-        }
-
-        // get created tally file meta
-        let tally_file_meta =
-            fs::metadata(tally_file).map_err(|_e| PamResultCode::PAM_SYSTEM_ERR)?;
-
-        // set tally file owner
-        let uid = unsafe { libc::getuid() };
-        if tally_file_meta.uid() != uid {
-            chown(tally_file, Some(uid), Some(uid)).map_err(|_e| PamResultCode::PAM_SYSTEM_ERR)?;
-        }
-
+        first_failure.audit_failure(pam_h, &name, settings);
         Ok(())
     }
 }
 
+/// Creates a temporary tally directory, trusted without running as root.
+#[cfg(test)]
+pub(crate) fn temp_tally_dir(prefix: &str) -> tempdir::TempDir {
+    common::store::set_tally_dir_owner(uzers::get_effective_uid());
+    tempdir::TempDir::new(prefix).unwrap()
+}
+
 // Unit Tests
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tempdir::TempDir;
+    use std::fs;
+    use uzers::User;
 
-    use common::config::Config;
+    use common::config::{Config, StorageBackend};
+    use common::curve::DelayCurve;
+    use common::settings::PamItems;
+    use common::track::Track;
 
     #[test]
     fn test_open_existing_tally_file() {
         // Create a temporary directory
-        let temp_dir = TempDir::new("test_open_existing_tally_file").unwrap();
+        let temp_dir = temp_tally_dir("test_open_existing_tally_file");
         let tally_file_path = temp_dir.path().join("test_user_a");
 
         // Create an existing TOML file
@@ -480,7 +696,7 @@ mod tests {
     #[test]
     fn test_open_nonexistent_tally_file() {
         // Create a temporary directory
-        let temp_dir = TempDir::new("test_open_nonexistent_tally_file").unwrap();
+        let temp_dir = temp_tally_dir("test_open_nonexistent_tally_file");
         let tally_file_path = temp_dir.path().join("test_user_b");
 
         let config = Config {
@@ -518,7 +734,7 @@ mod tests {
     #[test]
     fn test_open_auth_fail_updates_values() {
         // Create a temporary directory
-        let temp_dir = TempDir::new("test_open_auth_fail_updates_values").unwrap();
+        let temp_dir = temp_tally_dir("test_open_auth_fail_updates_values");
         let tally_file_path = temp_dir.path().join("test_user_c");
 
         // Create an existing TOML file with some initial values
@@ -537,6 +753,7 @@ mod tests {
             base_delay_seconds: 30,
             even_deny_root: false,
             countdown: true,
+            ..Config::default()
         };
 
         // Create settings and call new_from_tally_file with AUTHFAIL action
@@ -544,6 +761,7 @@ mod tests {
             user: Some(User::new(9999, "test_user_c", 9999)),
             action: Some(Actions::AUTHFAIL),
             pam_hook: "test",
+            items: PamItems::default(),
             config,
         };
 
@@ -561,10 +779,519 @@ mod tests {
         // Additional assertions as needed
     }
 
+    #[test]
+    fn test_concurrent_auth_fail_keeps_every_failure() {
+        const THREADS: usize = 8;
+        const ATTEMPTS: usize = 25;
+
+        let temp_dir = temp_tally_dir("test_concurrent_auth_fail");
+        let tally_dir = temp_dir.path().to_path_buf();
+
+        let handles: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let tally_dir = tally_dir.clone();
+                std::thread::spawn(move || {
+                    let settings = Settings {
+                        user: Some(User::new(9999, "test_user_h", 9999)),
+                        action: Some(Actions::AUTHFAIL),
+                        config: Config {
+                            tally_dir,
+                            ..Config::default()
+                        },
+                        ..Default::default()
+                    };
+                    for _ in 0..ATTEMPTS {
+                        Tally::new_from_tally_file(&None, &settings).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let settings = Settings {
+            user: Some(User::new(9999, "test_user_h", 9999)),
+            action: Some(Actions::PREAUTH),
+            config: Config {
+                tally_dir: tally_dir.clone(),
+                ..Config::default()
+            },
+            ..Default::default()
+        };
+        let tally = Tally::new_from_tally_file(&None, &settings).unwrap();
+        assert_eq!(
+            tally.failures_count,
+            i32::try_from(THREADS * ATTEMPTS).unwrap()
+        );
+
+        // no temporary files are left behind
+        let entries: Vec<_> = fs::read_dir(&tally_dir).unwrap().collect();
+        assert_eq!(entries.len(), 1);
+    }
+
+    #[test]
+    fn test_sqlite_storage() {
+        const THREADS: usize = 4;
+        const ATTEMPTS: usize = 10;
+
+        let temp_dir = temp_tally_dir("test_sqlite_storage");
+        let tally_dir = temp_dir.path().to_path_buf();
+
+        let handles: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let tally_dir = tally_dir.clone();
+                std::thread::spawn(move || {
+                    let settings = Settings {
+                        user: Some(User::new(9999, "test_user_k", 9999)),
+                        action: Some(Actions::AUTHFAIL),
+                        config: Config {
+                            tally_dir,
+                            storage: StorageBackend::Sqlite,
+                            ..Config::default()
+                        },
+                        ..Default::default()
+                    };
+                    for _ in 0..ATTEMPTS {
+                        Tally::new_from_tally_file(&None, &settings).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let mut settings = Settings {
+            user: Some(User::new(9999, "test_user_k", 9999)),
+            action: Some(Actions::PREAUTH),
+            config: Config {
+                tally_dir: tally_dir.clone(),
+                storage: StorageBackend::Sqlite,
+                ..Config::default()
+            },
+            ..Default::default()
+        };
+        let tally = Tally::new_from_tally_file(&None, &settings).unwrap();
+        assert_eq!(
+            tally.failures_count,
+            i32::try_from(THREADS * ATTEMPTS).unwrap()
+        );
+
+        // no tally file is created per user
+        assert!(!tally_dir.join("test_user_k").exists());
+
+        settings.action = Some(Actions::AUTHSUCC);
+        Tally::new_from_tally_file(&None, &settings).unwrap();
+        settings.action = Some(Actions::PREAUTH);
+        let tally = Tally::new_from_tally_file(&None, &settings).unwrap();
+        assert_eq!(tally.failures_count, 0);
+    }
+
+    #[test]
+    fn test_rhost_tally() {
+        let temp_dir = temp_tally_dir("test_rhost_tally");
+
+        let settings_for = |user: &str, rhost: &str, action: Actions| Settings {
+            user: Some(User::new(9999, user, 9999)),
+            action: Some(action),
+            items: PamItems {
+                rhost: Some(rhost.to_string()),
+                ..PamItems::default()
+            },
+            config: Config {
+                tally_dir: temp_dir.path().to_path_buf(),
+                track: vec![Track::User, Track::RHost],
+                rhost_ipv4_prefix: 24,
+                ..Config::default()
+            },
+            ..Default::default()
+        };
+
+        // spray two failures each for four users from one network
+        for (i, user) in ["spray_a", "spray_b", "spray_c", "spray_d"]
+            .iter()
+            .enumerate()
+        {
+            for _ in 0..2 {
+                let rhost = format!("192.0.2.{i}");
+                Tally::new_from_tally_file(&None, &settings_for(user, &rhost, Actions::AUTHFAIL))
+                    .unwrap();
+            }
+        }
+        assert!(temp_dir.path().join("@192.0.2.0%2F24").exists());
+
+        // a fresh user from the same network is locked by the remote host tally
+        let tally = Tally::new_from_tally_file(
+            &None,
+            &settings_for("spray_e", "192.0.2.200", Actions::PREAUTH),
+        )
+        .unwrap();
+        assert_eq!(tally.failures_count, 8);
+
+        // a successful login does not clear the remote host tally
+        Tally::new_from_tally_file(
+            &None,
+            &settings_for("spray_a", "192.0.2.1", Actions::AUTHSUCC),
+        )
+        .unwrap();
+        let settings = settings_for("spray_a", "192.0.2.1", Actions::PREAUTH);
+        let tally = Tally::new_from_tally_file(&None, &settings).unwrap();
+        assert_eq!(tally.failures_count, 8);
+        assert!(tally.locked_until(&settings).is_some());
+
+        // the same user from another network is not locked
+        let tally = Tally::new_from_tally_file(
+            &None,
+            &settings_for("spray_b", "198.51.100.1", Actions::PREAUTH),
+        )
+        .unwrap();
+        assert_eq!(tally.failures_count, 2);
+    }
+
+    #[test]
+    fn test_tally_namespace() {
+        let temp_dir = temp_tally_dir("test_tally_namespace");
+
+        let settings_for = |namespace: Option<&str>, action: Actions| Settings {
+            user: Some(User::new(9999, "ns_user", 9999)),
+            action: Some(action),
+            config: Config {
+                tally_dir: temp_dir.path().to_path_buf(),
+                tally_namespace: namespace.map(str::to_string),
+                ..Config::default()
+            },
+            ..Default::default()
+        };
+
+        Tally::new_from_tally_file(&None, &settings_for(Some("sshd"), Actions::AUTHFAIL)).unwrap();
+        assert!(temp_dir.path().join("sshd:ns_user").exists());
+        assert!(!temp_dir.path().join("ns_user").exists());
+
+        // the failure is not visible outside the namespace
+        let tally =
+            Tally::new_from_tally_file(&None, &settings_for(None, Actions::PREAUTH)).unwrap();
+        assert_eq!(tally.failures_count, 0);
+        let tally =
+            Tally::new_from_tally_file(&None, &settings_for(Some("sshd"), Actions::PREAUTH))
+                .unwrap();
+        assert_eq!(tally.failures_count, 1);
+    }
+
+    #[test]
+    fn test_auth_succ_without_tally_creates_no_file() {
+        let temp_dir = temp_tally_dir("test_auth_succ_without_tally");
+
+        let settings = Settings {
+            user: Some(User::new(9999, "test_user_i", 9999)),
+            action: Some(Actions::AUTHSUCC),
+            config: Config {
+                tally_dir: temp_dir.path().to_path_buf(),
+                ..Config::default()
+            },
+            ..Default::default()
+        };
+        let tally = Tally::new_from_tally_file(&None, &settings).unwrap();
+
+        assert_eq!(tally.failures_count, 0);
+        assert!(!temp_dir.path().join("test_user_i").exists());
+    }
+
+    #[test]
+    fn test_corrupt_tally_policies() {
+        let temp_dir = temp_tally_dir("test_corrupt_tally_policies");
+        let tally_file_path = temp_dir.path().join("test_user_j");
+
+        let mut settings = Settings {
+            user: Some(User::new(9999, "test_user_j", 9999)),
+            action: Some(Actions::PREAUTH),
+            config: Config {
+                tally_dir: temp_dir.path().to_path_buf(),
+                ..Config::default()
+            },
+            ..Default::default()
+        };
+
+        // fail closed by default
+        std::fs::write(&tally_file_path, "[Fails]\ncount = \"many\"").unwrap();
+        assert_eq!(
+            Tally::new_from_tally_file(&None, &settings),
+            Err(PamResultCode::PAM_SYSTEM_ERR)
+        );
+
+        // lock the account for the maximum delay without touching the file
+        settings.config.on_corrupt_tally = CorruptTallyPolicy::Lock;
+        settings.action = Some(Actions::AUTHFAIL);
+        let tally = Tally::new_from_tally_file(&None, &settings).unwrap();
+        assert!(tally.failures_count > settings.config.free_tries);
+        assert!(tally.unlock_instant.unwrap() > Utc::now() + Duration::hours(23));
+        assert!(fs::read_to_string(&tally_file_path)
+            .unwrap()
+            .contains("\"many\""));
+
+        // a huge max_delay_seconds does not overflow
+        settings.config.max_delay_seconds = i64::MAX;
+        let tally = Tally::new_from_tally_file(&None, &settings).unwrap();
+        assert_eq!(tally.unlock_instant, Some(DateTime::<Utc>::MAX_UTC));
+        settings.config.max_delay_seconds = Config::default().max_delay_seconds;
+
+        // fail open and overwrite the corrupt file on the next failure
+        settings.config.on_corrupt_tally = CorruptTallyPolicy::FailOpen;
+        let tally = Tally::new_from_tally_file(&None, &settings).unwrap();
+        assert_eq!(tally.failures_count, 1);
+        assert!(fs::read_to_string(&tally_file_path)
+            .unwrap()
+            .contains("schema_version = 3"));
+    }
+
+    #[test]
+    fn test_history_records_failures() {
+        let temp_dir = temp_tally_dir("test_history_records_failures");
+
+        let mut settings = Settings {
+            user: Some(User::new(9999, "test_user_g", 9999)),
+            action: Some(Actions::AUTHFAIL),
+            config: Config {
+                tally_dir: temp_dir.path().to_path_buf(),
+                max_history: 3,
+                ..Config::default()
+            },
+            ..Default::default()
+        };
+
+        for i in 0..5 {
+            settings.items = PamItems {
+                service: Some("sshd".to_string()),
+                rhost: Some(format!("192.0.2.{i}")),
+                tty: Some("ssh".to_string()),
+                ruser: Some("\"quoted\" ruser".to_string()),
+                ..PamItems::default()
+            };
+            Tally::new_from_tally_file(&None, &settings).unwrap();
+        }
+
+        settings.action = Some(Actions::PREAUTH);
+        let tally = Tally::new_from_tally_file(&None, &settings).unwrap();
+
+        assert_eq!(tally.failures_count, 5);
+        let rhosts: Vec<&str> = tally
+            .history
+            .iter()
+            .filter_map(|record| record.rhost.as_deref())
+            .collect();
+        assert_eq!(rhosts, vec!["192.0.2.2", "192.0.2.3", "192.0.2.4"]);
+        assert_eq!(tally.history[2].service.as_deref(), Some("sshd"));
+        assert_eq!(tally.history[2].tty.as_deref(), Some("ssh"));
+        assert_eq!(tally.history[2].ruser.as_deref(), Some("\"quoted\" ruser"));
+    }
+
+    #[test]
+    fn test_audit_log() {
+        let temp_dir = temp_tally_dir("test_audit_log");
+        let audit_log = temp_dir.path().join("audit.jsonl");
+
+        let mut settings = Settings {
+            user: Some(User::new(9999, "test_user_h", 9999)),
+            action: Some(Actions::AUTHFAIL),
+            items: PamItems {
+                service: Some("sshd".to_string()),
+                rhost: Some("192.0.2.1".to_string()),
+                ..PamItems::default()
+            },
+            config: Config {
+                tally_dir: temp_dir.path().to_path_buf(),
+                free_tries: 1,
+                audit_log: Some(audit_log.clone()),
+                ..Config::default()
+            },
+            ..Default::default()
+        };
+
+        Tally::new_from_tally_file(&None, &settings).unwrap();
+        Tally::new_from_tally_file(&None, &settings).unwrap();
+        settings.action = Some(Actions::AUTHSUCC);
+        Tally::new_from_tally_file(&None, &settings).unwrap();
+
+        let content = fs::read_to_string(&audit_log).unwrap();
+        let events: Vec<&str> = content
+            .lines()
+            .map(|line| line.split('"').nth(7).unwrap())
+            .collect();
+        // the second failure locks the account
+        assert_eq!(events, vec!["failure", "failure", "lockout", "unlock"]);
+        assert!(content.lines().all(|line| line.contains(
+            r#""tally":"test_user_h","user":"test_user_h","uid":9999,"service":"sshd","rhost":"192.0.2.1""#
+        )));
+        assert!(content.lines().nth(2).unwrap().contains(r#""count":2"#));
+    }
+
+    #[test]
+    fn test_fail_interval_forgets_old_failures() {
+        let temp_dir = temp_tally_dir("test_fail_interval_forgets_old_failures");
+        let tally_file_path = temp_dir.path().join("test_user_e");
+
+        let recent = Utc::now() - Duration::seconds(60);
+        let toml_str = format!(
+            "[Fails]\ncount = 3\ninstant = \"{recent}\"\ninstants = [\"2023-01-01T00:00:00Z\", \"2023-01-02T00:00:00Z\", \"{recent}\"]"
+        );
+        std::fs::write(&tally_file_path, toml_str).unwrap();
+
+        let settings = Settings {
+            user: Some(User::new(9999, "test_user_e", 9999)),
+            action: Some(Actions::AUTHFAIL),
+            config: Config {
+                tally_dir: temp_dir.path().to_path_buf(),
+                fail_interval: Some(900),
+                ..Config::default()
+            },
+            ..Default::default()
+        };
+
+        let tally = Tally::new_from_tally_file(&None, &settings).unwrap();
+
+        // the two failures from 2023 are forgotten, the new failure is added
+        assert_eq!(tally.failures_count, 2);
+        assert_eq!(tally.failure_instants.len(), 2);
+        assert_eq!(tally.failure_instants[0], recent);
+
+        // the timestamps written to the file can be read back
+        let settings = Settings {
+            action: Some(Actions::PREAUTH),
+            ..settings
+        };
+        let tally = Tally::new_from_tally_file(&None, &settings).unwrap();
+        assert_eq!(tally.failures_count, 2);
+    }
+
+    #[test]
+    fn test_fail_interval_legacy_tally_file() {
+        let temp_dir = temp_tally_dir("test_fail_interval_legacy_tally_file");
+        let tally_file_path = temp_dir.path().join("test_user_f");
+
+        let toml_str = r#"
+        [Fails]
+        count = 8
+        instant = "2023-01-01T00:00:00Z"
+        unlock_instant = "2023-01-02T00:00:00Z"
+    "#;
+        std::fs::write(tally_file_path, toml_str).unwrap();
+
+        let mut settings = Settings {
+            user: Some(User::new(9999, "test_user_f", 9999)),
+            action: Some(Actions::PREAUTH),
+            config: Config {
+                tally_dir: temp_dir.path().to_path_buf(),
+                ..Config::default()
+            },
+            ..Default::default()
+        };
+
+        // without fail_interval the failures are kept
+        let tally = Tally::new_from_tally_file(&None, &settings).unwrap();
+        assert_eq!(tally.failures_count, 8);
+
+        // with fail_interval all failures are older than the window
+        settings.config.fail_interval = Some(900);
+        let tally = Tally::new_from_tally_file(&None, &settings).unwrap();
+        assert_eq!(tally.failures_count, 0);
+    }
+
+    #[test]
+    fn test_get_delay_default_curve_matches_legacy_formula() {
+        let settings = Settings {
+            config: Config::default(),
+            ..Default::default()
+        };
+
+        for failures_count in 7..300 {
+            let tally = Tally {
+                failures_count,
+                ..Default::default()
+            };
+            let legacy = (f64::from(settings.config.ramp_multiplier)
+                * (f64::from(failures_count) - f64::from(settings.config.free_tries))
+                * ((f64::from(failures_count) - f64::from(settings.config.free_tries)).ln())
+                + f64::from(settings.config.base_delay_seconds)) as i64;
+
+            assert_eq!(tally.get_delay(&settings), Duration::seconds(legacy));
+        }
+    }
+
+    #[test]
+    fn test_get_delay_capped_at_max_delay() {
+        let settings = Settings {
+            config: Config {
+                max_delay_seconds: 600,
+                ..Config::default()
+            },
+            ..Default::default()
+        };
+
+        let tally = Tally {
+            failures_count: 300,
+            ..Default::default()
+        };
+        assert_eq!(tally.get_delay(&settings), Duration::seconds(600));
+
+        let tally = Tally {
+            failures_count: 7,
+            ..Default::default()
+        };
+        assert_eq!(tally.get_delay(&settings), Duration::seconds(30));
+    }
+
+    #[test]
+    fn test_is_permanently_locked() {
+        let mut settings = Settings {
+            config: Config::default(),
+            ..Default::default()
+        };
+        let tally = Tally {
+            failures_count: 20,
+            ..Default::default()
+        };
+        assert!(!tally.is_permanently_locked(&settings));
+
+        settings.config.permanent_lock_after = Some(20);
+        assert!(tally.is_permanently_locked(&settings));
+
+        settings.config.permanent_lock_after = Some(21);
+        assert!(!tally.is_permanently_locked(&settings));
+    }
+
+    #[test]
+    fn test_get_delay_steps_curve() {
+        let settings = Settings {
+            config: Config {
+                free_tries: 3,
+                delay_curve: DelayCurve::Steps(vec![30, 60, 300]),
+                ..Config::default()
+            },
+            ..Default::default()
+        };
+
+        let delays: Vec<Duration> = (3..=7)
+            .map(|failures_count| {
+                Tally {
+                    failures_count,
+                    ..Default::default()
+                }
+                .get_delay(&settings)
+            })
+            .collect();
+
+        assert_eq!(
+            delays,
+            [0, 30, 60, 300, 300].map(Duration::seconds).to_vec()
+        );
+    }
+
     #[test]
     fn test_open_auth_succ_resets_tally() {
         // Create a temporary directory
-        let temp_dir = TempDir::new("test_open_auth_succ_deletes_file").unwrap();
+        let temp_dir = temp_tally_dir("test_open_auth_succ_deletes_file");
         let tally_file_path = temp_dir.path().join("test_user_d");
 
         // Create an existing TOML file
@@ -583,6 +1310,7 @@ mod tests {
             base_delay_seconds: 30,
             even_deny_root: false,
             countdown: true,
+            ..Config::default()
         };
 
         // Create settings and call new_from_tally_file with AUTHSUCC action
@@ -590,6 +1318,7 @@ mod tests {
             user: Some(User::new(9999, "test_user_d", 9999)),
             action: Some(Actions::AUTHSUCC),
             pam_hook: "test",
+            items: PamItems::default(),
             config,
         };
 
@@ -603,4 +1332,119 @@ mod tests {
         );
         assert!(!toml_content.contains("unlock_instant = "));
     }
+
+    #[test]
+    fn test_auth_succ_keeps_admin_lock() {
+        let temp_dir = temp_tally_dir("test_auth_succ_keeps_admin_lock");
+        let tally_file_path = temp_dir.path().join("test_user_l");
+
+        let toml_str = r#"
+        schema_version = 3
+
+        [Fails]
+        count = 2
+        instant = "2023-01-01T00:00:00Z"
+
+        [Lock]
+        instant = "2023-01-01T00:00:00Z"
+        reason = "leaver"
+    "#;
+        std::fs::write(&tally_file_path, toml_str).unwrap();
+
+        let mut settings = Settings {
+            user: Some(User::new(9999, "test_user_l", 9999)),
+            action: Some(Actions::PREAUTH),
+            config: Config {
+                tally_dir: temp_dir.path().to_path_buf(),
+                ..Config::default()
+            },
+            ..Default::default()
+        };
+
+        // the lock applies within the free tries
+        let tally = Tally::new_from_tally_file(&None, &settings).unwrap();
+        assert!(tally.active_admin_lock().is_some());
+        assert_eq!(
+            tally.locked_until(&settings),
+            Some(DateTime::<Utc>::MAX_UTC)
+        );
+
+        // a successful login resets the failures but keeps the lock
+        settings.action = Some(Actions::AUTHSUCC);
+        Tally::new_from_tally_file(&None, &settings).unwrap();
+        let tally_file = TallyFile::parse(&fs::read_to_string(&tally_file_path).unwrap()).unwrap();
+        assert_eq!(tally_file.fails.count, 0);
+        assert_eq!(tally_file.lock.unwrap().reason, Some("leaver".to_string()));
+
+        // expired locks are dropped
+        std::fs::write(
+            &tally_file_path,
+            toml_str.replace("reason = \"leaver\"", "until = \"2023-01-02T00:00:00Z\""),
+        )
+        .unwrap();
+        let tally = Tally::new_from_tally_file(&None, &settings).unwrap();
+        assert!(tally.active_admin_lock().is_none());
+        let tally_file = TallyFile::parse(&fs::read_to_string(&tally_file_path).unwrap()).unwrap();
+        assert_eq!(tally_file.lock, None);
+    }
+
+    #[test]
+    fn test_trusted_source_keeps_admin_lock() {
+        let temp_dir = temp_tally_dir("test_trusted_source_keeps_admin_lock");
+        let tally_file_path = temp_dir.path().join("test_user_t");
+
+        let toml_str = r#"
+        schema_version = 3
+
+        [Fails]
+        count = 2
+        instant = "2023-01-01T00:00:00Z"
+
+        [Lock]
+        instant = "2023-01-01T00:00:00Z"
+        reason = "leaver"
+    "#;
+        std::fs::write(&tally_file_path, toml_str).unwrap();
+
+        let settings = Settings {
+            user: Some(User::new(9999, "test_user_t", 9999)),
+            action: Some(Actions::AUTHFAIL),
+            items: PamItems {
+                rhost: Some("10.1.2.3".to_string()),
+                ..PamItems::default()
+            },
+            config: Config {
+                tally_dir: temp_dir.path().to_path_buf(),
+                track: vec![Track::User, Track::UserRHost],
+                trusted_networks: vec!["10.0.0.0/8".parse().unwrap()],
+                ..Config::default()
+            },
+            ..Default::default()
+        };
+        assert!(settings.is_trusted_source());
+
+        // the lock bounces the trusted source, the failure is not counted
+        let tally = Tally::new_for_trusted_source(&None, &settings).unwrap();
+        assert_eq!(tally.failures_count, 0);
+        assert_eq!(
+            tally.active_admin_lock().unwrap().reason,
+            Some("leaver".to_string())
+        );
+        assert_eq!(
+            tally.locked_until(&settings),
+            Some(DateTime::<Utc>::MAX_UTC)
+        );
+        assert_eq!(fs::read_to_string(&tally_file_path).unwrap(), toml_str);
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+
+        // without a lock the trusted source passes
+        std::fs::write(
+            &tally_file_path,
+            toml_str.replace("reason = \"leaver\"", "until = \"2023-01-02T00:00:00Z\""),
+        )
+        .unwrap();
+        let tally = Tally::new_for_trusted_source(&None, &settings).unwrap();
+        assert!(tally.active_admin_lock().is_none());
+        assert_eq!(tally.locked_until(&settings), None);
+    }
 }